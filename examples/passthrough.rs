@@ -4,7 +4,7 @@ use std::io::BufReader;
 
 struct Dummy;
 impl domx::IsParser for Dummy {
-    fn handle_starttag(self: &mut Self, element: &domx::Tag, attributes: &Vec<domx::Attribute>) {
+    fn handle_starttag(&mut self, element: &domx::Tag, attributes: &Vec<domx::Attribute>, self_closing: bool, _span: &domx::Span) -> domx::Flow {
 
         let mut av: Vec<String> = Vec::new();
 
@@ -14,15 +14,41 @@ impl domx::IsParser for Dummy {
             av.push(format!("{}", attr));
         }
 
-        print!("<{}>", av.join(" ").as_str());
+        match self_closing {
+            true => print!("<{}/>", av.join(" ").as_str()),
+            false => print!("<{}>", av.join(" ").as_str()),
+        }
+        domx::Flow::Continue
     }
 
-    fn handle_endtag(self: &mut Self, element: &domx::Tag) {
+    fn handle_endtag(self: &mut Self, element: &domx::Tag, _span: &domx::Span) -> domx::Flow {
         print!("</{}>", element.to_string());
+        domx::Flow::Continue
     }
 
-    fn handle_data(self: &mut Self, data: &Vec<u8>) {
+    fn handle_data(&mut self, data: &Vec<u8>, _span: &domx::Span) -> domx::Flow {
         print!("{}", String::from_utf8(data.clone()).unwrap());
+        domx::Flow::Continue
+    }
+
+    fn handle_comment(&mut self, comment: &Vec<u8>, _span: &domx::Span) -> domx::Flow {
+        print!("<!--{}-->", String::from_utf8(comment.clone()).unwrap());
+        domx::Flow::Continue
+    }
+
+    fn handle_doctype(&mut self, doctype: &domx::Doctype, _span: &domx::Span) -> domx::Flow {
+        print!("{}", doctype);
+        domx::Flow::Continue
+    }
+
+    fn handle_cdata(&mut self, data: &Vec<u8>, _span: &domx::Span) -> domx::Flow {
+        print!("<![CDATA[{}]]>", String::from_utf8(data.clone()).unwrap());
+        domx::Flow::Continue
+    }
+
+    fn handle_processing_instruction(&mut self, instruction: &Vec<u8>, _span: &domx::Span) -> domx::Flow {
+        print!("<?{}?>", String::from_utf8(instruction.clone()).unwrap());
+        domx::Flow::Continue
     }
 }
 
@@ -36,5 +62,6 @@ fn main() {
     let filename = std::env::args().nth(1).unwrap();
     let file = File::open(filename).unwrap();
     let mut reader = BufReader::new(file);
-    domx::Parser::parse(&mut reader, &mut Dummy{}).unwrap();
+    let options = domx::ParserOptions { decode_entities: false, ..Default::default() };
+    domx::Parser::parse_with_options(&mut reader, &mut Dummy{}, &options).unwrap();
 }
@@ -0,0 +1,231 @@
+use std;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tag::{Tag};
+use attribute::{Attribute};
+
+/// Interned name of a tag or attribute.
+///
+/// A name read by the [Parser] is stored once in the table of the
+/// parser and shared by every tag and attribute with that name, the
+/// [Dom] keeps one such table for its elements. Cloning an atom does
+/// not copy the name and atoms of one table are compared by pointer,
+/// atoms of different tables by their names. The hash of the name is
+/// computed once when the atom is created.
+///
+/// [Parser]: struct.Parser.html
+/// [Dom]: struct.Dom.html
+///
+/// # Examples
+///
+/// ```
+/// use domx::{Atom, Tag};
+///
+/// let tag = Tag::Unknown(Atom::from("x-card"));
+/// assert_eq!(tag, "x-card".parse::<Tag>().unwrap());
+/// assert_eq!(tag.as_str(), "x-card");
+/// ```
+#[derive(Clone)]
+pub struct Atom(Arc<Name>);
+
+// Name shared by the atoms of a table, with the table it is stored in,
+// 0 for no table, and the hash of the name computed once
+struct Name {
+    table: u64,
+    hash: u64,
+    text: Arc<str>,
+}
+
+// Source of the ids of the tables, 0 is kept for atoms of no table
+static TABLES: AtomicU64 = AtomicU64::new(1);
+
+impl Atom {
+    fn _new(table: u64, text: Arc<str>) -> Atom {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        Atom(Arc::new(Name { table, hash: hasher.finish(), text }))
+    }
+
+    /// Get the name as string.
+    pub fn as_str(&self) -> &str {
+        &self.0.text
+    }
+
+    /// Test if two atoms share the same name in the same table.
+    pub fn ptr_eq(&self, other: &Atom) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<'a> From<&'a str> for Atom {
+    fn from(name: &'a str) -> Atom {
+        Atom::_new(0, Arc::from(name))
+    }
+}
+
+impl From<String> for Atom {
+    fn from(name: String) -> Atom {
+        Atom::_new(0, Arc::from(name))
+    }
+}
+
+impl std::ops::Deref for Atom {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0.text
+    }
+}
+
+impl PartialEq for Atom {
+    // A table stores each name once, so atoms of the same table are
+    // equal only when they share it
+    fn eq(&self, other: &Atom) -> bool {
+        if self.ptr_eq(other) {
+            return true;
+        }
+        match (self.0.table, other.0.table) {
+            (x, y) if x == y && x != 0 => false,
+            _ => self.0.hash == other.0.hash && self.0.text == other.0.text,
+        }
+    }
+}
+
+impl Eq for Atom {}
+
+impl PartialEq<str> for Atom {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0.text == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Atom {
+    fn eq(&self, other: &&'a str) -> bool {
+        &*self.0.text == *other
+    }
+}
+
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash)
+    }
+}
+
+impl std::fmt::Debug for Atom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.0.text, f)
+    }
+}
+
+impl std::fmt::Display for Atom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0.text)
+    }
+}
+
+/// Table of interned names, each name is stored once and handed out as
+/// [Atom] sharing it.
+pub struct Atoms {
+    table: u64,
+    names: HashMap<Arc<str>, Atom>,
+}
+
+// A copy of a table is another table, atoms it stores from then on are
+// not shared with the original
+impl Clone for Atoms {
+    fn clone(&self) -> Atoms {
+        Atoms {
+            table: TABLES.fetch_add(1, Ordering::Relaxed),
+            names: self.names.clone(),
+        }
+    }
+}
+
+impl Default for Atoms {
+    fn default() -> Atoms {
+        Atoms::new()
+    }
+}
+
+impl Atoms {
+    pub fn new() -> Atoms {
+        Atoms {
+            table: TABLES.fetch_add(1, Ordering::Relaxed),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Get the atom for a name, the name is stored at the first call.
+    pub fn intern(&mut self, name: &str) -> Atom {
+        if let Some(x) = self.names.get(name) {
+            return x.clone();
+        }
+        let atom = Atom::_new(self.table, Arc::from(name));
+        self.names.insert(atom.0.text.clone(), atom.clone());
+        atom
+    }
+
+    /// Get the atom of the table with the name of an atom, the name of
+    /// an atom of another table is shared without copying it.
+    pub fn insert(&mut self, atom: &Atom) -> Atom {
+        if atom.0.table == self.table {
+            return atom.clone();
+        }
+        if let Some(x) = self.names.get(atom.as_str()) {
+            return x.clone();
+        }
+        let shared = Atom(Arc::new(Name { table: self.table, hash: atom.0.hash, text: atom.0.text.clone() }));
+        self.names.insert(shared.0.text.clone(), shared.clone());
+        shared
+    }
+
+    /// Replace the names of a tag and its attributes with the atoms of
+    /// the table.
+    pub fn element(&mut self, tag: &mut Tag, attributes: &mut [Attribute]) {
+        match *tag {
+            Tag::Unknown(ref mut x) | Tag::Svg(ref mut x) | Tag::MathMl(ref mut x) => *x = self.insert(x),
+            _ => (),
+        }
+        for attr in attributes.iter_mut() {
+            attr.name = self.insert(&attr.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use atom::{Atom, Atoms};
+
+    #[test]
+    fn atoms_store_names_once() {
+        let mut atoms = Atoms::new();
+        let class = atoms.intern("class");
+        assert!(atoms.intern("class").ptr_eq(&class));
+        assert!(!atoms.intern("href").ptr_eq(&class));
+        assert_ne!(atoms.intern("href"), class);
+
+        // Atoms of other tables are taken over sharing the name and
+        // equal by name
+        let id = Atom::from("id");
+        let adopted = atoms.insert(&id);
+        assert!(Arc::ptr_eq(&adopted.0.text, &id.0.text));
+        assert!(atoms.insert(&id).ptr_eq(&adopted));
+        assert!(atoms.insert(&Atom::from("class")).ptr_eq(&class));
+        assert!(atoms.insert(&class).ptr_eq(&class));
+        assert_eq!(Atom::from("class"), class);
+        assert_eq!(class, "class");
+        assert_ne!(Atom::from("id"), class);
+
+        // Equal atoms of any table hash alike
+        let mut other = atoms.clone();
+        let names: HashSet<Atom> = [class.clone(), id.clone(), other.intern("src")].iter().cloned().collect();
+        assert!(names.contains(&Atoms::new().intern("class")));
+        assert!(names.contains(&adopted));
+        assert!(names.contains(&atoms.intern("src")));
+        assert!(!names.contains(&atoms.intern("href")));
+    }
+}
@@ -1,31 +1,47 @@
 use std;
+use std::collections::HashSet;
 
 use traits::{ToHTML};
+use error::{Error};
+use escape::{escape_attribute};
+use position::{Span};
+use namespace::{Namespace};
+use atom::{Atom, Atoms};
 
 /// Attribute representing a HTML attribute name and value.
 ///
-#[derive(Clone)]
+/// The name is an [Atom](struct.Atom.html) shared by the attributes
+/// with the same name.
+#[derive(Clone, PartialEq, Debug)]
 pub struct Attribute {
     #[doc(hidden)]
-    pub name: Vec<u8>,
+    pub name: Atom,
     #[doc(hidden)]
     pub value: Vec<u8>,
+    #[doc(hidden)]
+    pub span: Span,
+    #[doc(hidden)]
+    pub namespace: Option<Namespace>,
 }
 
 impl Attribute {
     /// Create new attribute
     pub fn new(name: &str, value: &str) -> Attribute {
         Attribute {
-            name: name.to_string().into_bytes(),
+            name: Atom::from(name),
             value: value.to_string().into_bytes(),
+            span: Span::default(),
+            namespace: None,
         }
     }
 
     /// Create new boolean attribute, eg. no value
     pub fn new_boolean(name: &str) -> Attribute {
         Attribute {
-            name: name.to_string().into_bytes(),
+            name: Atom::from(name),
             value: Vec::new(),
+            span: Span::default(),
+            namespace: None,
         }
     }
 
@@ -38,30 +54,160 @@ impl Attribute {
     }
 
     /// Get attribute name as utf8 encoded string
-    pub fn name(&self) -> String {
-        String::from_utf8(self.name.clone()).unwrap()
+    pub fn name(&self) -> Result<&str, Error> {
+        Ok(self.name.as_str())
     }
 
     /// Get attribute value as utf8 encoded string
-    pub fn value(&self) -> String {
-        String::from_utf8(self.value.clone()).unwrap()
+    pub fn value(&self) -> Result<&str, Error> {
+        Ok(std::str::from_utf8(&self.value)?)
+    }
+
+    /// Get span of the attribute in the source document
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Get namespace of the attribute, eg. XLink for ```xlink:href``` of
+    /// a SVG element. Most attributes are in no namespace.
+    pub fn namespace(&self) -> Option<Namespace> {
+        self.namespace
     }
 }
 
 impl ToHTML for Attribute {
     fn to_html(&self) -> String {
+        let name = self.name.as_str();
         match self.is_boolean() {
-            true => self.name(),
-            false => format!("{}=\"{}\"", self.name(), self.value())
+            true => name.to_string(),
+            false => format!("{}=\"{}\"", name, escape_attribute(&String::from_utf8_lossy(&self.value)))
         }
     }
 }
 
 impl std::fmt::Display for Attribute {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = self.name.as_str();
         match self.is_boolean() {
-            true => f.write_str(&self.name()),
-            false => f.write_str(&format!("{}=\"{}\"", self.name(), self.value()))
+            true => f.write_str(name),
+            false => f.write_str(&format!("{}=\"{}\"", name, String::from_utf8_lossy(&self.value)))
+        }
+    }
+}
+
+// Names of SVG and MathML attributes as spelled by the HTML standard,
+// names read in lowercase are adjusted to this spelling
+const FOREIGN_ATTRIBUTE_NAMES: [&str; 59] = [
+    "attributeName", "attributeType", "baseFrequency", "baseProfile",
+    "calcMode", "clipPathUnits", "definitionURL", "diffuseConstant",
+    "edgeMode", "filterUnits", "glyphRef", "gradientTransform",
+    "gradientUnits", "kernelMatrix", "kernelUnitLength", "keyPoints",
+    "keySplines", "keyTimes", "lengthAdjust", "limitingConeAngle",
+    "markerHeight", "markerUnits", "markerWidth", "maskContentUnits",
+    "maskUnits", "numOctaves", "pathLength", "patternContentUnits",
+    "patternTransform", "patternUnits", "pointsAtX", "pointsAtY",
+    "pointsAtZ", "preserveAlpha", "preserveAspectRatio", "primitiveUnits",
+    "refX", "refY", "repeatCount", "repeatDur", "requiredExtensions",
+    "requiredFeatures", "specularConstant", "specularExponent",
+    "spreadMethod", "startOffset", "stdDeviation", "stitchTiles",
+    "surfaceScale", "systemLanguage", "tableValues", "targetX", "targetY",
+    "textLength", "viewBox", "viewTarget", "xChannelSelector",
+    "yChannelSelector", "zoomAndPan",
+];
+
+/// Get the spelling of a SVG or MathML attribute name in the HTML
+/// standard, eg. ```viewBox``` for ```viewbox``` of a SVG element, None
+/// for other names.
+pub fn foreign_attribute_name(name: &[u8], namespace: Namespace) -> Option<&'static str> {
+    FOREIGN_ATTRIBUTE_NAMES.iter()
+        .find(|x| x.as_bytes().eq_ignore_ascii_case(name))
+        .filter(|&&x| match namespace {
+            Namespace::Svg => x != "definitionURL",
+            Namespace::MathMl => x == "definitionURL",
+            _ => false,
+        })
+        .copied()
+}
+
+// Attributes of foreign elements in a namespace
+const NAMESPACED_ATTRIBUTE_NAMES: [(&str, Namespace); 11] = [
+    ("xlink:actuate", Namespace::XLink), ("xlink:arcrole", Namespace::XLink),
+    ("xlink:href", Namespace::XLink), ("xlink:role", Namespace::XLink),
+    ("xlink:show", Namespace::XLink), ("xlink:title", Namespace::XLink),
+    ("xlink:type", Namespace::XLink), ("xml:lang", Namespace::Xml),
+    ("xml:space", Namespace::Xml), ("xmlns", Namespace::XmlNs),
+    ("xmlns:xlink", Namespace::XmlNs),
+];
+
+/// Get the namespace of an attribute of a SVG or MathML element, eg.
+/// XLink for ```xlink:href```, None for attributes in no namespace.
+pub fn foreign_attribute_namespace(name: &[u8]) -> Option<Namespace> {
+    NAMESPACED_ATTRIBUTE_NAMES.iter()
+        .find(|x| x.0.as_bytes().eq_ignore_ascii_case(name))
+        .map(|x| x.1)
+}
+
+/// Adjust the attributes of an element in a namespace, names of SVG and
+/// MathML attributes are spelled as in the HTML standard unless the
+/// case is preserved and attributes of foreign elements get their
+/// namespace.
+pub fn adjust_attributes(attributes: &mut [Attribute], namespace: Namespace, preserve_case: bool, atoms: &mut Atoms) {
+    if namespace == Namespace::Html {
+        return;
+    }
+    for attr in attributes.iter_mut() {
+        if let Some(x) = foreign_attribute_name(attr.name.as_bytes(), namespace).filter(|_| !preserve_case) {
+            attr.name = atoms.intern(x);
+        }
+        attr.namespace = foreign_attribute_namespace(attr.name.as_bytes());
+    }
+}
+
+/// Remove the attributes with the same name, compared ignoring ASCII
+/// case, as a previous attribute and call duplicate for each attribute
+/// removed.
+pub fn remove_duplicate_attributes<T, N, F>(attributes: &mut Vec<T>, name: N, mut duplicate: F)
+    where N: Fn(&T) -> &[u8], F: FnMut(&T)
+{
+    if attributes.len() < 2 {
+        return;
+    }
+    let mut names = HashSet::with_capacity(attributes.len());
+    attributes.retain(|x| {
+        let unique = names.insert(name(x).to_ascii_lowercase());
+        if !unique {
+            duplicate(x);
+        }
+        unique
+    });
+}
+
+/// Attribute as read by a tokenizer, the name is kept as read until the
+/// tag is finished.
+#[derive(Clone, Debug)]
+pub struct RawAttribute {
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+    pub span: Span,
+}
+
+impl RawAttribute {
+    pub fn new(span: Span) -> RawAttribute {
+        RawAttribute {
+            name: Vec::new(),
+            value: Vec::new(),
+            span,
+        }
+    }
+
+    /// Get the attribute with its name interned in atoms, invalid UTF-8
+    /// sequences of the name are replaced by U+FFFD.
+    pub fn intern(self, atoms: &mut Atoms) -> Attribute {
+        Attribute {
+            name: atoms.intern(&String::from_utf8_lossy(&self.name)),
+            value: self.value,
+            span: self.span,
+            namespace: None,
         }
     }
 }
@@ -69,7 +215,10 @@ impl std::fmt::Display for Attribute {
 #[cfg(test)]
 mod tests {
     use traits::ToHTML;
-    use attribute::Attribute;
+    use attribute::{Attribute, RawAttribute, adjust_attributes, remove_duplicate_attributes};
+    use namespace::{Namespace};
+    use position::{Span};
+    use atom::{Atoms};
 
     #[test]
     fn new_boolean_is_boolean() {
@@ -86,7 +235,15 @@ mod tests {
     #[test]
     fn new_with_utf8_value() {
         let a = Attribute::new("id", "💖");
-        assert_eq!(a.value(), "💖");
+        assert_eq!(a.value().unwrap(), "💖");
+    }
+
+    #[test]
+    fn invalid_utf8_value_is_error() {
+        let mut a = Attribute::new("id", "");
+        a.value = vec![b'a', 0xFF];
+        assert!(a.value().is_err());
+        assert_eq!(a.to_html(), "id=\"a\u{FFFD}\"");
     }
 
     #[test]
@@ -94,4 +251,54 @@ mod tests {
         let a = Attribute::new("id", "💖");
         assert_eq!(a.to_html(), "id=\"💖\"");
     }
+
+    #[test]
+    fn attribute_to_html_escapes_value() {
+        let a = Attribute::new("title", "\"quoted\" & <b>");
+        assert_eq!(a.to_html(), "title=\"&quot;quoted&quot; &amp; &lt;b&gt;\"");
+    }
+
+    #[test]
+    fn foreign_attributes_are_adjusted() {
+        let mut attributes = vec![Attribute::new("viewbox", "0 0 1 1"), Attribute::new("xlink:href", "#a"),
+                                  Attribute::new("definitionurl", "x")];
+        let mut atoms = Atoms::new();
+        adjust_attributes(&mut attributes, Namespace::Svg, false, &mut atoms);
+        assert_eq!(attributes[0].name().unwrap(), "viewBox");
+        assert_eq!(attributes[0].namespace(), None);
+        assert_eq!(attributes[1].name().unwrap(), "xlink:href");
+        assert_eq!(attributes[1].namespace(), Some(Namespace::XLink));
+        assert_eq!(attributes[2].name().unwrap(), "definitionurl");
+
+        adjust_attributes(&mut attributes, Namespace::MathMl, false, &mut atoms);
+        assert_eq!(attributes[2].name().unwrap(), "definitionURL");
+    }
+
+    #[test]
+    fn raw_attributes_share_interned_names() {
+        let mut atoms = Atoms::new();
+        let mut raw = RawAttribute::new(Span::default());
+        raw.name = b"class".to_vec();
+        let a = raw.clone().intern(&mut atoms);
+        let b = raw.intern(&mut atoms);
+        assert!(a.name.ptr_eq(&b.name));
+
+        let mut raw = RawAttribute::new(Span::default());
+        raw.name = vec![b'a', 0xFF];
+        assert_eq!(raw.intern(&mut atoms).name().unwrap(), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn duplicate_attributes_are_removed() {
+        let mut attributes = vec![Attribute::new("id", "a"), Attribute::new("ID", "b"), Attribute::new("class", "c"), Attribute::new("id", "d")];
+        let mut removed = Vec::new();
+        remove_duplicate_attributes(&mut attributes, |x| x.name.as_bytes(), |x| removed.push(x.value().unwrap().to_string()));
+        assert_eq!(attributes, [Attribute::new("id", "a"), Attribute::new("class", "c")]);
+        assert_eq!(removed, ["b", "d"]);
+
+        let mut attributes: Vec<Attribute> = (0..100000).map(|x| Attribute::new(&format!("a{}", x % 50000), "")).collect();
+        let mut count = 0;
+        remove_duplicate_attributes(&mut attributes, |x| x.name.as_bytes(), |_| count += 1);
+        assert_eq!((attributes.len(), count), (50000, 50000));
+    }
 }
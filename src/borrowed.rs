@@ -0,0 +1,994 @@
+use std;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+use tag::{Tag, tag_from_name, tag_in_namespace, tag_spelling};
+use attribute::{Attribute, foreign_attribute_name, foreign_attribute_namespace, remove_duplicate_attributes};
+use namespace::{Namespace, ForeignContent};
+use atom::{Atom, Atoms};
+use doctype::{Doctype};
+use error::{Error, ParseError};
+use position::{Position, Span, Cursor};
+use parser::{ParserOptions};
+use tokenizer::{Token, truncated_length};
+use charref;
+
+/// Attribute borrowing its name and value from the document.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BorrowedAttribute<'a> {
+    pub name: Cow<'a, str>,
+    pub value: Cow<'a, str>,
+    pub span: Span,
+    pub namespace: Option<Namespace>,
+}
+
+impl<'a> BorrowedAttribute<'a> {
+    /// Convert into an owned attribute
+    pub fn into_owned(self) -> Attribute {
+        Attribute {
+            name: Atom::from(self.name.into_owned()),
+            value: self.value.into_owned().into_bytes(),
+            span: self.span,
+            namespace: self.namespace,
+        }
+    }
+}
+
+/// Token borrowing its data from the document, produced by
+/// [BorrowedTokenizer](struct.BorrowedTokenizer.html).
+///
+/// Text and attribute values are only copied when character
+/// references are decoded.
+#[derive(Clone, PartialEq, Debug)]
+pub enum BorrowedToken<'a> {
+    /// Start tag, eg. ```<p class="info">```
+    StartTag {
+        tag: Tag,
+        /// Name as written when the case is preserved and it differs
+        /// from the name of the tag, eg. ```BR``` for ```<BR>```
+        spelling: Option<Atom>,
+        attributes: Vec<BorrowedAttribute<'a>>,
+        self_closing: bool,
+        span: Span,
+    },
+
+    /// End tag, eg. ```</p>```
+    EndTag {
+        tag: Tag,
+        span: Span,
+    },
+
+    /// Text between tags and the content of elements such as
+    /// ```<script>``` and ```<textarea>```
+    Text {
+        data: Cow<'a, str>,
+        span: Span,
+    },
+
+    /// Comment, eg. the text between ```<!--``` and ```-->```
+    Comment {
+        data: &'a str,
+        span: Span,
+    },
+
+    /// Doctype declaration
+    Doctype {
+        doctype: Doctype,
+        span: Span,
+    },
+
+    /// Content of a CDATA section
+    CData {
+        data: &'a str,
+        span: Span,
+    },
+
+    /// Processing instruction, eg. the text between ```<?``` and
+    /// ```?>```
+    ProcessingInstruction {
+        data: &'a str,
+        span: Span,
+    },
+
+    /// Recoverable error in the document
+    Error {
+        error: ParseError,
+        span: Span,
+    },
+}
+
+impl<'a> BorrowedToken<'a> {
+    /// Get span of the token in the source document
+    pub fn span(&self) -> &Span {
+        match *self {
+            BorrowedToken::StartTag { ref span, .. } => span,
+            BorrowedToken::EndTag { ref span, .. } => span,
+            BorrowedToken::Text { ref span, .. } => span,
+            BorrowedToken::Comment { ref span, .. } => span,
+            BorrowedToken::Doctype { ref span, .. } => span,
+            BorrowedToken::CData { ref span, .. } => span,
+            BorrowedToken::ProcessingInstruction { ref span, .. } => span,
+            BorrowedToken::Error { ref span, .. } => span,
+        }
+    }
+
+    /// Convert into an owned token
+    pub fn into_owned(self) -> Token {
+        match self {
+            BorrowedToken::StartTag { tag, spelling, attributes, self_closing, span } => Token::StartTag {
+                tag,
+                spelling,
+                attributes: attributes.into_iter().map(|x| x.into_owned()).collect(),
+                self_closing,
+                span,
+            },
+            BorrowedToken::EndTag { tag, span } => Token::EndTag { tag, span },
+            BorrowedToken::Text { data, span } => Token::Text { data: data.into_owned().into_bytes(), span },
+            BorrowedToken::Comment { data, span } => Token::Comment { data: data.as_bytes().to_vec(), span },
+            BorrowedToken::Doctype { doctype, span } => Token::Doctype { doctype, span },
+            BorrowedToken::CData { data, span } => Token::CData { data: data.as_bytes().to_vec(), span },
+            BorrowedToken::ProcessingInstruction { data, span } => Token::ProcessingInstruction { data: data.as_bytes().to_vec(), span },
+            BorrowedToken::Error { error, span } => Token::Error { error, span },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    FindTag,
+    TagName,
+    Comment,
+    Doctype,
+    CData,
+    ProcessingInstruction,
+    Data,
+    RawData,
+    RcData,
+    PlainText,
+    AttributeName,
+    AttributeValue,
+    Done,
+}
+
+// Bytes of the document read into a name or value, a range of the
+// document unless the bytes are not contiguous.
+#[derive(Clone)]
+struct Bytes {
+    start: usize,
+    end: usize,
+    owned: Option<Vec<u8>>,
+}
+
+impl Bytes {
+    fn new() -> Bytes {
+        Bytes {
+            start: 0,
+            end: 0,
+            owned: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.owned.is_none() && self.start == self.end
+    }
+
+    // Add the byte at index of input
+    fn push(&mut self, input: &[u8], index: usize) {
+        if let Some(ref mut x) = self.owned {
+            x.push(input[index]);
+        } else if self.start == self.end {
+            self.start = index;
+            self.end = index + 1;
+        } else if self.end == index {
+            self.end += 1;
+        } else {
+            let mut x = input[self.start..self.end].to_vec();
+            x.push(input[index]);
+            self.owned = Some(x);
+        }
+    }
+
+    fn get<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        match self.owned {
+            Some(ref x) => String::from_utf8_lossy(x).into_owned().into(),
+            None => Cow::Borrowed(&input[self.start..self.end]),
+        }
+    }
+}
+
+// Attribute read so far
+#[derive(Clone)]
+struct PendingAttribute {
+    name: Bytes,
+    value: Bytes,
+    span: Span,
+}
+
+impl PendingAttribute {
+    fn new() -> PendingAttribute {
+        PendingAttribute {
+            name: Bytes::new(),
+            value: Bytes::new(),
+            span: Span::default(),
+        }
+    }
+}
+
+// Test if buf starts with keyword, None if the document ends before
+// it can be told
+fn starts_with(buf: &[u8], keyword: &[u8], ignore_case: bool) -> Option<bool> {
+    let length = std::cmp::min(buf.len(), keyword.len());
+    let matched = match ignore_case {
+        true => buf[..length].eq_ignore_ascii_case(&keyword[..length]),
+        false => buf[..length] == keyword[..length],
+    };
+
+    if !matched {
+        Some(false)
+    } else if length < keyword.len() {
+        None
+    } else {
+        Some(true)
+    }
+}
+
+// Find needle in data starting at pos, returns the position of needle
+fn find(data: &[u8], pos: usize, needle: &[u8]) -> Option<usize> {
+    data[pos..].windows(needle.len())
+        .position(|x| x == needle)
+        .map(|x| pos + x)
+}
+
+/// Tokenizer for documents in memory, the tokens borrow their data
+/// from the document.
+///
+/// It produces the same tokens as [Tokenizer](struct.Tokenizer.html)
+/// without copying the document, text and attribute values are only
+/// copied when character references are decoded. The document is
+/// expected to be UTF-8, a leading byte order mark is skipped.
+///
+/// # Examples
+///
+/// ```
+/// use domx::{BorrowedTokenizer, BorrowedToken};
+///
+/// let html = "<p class=\"info\">Hello</p>";
+/// for token in BorrowedTokenizer::new(html) {
+///     match token {
+///         BorrowedToken::StartTag { tag, .. } => println!("start {}", tag),
+///         BorrowedToken::Text { data, .. } => println!("text {}", data),
+///         _ => (),
+///     }
+/// }
+/// ```
+pub struct BorrowedTokenizer<'a> {
+    input: &'a str,
+    options: ParserOptions,
+    state: State,
+    pos: usize,
+    // Length of the byte order mark skipped before input
+    bom: usize,
+    cursor: Cursor,
+    tokens: VecDeque<BorrowedToken<'a>>,
+
+    // Tag being read
+    start: Position,
+    name: Vec<u8>,
+    closing: bool,
+    tag: Option<Tag>,
+    attributes: Vec<PendingAttribute>,
+    foreign: ForeignContent,
+    atoms: Atoms,
+}
+
+impl<'a> BorrowedTokenizer<'a> {
+    /// Create a new tokenizer for document
+    pub fn new(input: &'a str) -> BorrowedTokenizer<'a> {
+        BorrowedTokenizer::with_options(input, &ParserOptions::default())
+    }
+
+    /// Create a new tokenizer for document using options, the
+    /// encoding options are not used. The document is always read by
+    /// the legacy tokenizer, giving the same tokens as
+    /// [Tokenizer](struct.Tokenizer.html) does, and
+    /// [ParserOptions::tokenizer](struct.ParserOptions.html#structfield.tokenizer)
+    /// is ignored, also when it is ```TokenizerMode::Whatwg```. A byte
+    /// order mark starting the document is skipped, spans are still
+    /// offsets in the whole document.
+    pub fn with_options(input: &'a str, options: &ParserOptions) -> BorrowedTokenizer<'a> {
+        let (input, bom) = match input.strip_prefix('\u{FEFF}') {
+            Some(x) => (x, '\u{FEFF}'.len_utf8()),
+            None => (input, 0),
+        };
+        let mut base = Position::new();
+        base.offset = bom;
+        BorrowedTokenizer {
+            input,
+            options: options.clone(),
+            state: State::FindTag,
+            pos: 0,
+            bom,
+            cursor: Cursor::new(base),
+            tokens: VecDeque::new(),
+            start: Position::new(),
+            name: Vec::new(),
+            closing: false,
+            tag: None,
+            attributes: Vec::new(),
+            foreign: ForeignContent::new(),
+            atoms: Atoms::new(),
+        }
+    }
+
+    /// Create a new tokenizer for a document of bytes, fails if the
+    /// document is not valid UTF-8.
+    pub fn from_bytes(input: &'a [u8]) -> Result<BorrowedTokenizer<'a>, Error> {
+        Ok(BorrowedTokenizer::new(std::str::from_utf8(input)?))
+    }
+
+    /// Get the number of bytes of the document parsed so far
+    pub fn parsed(&self) -> usize {
+        self.bom + self.pos
+    }
+
+    fn _position(&mut self, index: usize) -> Position {
+        self.cursor.at(self.input.as_bytes(), index)
+    }
+
+    fn _span(&mut self, start: usize, end: usize) -> Span {
+        Span::new(self._position(start), self._position(end))
+    }
+
+    // Report error for the byte at index
+    fn _error(&mut self, index: usize, error: ParseError) {
+        let span = self._span(index, index + 1);
+        self.tokens.push_back(BorrowedToken::Error { error, span });
+    }
+
+    // Extend span of last attribute to include byte at index
+    fn _extend_attribute_span(&mut self, index: usize) {
+        let start = self._position(index);
+        let end = self._position(index + 1);
+        if let Some(attr) = self.attributes.last_mut() {
+            if attr.name.is_empty() {
+                attr.span.start = start;
+            }
+            attr.span.end = end;
+        }
+    }
+
+    // Report errors for a document ending inside a token
+    fn _end_of_file(&mut self) {
+        let error = match self.state {
+            State::Comment => Some(ParseError::EofInComment),
+            State::Doctype => Some(ParseError::EofInDoctype),
+            State::CData => Some(ParseError::EofInCdata),
+            State::TagName if self.name.is_empty() && !self.closing => Some(ParseError::EofBeforeTagName),
+            State::TagName | State::AttributeName | State::AttributeValue => Some(ParseError::EofInTag),
+            _ => None,
+        };
+        self.pos = self.input.len();
+        if let Some(x) = error {
+            let position = self._position(self.pos);
+            self.tokens.push_back(BorrowedToken::Error { error: x, span: Span::new(position, position) });
+        }
+        self.state = State::Done;
+    }
+
+    fn _state_find_tag(&mut self) {
+        match self.input.as_bytes()[self.pos..].iter().position(|&x| x == b'<') {
+            Some(x) => {
+                self.pos += x;
+                let pos = self.pos;
+                self.start = self._position(pos);
+                self.name.clear();
+                self.closing = false;
+                self.tag = None;
+                self.state = State::TagName;
+            },
+            None => self._end_of_file(),
+        }
+    }
+
+    fn _state_tag_name(&mut self) {
+        let input = self.input.as_bytes();
+        while self.pos < input.len() {
+            let b = input[self.pos];
+            match b {
+                // Skip begin of tag
+                b'<' | b'\r' | b'\n' => self.pos += 1,
+
+                // Comment, doctype or CDATA section detected
+                b'!' => {
+                    let rest = &input[self.pos + 1..];
+                    let comment = starts_with(rest, b"--", false);
+                    let doctype = starts_with(rest, b"DOCTYPE", true);
+                    let cdata = starts_with(rest, b"[CDATA[", false);
+
+                    self.state = match (comment, doctype, cdata) {
+                        (Some(true), _, _) => {
+                            self.pos += 3;
+                            State::Comment
+                        },
+                        (_, Some(true), _) => {
+                            self.pos += 8;
+                            State::Doctype
+                        },
+                        (_, _, Some(true)) => {
+                            self.pos += 8;
+                            State::CData
+                        },
+                        (None, _, _) | (_, None, _) | (_, _, None) => break,
+                        _ => {
+                            let pos = self.pos;
+                            self._error(pos, ParseError::IncorrectlyOpenedComment);
+                            self.pos += 1;
+                            State::FindTag
+                        }
+                    };
+                    return;
+                },
+
+                // Processing instruction detected, eg. <?xml ... ?>
+                b'?' if self.name.is_empty() && !self.closing => {
+                    let pos = self.pos;
+                    self._error(pos, ParseError::UnexpectedQuestionMarkInsteadOfTagName);
+                    self.pos += 1;
+                    self.state = State::ProcessingInstruction;
+                    return;
+                },
+
+                // Closing tag detected
+                b'/' if self.name.is_empty() => {
+                    self.closing = true;
+                    self.pos += 1;
+                },
+
+                // Complete tag name read
+                b'>' | b' ' | b'/' => {
+                    if self.name.is_empty() {
+                        let error = match (b, self.closing) {
+                            (b'>', true) => ParseError::MissingEndTagName,
+                            _ => ParseError::InvalidFirstCharacterOfTagName,
+                        };
+                        let pos = self.pos;
+                        self._error(pos, error);
+                    }
+                    self.attributes.clear();
+                    self.attributes.push(PendingAttribute::new());
+                    self.state = State::AttributeName;
+                    return;
+                },
+
+                _ => {
+                    if self.name.is_empty() && !b.is_ascii_alphabetic() {
+                        let pos = self.pos;
+                        self._error(pos, ParseError::InvalidFirstCharacterOfTagName);
+                    }
+                    // Keep one byte more than the limit to tell the name
+                    // is truncated
+                    if self.name.len() <= self.options.max_name_length {
+                        self.name.push(b);
+                    }
+                    self.pos += 1;
+                }
+            }
+        }
+
+        self._end_of_file();
+    }
+
+    // Read until terminator, returns the content if terminator was found
+    fn _read_until(&mut self, terminator: &[u8]) -> Option<(&'a str, Span)> {
+        let input: &'a str = self.input;
+        match find(input.as_bytes(), self.pos, terminator) {
+            Some(end) => {
+                let data = &input[self.pos..end];
+                self.pos = end + terminator.len();
+                self.state = State::Data;
+                let pos = self.pos;
+                Some((data, Span::new(self.start, self._position(pos))))
+            },
+            None => {
+                self._end_of_file();
+                None
+            }
+        }
+    }
+
+    fn _state_comment(&mut self) {
+        if let Some((data, span)) = self._read_until(b"-->") {
+            if !self.options.skip_comments {
+                self.tokens.push_back(BorrowedToken::Comment { data, span });
+            }
+        }
+    }
+
+    fn _state_cdata(&mut self) {
+        if let Some((data, span)) = self._read_until(b"]]>") {
+            self.tokens.push_back(BorrowedToken::CData { data, span });
+        }
+    }
+
+    fn _state_processing_instruction(&mut self) {
+        if let Some((data, span)) = self._read_until(b"?>") {
+            self.tokens.push_back(BorrowedToken::ProcessingInstruction { data, span });
+        }
+    }
+
+    fn _state_doctype(&mut self) {
+        if let Some((data, span)) = self._read_until(b">") {
+            self.tokens.push_back(BorrowedToken::Doctype { doctype: Doctype::parse(data.as_bytes()), span });
+        }
+    }
+
+    // Emit text between start and end
+    fn _text(&mut self, start: usize, end: usize, decode: bool) {
+        if end > start {
+            let input: &'a str = self.input;
+            let data = &input[start..end];
+            let span = self._span(start, end);
+            let data = match decode && self.options.decode_entities && data.contains('&') {
+                true => String::from_utf8_lossy(&charref::decode(data.as_bytes(), false)).into_owned().into(),
+                false => Cow::Borrowed(data),
+            };
+            self.tokens.push_back(BorrowedToken::Text { data, span });
+        }
+    }
+
+    // Read data until next tag, text at the end of the document is kept
+    fn _state_data(&mut self) {
+        let start = self.pos;
+        match self.input.as_bytes()[start..].iter().position(|&x| x == b'<') {
+            Some(length) => {
+                self._text(start, start + length, true);
+                self.pos += length;
+                self.state = State::FindTag;
+            },
+            None => {
+                let end = self.input.len();
+                self._text(start, end, true);
+                self._end_of_file();
+            }
+        }
+    }
+
+    // Read data until closing element, eg <script></script>, character
+    // references are decoded if decode is set
+    fn _state_raw_data(&mut self, decode: bool) {
+        let bytes = self.input.as_bytes();
+        let name = match self.tag {
+            Some(ref x) => x.to_string().into_bytes(),
+            None => Vec::new(),
+        };
+
+        let mut pos = self.pos;
+        while let Some(x) = bytes[pos..].iter().position(|&x| x == b'<') {
+            pos += x;
+            let rest = &bytes[pos..];
+            if rest.len() < name.len() + 3 {
+                break;
+            }
+
+            let found = rest[1] == b'/' && rest[2..2 + name.len()].eq_ignore_ascii_case(&name) && matches!(rest[2 + name.len()], b'\t' | b'\n' | 0x0C | b'\r' | b' ' | b'/' | b'>');
+
+            if found {
+                let start = self.pos;
+                self._text(start, pos, decode);
+                self.pos = pos;
+                self.state = State::FindTag;
+                return;
+            }
+            pos += 1;
+        }
+
+        // Raw text runs to the end of the document
+        let (start, end) = (self.pos, bytes.len());
+        self._text(start, end, decode);
+
+        self._end_of_file();
+    }
+
+    // Read data until the end of the document, eg. after <plaintext>
+    fn _state_plain_text(&mut self) {
+        let (start, end) = (self.pos, self.input.len());
+        self._text(start, end, false);
+        self._end_of_file();
+    }
+
+    // Get state for reading the content of an element
+    fn _content_state(tag: &Tag) -> State {
+        match *tag {
+            Tag::PLAINTEXT => State::PlainText,
+            _ if tag.is_raw_text() => State::RawData,
+            _ if tag.is_escapable_raw_text() => State::RcData,
+            _ => State::Data,
+        }
+    }
+
+    // Start a new attribute, when there are more attributes than the
+    // limit the last one is dropped
+    fn _new_attribute(&mut self) {
+        if self.attributes.len() <= self.options.max_attributes {
+            self.attributes.push(PendingAttribute::new());
+            return;
+        }
+
+        let attr = self.attributes.pop().unwrap();
+        if !attr.name.is_empty() {
+            self.tokens.push_back(BorrowedToken::Error { error: ParseError::TooManyAttributes, span: attr.span });
+        }
+        self.attributes.push(PendingAttribute::new());
+    }
+
+    // Get attribute with name and value truncated to the limits and the
+    // value decoded
+    fn _finish_attribute(&mut self, attr: &PendingAttribute) -> BorrowedAttribute<'a> {
+        let input: &'a str = self.input;
+        let mut name = attr.name.get(input);
+        let length = truncated_length(name.as_bytes(), self.options.max_name_length);
+        if length < name.len() {
+            name = match name {
+                Cow::Borrowed(x) => Cow::Borrowed(&x[..length]),
+                Cow::Owned(x) => Cow::Owned(x[..length].to_string()),
+            };
+            self.tokens.push_back(BorrowedToken::Error { error: ParseError::NameTooLong, span: attr.span });
+        }
+
+        let mut value = attr.value.get(input);
+        let length = truncated_length(value.as_bytes(), self.options.max_attribute_value_length);
+        let mut truncated = length < value.len();
+        if truncated {
+            value = match value {
+                Cow::Borrowed(x) => Cow::Borrowed(&x[..length]),
+                Cow::Owned(x) => Cow::Owned(x[..length].to_string()),
+            };
+        }
+        if self.options.decode_entities && value.contains('&') {
+            let mut decoded = charref::decode(value.as_bytes(), true);
+            let length = truncated_length(&decoded, self.options.max_attribute_value_length);
+            truncated |= length < decoded.len();
+            decoded.truncate(length);
+            value = String::from_utf8_lossy(&decoded).into_owned().into();
+        }
+        if truncated {
+            self.tokens.push_back(BorrowedToken::Error { error: ParseError::AttributeValueTooLong, span: attr.span });
+        }
+
+        BorrowedAttribute {
+            name,
+            value,
+            span: attr.span,
+            namespace: None,
+        }
+    }
+
+    // Finish start or end tag ending at index
+    fn _finish_tag(&mut self, index: usize, self_closing: bool) {
+        let span = Span::new(self.start, self._position(index + 1));
+        let length = truncated_length(&self.name, self.options.max_name_length);
+        if length < self.name.len() {
+            self.name.truncate(length);
+            self.tokens.push_back(BorrowedToken::Error { error: ParseError::NameTooLong, span });
+        }
+
+        let tag = tag_from_name(&String::from_utf8_lossy(&self.name), self.options.preserve_case, &mut self.atoms);
+        let mut tag = self.options.tags.resolve(&tag).unwrap_or(tag);
+        let preserve_case = self.options.preserve_case;
+
+        self.state = match self.closing {
+            false => {
+                if self.attributes.len() > self.options.max_attributes {
+                    let attr = self.attributes.pop().unwrap();
+                    self.tokens.push_back(BorrowedToken::Error { error: ParseError::TooManyAttributes, span: attr.span });
+                }
+                let pending = std::mem::take(&mut self.attributes);
+                let mut attributes: Vec<BorrowedAttribute<'a>> = pending.iter().map(|x| self._finish_attribute(x)).collect();
+                self.attributes = pending;
+                let namespace = self.foreign.start_tag(&tag, attributes.iter().map(|x| (x.name.as_bytes(), x.value.as_bytes())), self_closing);
+                tag = tag_in_namespace(&tag, namespace, preserve_case, &mut self.atoms);
+                if namespace != Namespace::Html {
+                    for attr in attributes.iter_mut() {
+                        if let Some(x) = foreign_attribute_name(attr.name.as_bytes(), namespace).filter(|_| !preserve_case) {
+                            attr.name = x.into();
+                        }
+                        attr.namespace = foreign_attribute_namespace(attr.name.as_bytes());
+                    }
+                }
+                let tokens = &mut self.tokens;
+                remove_duplicate_attributes(&mut attributes, |x| x.name.as_bytes(), |x| {
+                    tokens.push_back(BorrowedToken::Error { error: ParseError::DuplicateAttribute, span: x.span });
+                });
+                let next = match namespace {
+                    Namespace::Html => BorrowedTokenizer::_content_state(&tag),
+                    _ => State::Data,
+                };
+                let spelling = tag_spelling(&tag, &String::from_utf8_lossy(&self.name), preserve_case, &mut self.atoms);
+                self.tokens.push_back(BorrowedToken::StartTag { tag: tag.clone(), spelling, attributes, self_closing, span });
+                next
+            },
+            true => {
+                if !self.attributes.is_empty() {
+                    self.tokens.push_back(BorrowedToken::Error { error: ParseError::EndTagWithAttributes, span });
+                }
+                if self_closing {
+                    self.tokens.push_back(BorrowedToken::Error { error: ParseError::EndTagWithTrailingSolidus, span });
+                }
+                let namespace = self.foreign.end_tag(&tag);
+                tag = tag_in_namespace(&tag, namespace, preserve_case, &mut self.atoms);
+                self.tokens.push_back(BorrowedToken::EndTag { tag: tag.clone(), span });
+                State::Data
+            }
+        };
+        self.tag = Some(tag);
+        self.pos = index + 1;
+    }
+
+    fn _state_attribute_name(&mut self) {
+        let input = self.input.as_bytes();
+        while self.pos < input.len() {
+            let pos = self.pos;
+            match input[pos] {
+                // Self-closing tag, eg. <br/>, or a misplaced solidus
+                b'/' => match input.get(pos + 1) {
+                    Some(&b'>') => {
+                        if self.attributes.last().is_some_and(|x| x.name.is_empty()) {
+                            // pop last attribute if it is an empty placeholder
+                            self.attributes.pop();
+                        }
+                        self._finish_tag(pos + 1, true);
+                        return;
+                    },
+                    Some(_) => {
+                        self._error(pos, ParseError::UnexpectedSolidusInTag);
+                        if self.attributes.last().is_some_and(|x| !x.name.is_empty()) {
+                            self._new_attribute();
+                        }
+                        self.pos += 1;
+                    },
+                    None => break,
+                },
+
+                // Found closing, lets finish up
+                b'>' => {
+                    if self.attributes.last().is_some_and(|x| x.name.is_empty()) {
+                        // pop last attribute if it is an empty placeholder
+                        self.attributes.pop();
+                    }
+                    self._finish_tag(pos, false);
+                    return;
+                },
+
+                b'=' => {
+                    self.pos += 1;
+                    self.state = State::AttributeValue;
+                    return;
+                },
+
+                b' ' => {
+                    if self.attributes.last().is_some_and(|x| !x.name.is_empty()) {
+                        self._new_attribute();
+                    }
+                    self.pos += 1;
+                },
+
+                b'\n' | b'\r' | b'\t' => self.pos += 1,
+
+                _ => {
+                    self._extend_attribute_span(pos);
+                    if let Some(attr) = self.attributes.last_mut() {
+                        attr.name.push(input, pos);
+                    }
+                    self.pos += 1;
+                }
+            }
+        }
+
+        self._end_of_file();
+    }
+
+    fn _state_attribute_value(&mut self) {
+        let input = self.input.as_bytes();
+        while self.pos < input.len() {
+            let pos = self.pos;
+            let (have_value, is_quoted) = match self.attributes.last() {
+                Some(x) if !x.value.is_empty() => (true, input[x.value.start] == b'"' || input[x.value.start] == b'\''),
+                _ => (false, false),
+            };
+
+            match input[pos] {
+                b'>' => {
+                    let (placeholder, empty) = match self.attributes.last() {
+                        Some(x) => (x.name.is_empty(), x.value.is_empty()),
+                        None => (false, false),
+                    };
+                    // pop last attribute if it is an empty placeholder
+                    if placeholder {
+                        self.attributes.pop();
+                    } else if empty {
+                        self._error(pos, ParseError::MissingAttributeValue);
+                    }
+                    self._finish_tag(pos, false);
+                    self.attributes.clear();
+                    return;
+                },
+
+                b'"' | b'\'' if have_value => {
+                    self._extend_attribute_span(pos);
+                    if let Some(attr) = self.attributes.last_mut() {
+                        // Trim " and ' from attribute value
+                        let value = &mut attr.value;
+                        if input[value.start] == b'"' || input[value.start] == b'\'' {
+                            value.start += 1;
+                        }
+                        if value.start < value.end && (input[value.end - 1] == b'"' || input[value.end - 1] == b'\'') {
+                            value.end -= 1;
+                        }
+                    }
+                    self._new_attribute();
+                    self.pos += 1;
+                    self.state = State::AttributeName;
+                    return;
+                },
+
+                b' ' if have_value && !is_quoted => {
+                    self._new_attribute();
+                    self.pos += 1;
+                    self.state = State::AttributeName;
+                    return;
+                },
+
+                _ => {
+                    self._extend_attribute_span(pos);
+                    if let Some(attr) = self.attributes.last_mut() {
+                        attr.value.push(input, pos);
+                    }
+                    self.pos += 1;
+                }
+            }
+        }
+
+        self._end_of_file();
+    }
+}
+
+impl<'a> Iterator for BorrowedTokenizer<'a> {
+    type Item = BorrowedToken<'a>;
+
+    fn next(&mut self) -> Option<BorrowedToken<'a>> {
+        loop {
+            if let Some(token) = self.tokens.pop_front() {
+                return Some(token);
+            }
+
+            match self.state {
+                State::FindTag => self._state_find_tag(),
+                State::TagName => self._state_tag_name(),
+                State::Comment => self._state_comment(),
+                State::Doctype => self._state_doctype(),
+                State::CData => self._state_cdata(),
+                State::ProcessingInstruction => self._state_processing_instruction(),
+                State::Data => self._state_data(),
+                State::RawData => self._state_raw_data(false),
+                State::RcData => self._state_raw_data(true),
+                State::PlainText => self._state_plain_text(),
+                State::AttributeName => self._state_attribute_name(),
+                State::AttributeValue => self._state_attribute_value(),
+                State::Done => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use tag::Tag;
+    use parser::ParserOptions;
+    use tokenizer::{Tokenizer, Token};
+    use borrowed::{BorrowedTokenizer, BorrowedToken};
+
+    fn owned_tokens(data: &str, options: &ParserOptions) -> Vec<Token> {
+        Tokenizer::with_options(data.as_bytes(), options).map(|x| x.unwrap()).collect()
+    }
+
+    fn borrowed_tokens(data: &str, options: &ParserOptions) -> Vec<Token> {
+        BorrowedTokenizer::with_options(data, options).map(|x| x.into_owned()).collect()
+    }
+
+    #[test]
+    fn borrowed_tokens_borrow_from_document() {
+        let t: Vec<BorrowedToken> = BorrowedTokenizer::new("<p class=\"info\">Hello &amp; bye</p><!-- end -->").collect();
+        assert_eq!(t.len(), 4);
+        match t[0] {
+            BorrowedToken::StartTag { ref tag, ref attributes, .. } => {
+                assert_eq!(*tag, Tag::P);
+                assert_eq!(attributes[0].name, Cow::Borrowed("class"));
+                assert_eq!(attributes[0].value, Cow::Borrowed("info"));
+            },
+            _ => panic!("expected start tag"),
+        }
+        match t[1] {
+            BorrowedToken::Text { data: Cow::Owned(ref data), .. } => assert_eq!(data, "Hello & bye"),
+            _ => panic!("expected decoded text"),
+        }
+        assert_eq!(t[3], BorrowedToken::Comment { data: " end ", span: *t[3].span() });
+    }
+
+    #[test]
+    fn borrowed_tokens_non_ascii_tag_names() {
+        let t: Vec<BorrowedToken> = BorrowedTokenizer::new("<x-caf\u{e9}>a</X-CAF\u{c9}>").collect();
+        match t[0] {
+            BorrowedToken::StartTag { ref tag, .. } => assert_eq!(tag.as_str(), "x-caf\u{e9}"),
+            _ => panic!("expected start tag"),
+        }
+        match t[2] {
+            BorrowedToken::EndTag { ref tag, .. } => assert_eq!(tag.as_str(), "x-caf\u{e9}"),
+            _ => panic!("expected end tag"),
+        }
+    }
+
+    #[test]
+    fn borrowed_tokens_from_bytes() {
+        assert!(BorrowedTokenizer::from_bytes(b"<p>\xFF</p>").is_err());
+        assert_eq!(BorrowedTokenizer::from_bytes(b"\xEF\xBB\xBF<p>").unwrap().count(), 1);
+    }
+
+    #[test]
+    fn borrowed_tokens_skip_one_byte_order_mark() {
+        let input = "\u{FEFF}\u{FEFF}<p>a";
+        let mut tokenizer = BorrowedTokenizer::new(input);
+        let t: Vec<BorrowedToken> = tokenizer.by_ref().collect();
+        assert_eq!(tokenizer.parsed(), input.len());
+        match t[0] {
+            BorrowedToken::StartTag { span, .. } => {
+                assert_eq!(span.start.offset, 6);
+                assert_eq!(&input[span.start.offset..span.end.offset], "<p>");
+            },
+            _ => panic!("expected start tag"),
+        }
+        match t[1] {
+            BorrowedToken::Text { span, .. } => assert_eq!(&input[span.start.offset..span.end.offset], "a"),
+            _ => panic!("expected text"),
+        }
+    }
+
+    #[test]
+    fn borrowed_tokens_equal_tokenizer() {
+        let documents = [
+            "",
+            "<!DOCTYPE html><html><head><title>Test &lt;1&gt;</title></head><body>\n<p class=\"info\" id='x'>Hello<br/></p></body></html>\n",
+            "<p id=a id=b ID=c>x<!-- open",
+            "<img src=\"x\" />text<a\nhref\t=x title=\"a b\">link</a>",
+            "<script>if (a < b) { x = '</p>'; }</script><style type=\"text/css\">p { }</style>",
+            "<?xml version=\"1.0\"?><![CDATA[ <raw> ]]><!bogus><p>",
+            "</ p>< b></>a < b</p x=1>&#x41;&amp",
+            "<p title=caf\u{e9} data-\u{2603}=\"\u{2603}\">\u{c5}ngstr\u{f6}m</p><sCrIpT>x</SCRIPT>",
+            "<div a=\"1\"b='2' c = d e>",
+            "<script>a</scripts></SCRIPT ><textarea>&amp;<p></textarea/><title>x&lt;</title><plaintext></plaintext>&amp;",
+            "<xmp>a</xmp><iframe><b></iframe><noembed>x</NOEMBED><title>abc",
+            "<br/><img src=x/><img src=\"x\"/><p a/b c/ >x</p/><hr /",
+            "<!DOCTYP",
+            "<!-- a -- b --><!doctype html",
+            "<SVG viewBox=\"0 0 1 1\" VIEWBOX=x><ForeignObject><DIV ViewBox=y>a</div></foreignobject></svg>",
+            "<P>a<BR>b<IMG SRC=x>c</P><SCRIPT>a<b</SCRIPT>",
+        ];
+
+        // Tokens and their spans are the same for every prefix of the
+        // documents, ie. also for documents ending anywhere in a token
+        let options = [
+            ParserOptions::default(),
+            ParserOptions { decode_entities: false, skip_comments: true, ..Default::default() },
+            ParserOptions { preserve_case: true, ..Default::default() },
+            ParserOptions { max_name_length: 3, max_attributes: 1, max_attribute_value_length: 2, ..Default::default() },
+        ];
+        for options in options.iter() {
+            for doc in documents.iter() {
+                for end in (0..doc.len() + 1).filter(|x| doc.is_char_boundary(*x)) {
+                    let doc = &doc[..end];
+                    assert_eq!(borrowed_tokens(doc, options), owned_tokens(doc, options), "document {:?}", doc);
+                }
+            }
+        }
+    }
+}
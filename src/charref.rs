@@ -0,0 +1,204 @@
+use std;
+
+use entities::{ENTITIES};
+
+// Longest named character reference including the semicolon
+pub const MAX_NAME_LENGTH: usize = 32;
+
+// Replacements for numeric references in the C1 control range
+const C1_REPLACEMENTS: [u32; 32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021,
+    0x02C6, 0x2030, 0x0160, 0x2039, 0x0152, 0x008D, 0x017D, 0x008F,
+    0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+];
+
+/// Lookup a named character reference, name without leading '&'.
+pub fn lookup(name: &str) -> Option<&'static str> {
+    match ENTITIES.binary_search_by(|&(x, _)| x.cmp(name)) {
+        Ok(idx) => Some(ENTITIES[idx].1),
+        Err(_) => None,
+    }
+}
+
+/// Map a numeric character reference to the character it represents.
+///
+/// Follows the HTML rules for invalid code points, which are replaced
+/// with U+FFFD, and for the C1 control range, which is mapped as
+/// windows-1252.
+pub fn numeric(value: u32) -> char {
+    match value {
+        0 => '\u{FFFD}',
+        0x80..=0x9F => std::char::from_u32(C1_REPLACEMENTS[(value - 0x80) as usize]).unwrap(),
+        _ => std::char::from_u32(value).unwrap_or('\u{FFFD}'),
+    }
+}
+
+// Decode numeric reference at start of buf, eg. "#x1F496;". Returns
+// the character and number of bytes consumed.
+fn decode_numeric(buf: &[u8]) -> Option<(char, usize)> {
+    let (radix, mut consumed) = match buf.get(1) {
+        Some(&b'x') | Some(&b'X') => (16, 2),
+        _ => (10, 1),
+    };
+
+    let start = consumed;
+    let mut value: u32 = 0;
+    while let Some(digit) = buf.get(consumed).and_then(|&b| (b as char).to_digit(radix)) {
+        // Saturate above the unicode range, the result is U+FFFD anyway
+        value = value.saturating_mul(radix).saturating_add(digit).min(0x110000);
+        consumed += 1;
+    }
+
+    if consumed == start {
+        return None;
+    }
+
+    if buf.get(consumed) == Some(&b';') {
+        consumed += 1;
+    }
+
+    Some((numeric(value), consumed))
+}
+
+/// Find the longest named character reference at start of buf, name
+/// without leading '&'. Returns the replacement and length of the name.
+pub fn match_named(buf: &[u8]) -> Option<(&'static str, usize)> {
+    let mut length = 0;
+    while length < buf.len() && length < MAX_NAME_LENGTH && buf[length].is_ascii_alphanumeric() {
+        length += 1;
+    }
+    if length < buf.len() && buf[length] == b';' {
+        length += 1;
+    }
+
+    for end in (1..length + 1).rev() {
+        // Only ascii bytes so far, conversion can not fail
+        let name = std::str::from_utf8(&buf[..end]).unwrap();
+        if let Some(value) = lookup(name) {
+            return Some((value, end));
+        }
+    }
+
+    None
+}
+
+/// Test if a reference without semicolon in an attribute value is kept
+/// as is, which for historical reasons is the case when it is followed
+/// by '=' or an alphanumeric character.
+pub fn is_legacy_attribute_text(next: Option<u8>) -> bool {
+    match next {
+        Some(b'=') => true,
+        Some(x) => x.is_ascii_alphanumeric(),
+        None => false,
+    }
+}
+
+// Decode named reference at start of buf using the longest matching
+// name. Returns the replacement and number of bytes consumed.
+fn decode_named(buf: &[u8], in_attribute: bool) -> Option<(&'static str, usize)> {
+    match match_named(buf) {
+        Some((_, end)) if in_attribute && buf[end - 1] != b';' && is_legacy_attribute_text(buf.get(end).cloned()) => None,
+        x => x,
+    }
+}
+
+/// Decode character references in text or an attribute value.
+///
+/// Named, decimal and hexadecimal references are decoded, references
+/// that can not be decoded are left as is.
+pub fn decode(data: &[u8], in_attribute: bool) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut pos = 0;
+
+    while pos < data.len() {
+        if data[pos] != b'&' {
+            result.push(data[pos]);
+            pos += 1;
+            continue;
+        }
+
+        let rest = &data[pos + 1..];
+        let decoded = match rest.first() {
+            Some(&b'#') => decode_numeric(rest).map(|(c, consumed)| {
+                let mut utf8 = [0; 4];
+                result.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                consumed
+            }),
+            Some(_) => decode_named(rest, in_attribute).map(|(value, consumed)| {
+                result.extend_from_slice(value.as_bytes());
+                consumed
+            }),
+            None => None,
+        };
+
+        match decoded {
+            Some(consumed) => pos += 1 + consumed,
+            None => {
+                result.push(b'&');
+                pos += 1;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use charref::decode;
+
+    fn text(data: &str) -> String {
+        String::from_utf8(decode(data.as_bytes(), false)).unwrap()
+    }
+
+    fn attribute(data: &str) -> String {
+        String::from_utf8(decode(data.as_bytes(), true)).unwrap()
+    }
+
+    #[test]
+    fn decode_named_references() {
+        assert_eq!(text("a &amp; b &lt;c&gt; &nbsp;"), "a & b <c> \u{a0}");
+        assert_eq!(text("&NotEqualTilde;"), "\u{2242}\u{338}");
+    }
+
+    #[test]
+    fn decode_numeric_references() {
+        assert_eq!(text("&#65;&#x42;&#X43;"), "ABC");
+        assert_eq!(text("&#x1F496;"), "💖");
+        assert_eq!(text("&#65"), "A");
+    }
+
+    #[test]
+    fn decode_invalid_numeric_references() {
+        assert_eq!(text("&#0;"), "\u{FFFD}");
+        assert_eq!(text("&#xD800;"), "\u{FFFD}");
+        assert_eq!(text("&#x110000;"), "\u{FFFD}");
+        assert_eq!(text("&#99999999999999999999;"), "\u{FFFD}");
+        assert_eq!(text("&#x80;"), "\u{20AC}");
+        assert_eq!(text("&#x9F;"), "\u{178}");
+        assert_eq!(text("&#;&#x;"), "&#;&#x;");
+    }
+
+    #[test]
+    fn decode_legacy_reference_without_semicolon() {
+        assert_eq!(text("&ampx &copy2019"), "&x ©2019");
+        assert_eq!(text("&notit;"), "¬it;");
+        assert_eq!(text("&notin;"), "∉");
+    }
+
+    #[test]
+    fn decode_unknown_reference_is_kept() {
+        assert_eq!(text("&foo; & &;"), "&foo; & &;");
+        assert_eq!(text("AT&T"), "AT&T");
+    }
+
+    #[test]
+    fn decode_reference_in_attribute_value() {
+        assert_eq!(attribute("?a=1&amp;b=2"), "?a=1&b=2");
+        assert_eq!(attribute("?a=1&copy=2"), "?a=1&copy=2");
+        assert_eq!(attribute("?a=1&copyx"), "?a=1&copyx");
+        assert_eq!(attribute("&copy;=2"), "©=2");
+        assert_eq!(attribute("&copy 2019"), "© 2019");
+    }
+}
@@ -0,0 +1,329 @@
+use std;
+
+use traits::{ToHTML};
+
+/// Document compatibility mode as determined by the DOCTYPE.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum QuirksMode {
+    /// Standards mode
+    NoQuirks,
+    /// Almost standards mode
+    LimitedQuirks,
+    /// Quirks mode
+    Quirks,
+}
+
+// Public identifier prefixes that trigger quirks mode
+const QUIRKS_PUBLIC_PREFIXES: [&str; 55] = [
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+// Public identifiers that trigger quirks mode
+const QUIRKS_PUBLIC_IDS: [&str; 3] = [
+    "-//w3o//dtd w3 html strict 3.0//en//",
+    "-/w3c/dtd html 4.0 transitional/en",
+    "html",
+];
+
+const HTML401_PUBLIC_PREFIXES: [&str; 2] = [
+    "-//w3c//dtd html 4.01 frameset//",
+    "-//w3c//dtd html 4.01 transitional//",
+];
+
+const XHTML10_PUBLIC_PREFIXES: [&str; 2] = [
+    "-//w3c//dtd xhtml 1.0 frameset//",
+    "-//w3c//dtd xhtml 1.0 transitional//",
+];
+
+/// Doctype representing a ```<!DOCTYPE>``` declaration.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Doctype {
+    name: Option<String>,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    force_quirks: bool,
+}
+
+// Skip whitespace and return the new position
+fn skip_whitespace(data: &[u8], mut pos: usize) -> usize {
+    while pos < data.len() && (data[pos] as char).is_ascii_whitespace() {
+        pos += 1;
+    }
+    pos
+}
+
+// Read a quoted identifier at pos, returns identifier and new position
+fn read_identifier(data: &[u8], pos: usize) -> Option<(String, usize)> {
+    match data.get(pos) {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            let start = pos + 1;
+            data[start..].iter().position(|&b| b == quote).map(|length| {
+                let end = start + length;
+                (String::from_utf8_lossy(&data[start..end]).into_owned(), end + 1)
+            })
+        },
+        _ => None,
+    }
+}
+
+impl Doctype {
+    /// Create new doctype, eg. ```Doctype::new("html", None, None)```
+    pub fn new(name: &str, public_id: Option<&str>, system_id: Option<&str>) -> Doctype {
+        Doctype {
+            name: Some(name.to_string()),
+            public_id: public_id.map(|x| x.to_string()),
+            system_id: system_id.map(|x| x.to_string()),
+            force_quirks: false,
+        }
+    }
+
+    /// Create doctype from the parts read by a tokenizer
+    pub fn from_parts(name: Option<String>, public_id: Option<String>, system_id: Option<String>, force_quirks: bool) -> Doctype {
+        Doctype {
+            name,
+            public_id,
+            system_id,
+            force_quirks,
+        }
+    }
+
+    /// Parse the content of a doctype declaration, eg. the text between
+    /// ```<!DOCTYPE``` and ```>```.
+    pub fn parse(data: &[u8]) -> Doctype {
+        let mut doctype = Doctype {
+            name: None,
+            public_id: None,
+            system_id: None,
+            force_quirks: false,
+        };
+
+        let mut pos = skip_whitespace(data, 0);
+        let start = pos;
+        while pos < data.len() && !(data[pos] as char).is_ascii_whitespace() {
+            pos += 1;
+        }
+
+        if start == pos {
+            doctype.force_quirks = true;
+            return doctype;
+        }
+
+        doctype.name = Some(String::from_utf8_lossy(&data[start..pos]).to_ascii_lowercase());
+
+        pos = skip_whitespace(data, pos);
+        if pos == data.len() {
+            return doctype;
+        }
+
+        let keyword = data[pos..].iter().take(6).cloned().collect::<Vec<u8>>();
+        if keyword.eq_ignore_ascii_case(b"public") {
+            pos = skip_whitespace(data, pos + 6);
+            match read_identifier(data, pos) {
+                Some((id, next)) => {
+                    doctype.public_id = Some(id);
+                    pos = skip_whitespace(data, next);
+                },
+                None => {
+                    doctype.force_quirks = true;
+                    return doctype;
+                }
+            }
+
+            if pos < data.len() {
+                match read_identifier(data, pos) {
+                    Some((id, _)) => doctype.system_id = Some(id),
+                    None => doctype.force_quirks = true,
+                }
+            }
+        } else if keyword.eq_ignore_ascii_case(b"system") {
+            pos = skip_whitespace(data, pos + 6);
+            match read_identifier(data, pos) {
+                Some((id, _)) => doctype.system_id = Some(id),
+                None => doctype.force_quirks = true,
+            }
+        } else {
+            doctype.force_quirks = true;
+        }
+
+        doctype
+    }
+
+    /// Get the doctype name, eg. "html"
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Get the public identifier
+    pub fn public_id(&self) -> Option<&str> {
+        self.public_id.as_deref()
+    }
+
+    /// Get the system identifier
+    pub fn system_id(&self) -> Option<&str> {
+        self.system_id.as_deref()
+    }
+
+    /// Test if the doctype was malformed and forces quirks mode
+    pub fn force_quirks(&self) -> bool {
+        self.force_quirks
+    }
+
+    /// Get the document mode that this doctype selects.
+    pub fn quirks_mode(&self) -> QuirksMode {
+        let public_id = self.public_id.as_ref().map(|x| x.to_ascii_lowercase());
+        let system_id = self.system_id.as_ref().map(|x| x.to_ascii_lowercase());
+        let public_starts_with = |prefixes: &[&str]| match public_id {
+            Some(ref id) => prefixes.iter().any(|x| id.starts_with(x)),
+            None => false,
+        };
+
+        if self.force_quirks || self.name() != Some("html") {
+            return QuirksMode::Quirks;
+        }
+
+        if let Some(ref id) = public_id {
+            if QUIRKS_PUBLIC_IDS.contains(&id.as_str()) {
+                return QuirksMode::Quirks;
+            }
+        }
+
+        if system_id.as_deref() == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd") {
+            return QuirksMode::Quirks;
+        }
+
+        if public_starts_with(&QUIRKS_PUBLIC_PREFIXES) {
+            return QuirksMode::Quirks;
+        }
+
+        if public_starts_with(&HTML401_PUBLIC_PREFIXES) {
+            return match system_id {
+                None => QuirksMode::Quirks,
+                Some(_) => QuirksMode::LimitedQuirks,
+            };
+        }
+
+        if public_starts_with(&XHTML10_PUBLIC_PREFIXES) {
+            return QuirksMode::LimitedQuirks;
+        }
+
+        QuirksMode::NoQuirks
+    }
+}
+
+impl ToHTML for Doctype {
+    fn to_html(&self) -> String {
+        format!("{}", self)
+    }
+}
+
+impl std::fmt::Display for Doctype {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("<!DOCTYPE")?;
+        if let Some(ref name) = self.name {
+            f.write_str(&format!(" {}", name))?;
+        }
+        match (self.public_id(), self.system_id()) {
+            (Some(public_id), Some(system_id)) => f.write_str(&format!(" PUBLIC \"{}\" \"{}\"", public_id, system_id))?,
+            (Some(public_id), None) => f.write_str(&format!(" PUBLIC \"{}\"", public_id))?,
+            (None, Some(system_id)) => f.write_str(&format!(" SYSTEM \"{}\"", system_id))?,
+            (None, None) => (),
+        }
+        f.write_str(">")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use doctype::{Doctype, QuirksMode};
+
+    #[test]
+    fn parse_html5_doctype() {
+        let d = Doctype::parse(b" HTML");
+        assert_eq!(d.name(), Some("html"));
+        assert_eq!(d.public_id(), None);
+        assert_eq!(d.system_id(), None);
+        assert_eq!(d.quirks_mode(), QuirksMode::NoQuirks);
+        assert_eq!(d.to_string(), "<!DOCTYPE html>");
+    }
+
+    #[test]
+    fn parse_public_doctype() {
+        let d = Doctype::parse(b" html PUBLIC \"-//W3C//DTD HTML 4.01//EN\"\n \"http://www.w3.org/TR/html4/strict.dtd\"");
+        assert_eq!(d.public_id(), Some("-//W3C//DTD HTML 4.01//EN"));
+        assert_eq!(d.system_id(), Some("http://www.w3.org/TR/html4/strict.dtd"));
+        assert_eq!(d.quirks_mode(), QuirksMode::NoQuirks);
+        assert_eq!(d.to_string(), "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \"http://www.w3.org/TR/html4/strict.dtd\">");
+    }
+
+    #[test]
+    fn parse_system_doctype() {
+        let d = Doctype::parse(b" html SYSTEM 'about:legacy-compat'");
+        assert_eq!(d.system_id(), Some("about:legacy-compat"));
+        assert_eq!(d.quirks_mode(), QuirksMode::NoQuirks);
+    }
+
+    #[test]
+    fn quirks_mode_from_doctype() {
+        assert_eq!(Doctype::parse(b"").quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(Doctype::parse(b" svg").quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(Doctype::parse(b" html bogus").quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(Doctype::parse(b" html PUBLIC \"-//W3C//DTD HTML 3.2 Final//EN\"").quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(Doctype::parse(b" html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\"").quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(Doctype::parse(b" html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\"").quirks_mode(), QuirksMode::LimitedQuirks);
+        assert_eq!(Doctype::parse(b" html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd\"").quirks_mode(), QuirksMode::LimitedQuirks);
+    }
+}
@@ -1,18 +1,34 @@
 use std;
 use traits::{ToHTML};
 use tag::{Tag};
+use namespace::{Namespace};
 use attribute::{Attribute};
-use parser::{IsParser, Parser};
+use atom::{Atom, Atoms};
+use doctype::{Doctype, QuirksMode};
+use position::{Span};
+use parser::{IsParser, Flow, Parser, ParserOptions, TokenizerMode};
+use registry::{TagRegistry};
+use treebuilder::{TreeBuilder};
+use escape::{escape_text};
+use error::{Error, ParseError};
+use encoding_rs::{Encoding, UTF_8};
+#[cfg(feature = "tokio")]
+use std::future::{Future, poll_fn};
+#[cfg(feature = "tokio")]
+use std::task::Poll;
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncBufRead;
 
 /// Id for node references between nodes
 pub type NodeId = usize;
 
 
-const ROOT_NODE_ID: NodeId = 0;
+pub const ROOT_NODE_ID: NodeId = 0;
 
 pub struct NodeElement {
     tag: Tag,
-    attributes: Vec<Attribute>
+    attributes: Vec<Attribute>,
+    spelling: Option<Atom>,
 }
 
 impl NodeElement {
@@ -20,29 +36,50 @@ impl NodeElement {
         &self.tag
     }
 
+    /// Get the name of the element as written in the document, which
+    /// differs from the name of the tag only when the case is
+    /// preserved, eg. ```BR```.
+    pub fn name(&self) -> &str {
+        match self.spelling {
+            Some(ref x) => x.as_str(),
+            None => self.tag.as_str(),
+        }
+    }
+
     pub fn attributes(&self) -> &Vec<Attribute> {
         &self.attributes
     }
 }
 
-impl ToHTML for NodeElement {
-    fn to_html(&self) -> String {
+impl NodeElement {
+    // Write the start tag, a foreign element without children is
+    // written as self-closing tag, eg. <circle/>
+    fn _to_html(&self, self_closing: bool) -> String {
         let mut html: String = "".to_owned();
         html.push_str("<");
-        html.push_str(&self.tag().to_string());
+        html.push_str(self.name());
         for attr in self.attributes().iter() {
             html.push_str(" ");
             html.push_str(&attr.to_html());
         }
-        html.push_str(">");
+        match self_closing {
+            true => html.push_str("/>"),
+            false => html.push('>'),
+        }
         html
     }
 }
 
+impl ToHTML for NodeElement {
+    fn to_html(&self) -> String {
+        self._to_html(false)
+    }
+}
+
 impl std::fmt::Display for NodeElement {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.write_str("<").unwrap();
-        f.write_str(&format!("{}",self.tag)).unwrap();
+        f.write_str(self.name()).unwrap();
 
         let mut av: Vec<String> = Vec::new();
         for ref attr in self.attributes.clone() {
@@ -60,13 +97,21 @@ impl std::fmt::Display for NodeElement {
 pub enum NodeData {
     Element(NodeElement),
     Data(String),
+    Comment(String),
+    Doctype(Doctype),
+    CData(String),
+    ProcessingInstruction(String),
 }
 
 impl ToHTML for NodeData {
     fn to_html(&self) -> String {
         (match self {
-            &NodeData::Element(ref x) => x.to_html(),
-            &NodeData::Data(ref x) => x.to_string()
+            NodeData::Element(x) => x.to_html(),
+            NodeData::Data(x) => escape_text(x),
+            NodeData::Comment(x) => format!("<!--{}-->", x),
+            NodeData::Doctype(x) => x.to_html(),
+            NodeData::CData(x) => format!("<![CDATA[{}]]>", x),
+            NodeData::ProcessingInstruction(x) => format!("<?{}?>", x)
         }).to_string()
     }
 }
@@ -74,8 +119,12 @@ impl ToHTML for NodeData {
 impl std::fmt::Display for NodeData {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            &NodeData::Element(ref x) => f.write_str(&format!("{}", x)),
-            &NodeData::Data(ref x) => f.write_str(x)
+            NodeData::Element(x) => f.write_str(&format!("{}", x)),
+            NodeData::Data(x) => f.write_str(x),
+            NodeData::Comment(x) => f.write_str(&format!("<!--{}-->", x)),
+            NodeData::Doctype(x) => f.write_str(&format!("{}", x)),
+            NodeData::CData(x) => f.write_str(&format!("<![CDATA[{}]]>", x)),
+            NodeData::ProcessingInstruction(x) => f.write_str(&format!("<?{}?>", x))
         }
     }
 }
@@ -86,6 +135,7 @@ pub struct Node {
     parent: Option<NodeId>,
     children: Vec<NodeId>,
     data: Option<NodeData>,
+    span: Span,
 }
 
 impl Node {
@@ -99,7 +149,9 @@ impl Node {
             data: Some(NodeData::Element(NodeElement{
                 tag: tag,
                 attributes: attributes,
+                spelling: None,
             })),
+            span: Span::default(),
         }
     }
 
@@ -110,40 +162,118 @@ impl Node {
             parent: None,
             children: Vec::new(),
             data: Some(NodeData::Data(data)),
+            span: Span::default(),
+        }
+    }
+
+    /// Create a new comment node
+    pub fn new_comment(comment: String) -> Node {
+        Node {
+            id: 0,
+            parent: None,
+            children: Vec::new(),
+            data: Some(NodeData::Comment(comment)),
+            span: Span::default(),
+        }
+    }
+
+    /// Create a new doctype node
+    pub fn new_doctype(doctype: Doctype) -> Node {
+        Node {
+            id: 0,
+            parent: None,
+            children: Vec::new(),
+            data: Some(NodeData::Doctype(doctype)),
+            span: Span::default(),
+        }
+    }
+
+    /// Create a new CDATA section node
+    pub fn new_cdata(data: String) -> Node {
+        Node {
+            id: 0,
+            parent: None,
+            children: Vec::new(),
+            data: Some(NodeData::CData(data)),
+            span: Span::default(),
+        }
+    }
+
+    /// Create a new processing instruction node
+    pub fn new_processing_instruction(instruction: String) -> Node {
+        Node {
+            id: 0,
+            parent: None,
+            children: Vec::new(),
+            data: Some(NodeData::ProcessingInstruction(instruction)),
+            span: Span::default(),
         }
     }
 
     /// Test if node is an element.
     pub fn is_element(&self) -> bool {
-        match self.data.as_ref().unwrap() {
-            &NodeData::Element(_) => true,
-            _ => false
-        }
+        matches!(self.data, Some(NodeData::Element(_)))
     }
 
     /// Test if node is data.
     pub fn is_data(&self) -> bool {
-        match self.data.as_ref().unwrap() {
-            &NodeData::Data(_) => true,
-            _ => false
-        }
+        matches!(self.data, Some(NodeData::Data(_)))
+    }
+
+    /// Test if node is a comment.
+    pub fn is_comment(&self) -> bool {
+        matches!(self.data, Some(NodeData::Comment(_)))
+    }
+
+    /// Test if node is a doctype.
+    pub fn is_doctype(&self) -> bool {
+        matches!(self.data, Some(NodeData::Doctype(_)))
     }
 
     pub fn element(&self) -> Option<&NodeElement> {
-        match self.data.as_ref().unwrap() {
-            &NodeData::Element(ref x) => Some(x),
+        match self.data {
+            Some(NodeData::Element(ref x)) => Some(x),
             _ => None
         }
     }
 
-    pub fn data(&self) -> &NodeData {
-        self.data.as_ref().unwrap()
+    /// Get data of node, fails for the root node which has no data.
+    pub fn data(&self) -> Result<&NodeData, Error> {
+        match self.data {
+            Some(ref x) => Ok(x),
+            None => Err(Error::Structure(format!("node({}) has no data", self.id))),
+        }
+    }
+
+    /// Get span of the node in the source document, for elements
+    /// this is the span of the start tag.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Get id of the parent node, the root node has no parent.
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    /// Get ids of the child nodes in document order.
+    pub fn children(&self) -> &Vec<NodeId> {
+        &self.children
     }
 }
 
+// Test if node is written as self-closing tag, a SVG or MathML element
+// without children
+fn is_self_closing(node: &Node) -> bool {
+    node.children.is_empty() && node.element().is_some_and(|x| x.tag().namespace() != Namespace::Html)
+}
+
 /// Store used for allocation
-struct Store {
-    nodes: Vec<Option<Node>>
+pub struct Store {
+    nodes: Vec<Option<Node>>,
+    max_nodes: usize,
+    full: bool,
+    atoms: Atoms,
 }
 
 impl std::ops::Index<usize> for Store {
@@ -160,44 +290,195 @@ impl std::ops::IndexMut<usize> for Store {
 }
 
 impl Store {
-    pub fn new() -> Store {
+    /// Create store allocating at most max_nodes nodes besides the root
+    pub fn new(max_nodes: usize) -> Store {
         Store {
             nodes: vec!(Some(Node{
                 id: 0,
                 parent: None,
                 children: Vec::new(),
-                data: None
-            }))
+                data: None,
+                span: Span::default(),
+            })),
+            max_nodes,
+            full: false,
+            atoms: Atoms::new(),
         }
     }
 
+    /// Get the table of the tag and attribute names of the elements,
+    /// each name is stored once
+    pub fn atoms(&mut self) -> &mut Atoms {
+        &mut self.atoms
+    }
+
+    // Share the names of an element added with the other elements
+    fn _intern(self: &mut Store, data: &mut NodeData) {
+        if let NodeData::Element(ref mut x) = *data {
+            self.atoms.element(&mut x.tag, &mut x.attributes);
+        }
+    }
+
+    /// Test if a node was refused because the store allocated the most
+    /// nodes allowed
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    // Fail if the store allocated the most nodes allowed
+    fn _reserve(self: &mut Store) -> Result<(), Error> {
+        if self.nodes.len() > self.max_nodes {
+            self.full = true;
+            return Err(self._limit_error());
+        }
+        Ok(())
+    }
+
+    fn _limit_error(&self) -> Error {
+        Error::Limit(format!("more than {} nodes", self.max_nodes))
+    }
+
     /// Add node to store and return NodeId
-    pub fn add(self: &mut Store, node: Node) -> Result<NodeId, ()> {
+    pub fn add(self: &mut Store, mut node: Node) -> Result<NodeId, Error> {
 
-        let parent = node.parent.unwrap();
+        let parent = match node.parent {
+            Some(x) if self.is_node(x) => x,
+            _ => return Err(Error::Structure("node added without valid parent".to_string())),
+        };
+        self._reserve()?;
+        if let Some(ref mut x) = node.data {
+            self._intern(x);
+        }
+
+        let id = self.nodes.len();
+        node.id = id;
         self.nodes.push(Some(node));
 
-        let id = self.nodes.len() - 1;
-        self[parent].as_mut().unwrap().children.push(id);
+        if let Some(ref mut x) = self[parent] {
+            x.children.push(id);
+        }
+        Ok(id)
+    }
 
-        self.nodes[id].as_mut().unwrap().id = id;
+    /// Add node to store without parent and return NodeId
+    pub fn create(self: &mut Store, mut node: Node, span: &Span) -> Result<NodeId, Error> {
+        self._reserve()?;
+        if let Some(ref mut x) = node.data {
+            self._intern(x);
+        }
+
+        let id = self.nodes.len();
+        node.id = id;
+        node.parent = None;
+        node.span = *span;
+        self.nodes.push(Some(node));
+        Ok(id)
+    }
+
+    /// Add node to store as child of parent, before the child before
+    /// or as last child, and return NodeId
+    pub fn insert(self: &mut Store, node: Node, span: &Span, parent: NodeId, before: Option<NodeId>) -> Result<NodeId, Error> {
+        if !self.is_node(parent) {
+            return Err(Error::Structure(format!("parent node({}) does not exist", parent)));
+        }
+
+        let id = self.create(node, span)?;
+        self.attach(id, parent, before);
         Ok(id)
     }
 
+    /// Add text as child of parent, before the child before or as last
+    /// child, appending it to a data node right before it.
+    pub fn insert_text(self: &mut Store, text: &str, span: &Span, parent: NodeId, before: Option<NodeId>) -> Result<NodeId, Error> {
+        let previous = match self.get(parent) {
+            Some(x) => {
+                let index = before.and_then(|b| x.children.iter().position(|&c| c == b)).unwrap_or(x.children.len());
+                match index {
+                    0 => None,
+                    _ => Some(x.children[index - 1]),
+                }
+            },
+            None => return Err(Error::Structure(format!("parent node({}) does not exist", parent))),
+        };
+
+        if let Some(id) = previous {
+            if let Some(Node{data: Some(NodeData::Data(ref mut x)), span: ref mut s, ..}) = self[id] {
+                x.push_str(text);
+                s.end = span.end;
+                return Ok(id);
+            }
+        }
+        self.insert(Node::new_data(text.to_string()), span, parent, before)
+    }
+
+    /// Move node with its children to parent, before the child before
+    /// or as last child.
+    pub fn attach(self: &mut Store, id: NodeId, parent: NodeId, before: Option<NodeId>) {
+        if !self.is_node(id) || !self.is_node(parent) {
+            return;
+        }
+
+        self.detach(id);
+        if let Some(ref mut x) = self[parent] {
+            let index = before.and_then(|b| x.children.iter().position(|&c| c == b)).unwrap_or(x.children.len());
+            x.children.insert(index, id);
+        }
+        if let Some(ref mut x) = self[id] {
+            x.parent = Some(parent);
+        }
+    }
+
+    /// Remove node with its children from its parent, the node is kept
+    /// in store.
+    pub fn detach(self: &mut Store, id: NodeId) {
+        let parent = match self.get(id) {
+            Some(x) => x.parent,
+            None => return,
+        };
+
+        if let Some(parent) = parent {
+            if let Some(ref mut x) = self[parent] {
+                x.children.retain(|&c| c != id);
+            }
+        }
+        if let Some(ref mut x) = self[id] {
+            x.parent = None;
+        }
+    }
+
+    /// Add the attributes an element does not have yet
+    pub fn merge_attributes(self: &mut Store, id: NodeId, attributes: &[Attribute]) {
+        if !self.is_node(id) {
+            return;
+        }
+
+        if let Some(Node{data: Some(NodeData::Element(ref mut x)), ..}) = self[id] {
+            for attribute in attributes.iter() {
+                if !x.attributes.iter().any(|a| a.name == attribute.name) {
+                    x.attributes.push(attribute.clone());
+                }
+            }
+        }
+    }
+
+    /// Get node by id
+    pub fn get(self: &Store, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id).and_then(|x| x.as_ref())
+    }
+
     pub fn is_node(self: &Store, id: NodeId) -> bool {
-        if id <= self.nodes.len() - 1 && self.nodes[id].is_some() {
+        if id < self.nodes.len() && self.nodes[id].is_some() {
             return true;
         }
         return false;
     }
 
     /// Create a new node with parent and return NodeId
-    pub fn new_node_with_parent(self: &mut Store, parent: NodeId) -> Result<NodeId, ()> {
+    pub fn new_node_with_parent(self: &mut Store, parent: NodeId) -> Result<NodeId, Error> {
 
         // validate parent
         if !self.is_node(parent) {
-            // Invalid parent
-            return Err(());
+            return Err(Error::Structure(format!("parent node({}) does not exist", parent)));
         }
 
         // create and add new node returning new NodeId
@@ -205,22 +486,26 @@ impl Store {
             id: 0,
             parent: Some(parent),
             children: Vec::new(),
-            data: None
+            data: None,
+            span: Span::default(),
         })
     }
 
-    fn _recurse<F>(self: &Store, id: NodeId, level: usize, enter: &mut F)
+    // Walk the tree below id depth first, with a stack instead of
+    // recursion so deep trees do not overflow the call stack
+    fn _recurse<F>(self: &Store, id: NodeId, enter: &mut F)
         where
         F: FnMut(NodeId, usize),
     {
-        match self.nodes[id] {
-            Some(ref x) => {
-                for cid in x.children.iter() {
-                    enter(*cid, level);
-                    self._recurse(*cid, level + 1, enter);
-                }
+        let mut stack: Vec<(NodeId, usize)> = Vec::new();
+        if let Some(ref x) = self.nodes[id] {
+            stack.extend(x.children.iter().rev().map(|&cid| (cid, 0)));
+        }
+        while let Some((id, level)) = stack.pop() {
+            enter(id, level);
+            if let Some(ref x) = self.nodes[id] {
+                stack.extend(x.children.iter().rev().map(|&cid| (cid, level + 1)));
             }
-            None => ()
         }
     }
 
@@ -229,35 +514,33 @@ impl Store {
         F1: FnMut(&Node, &mut String),
         F2: FnMut(&Node, &mut String),
     {
-        match self.nodes[id] {
-            Some(ref x) => {
-                for cid in x.children.iter() {
-                    let node = self.nodes[*cid].as_ref().unwrap();
-                    enter(node, output);
-                    self._recurse_with_output(*cid, enter, leave, output);
-                    leave(node, output);
-                }
+        // node and index of the next child to enter
+        let mut stack: Vec<(NodeId, usize)> = vec![(id, 0)];
+        while let Some((pid, index)) = stack.pop() {
+            let parent = match self.nodes[pid] {
+                Some(ref x) => x,
+                None => continue,
+            };
+            match parent.children.get(index) {
+                Some(&cid) => {
+                    stack.push((pid, index + 1));
+                    enter(self.nodes[cid].as_ref().unwrap(), output);
+                    stack.push((cid, 0));
+                },
+                None if pid != id => leave(parent, output),
+                None => (),
             }
-            None => ()
         }
     }
 
     fn _recurse_remove_node(&self, id: NodeId, nodes: &mut Vec<NodeId>)
     {
-
-        match self.nodes[id] {
-            None => (),
-            Some(ref x) => {
-                // recurse to leaf and then remove nodes back to top
-                {
-                    for cid in x.children.iter() {
-                        self._recurse_remove_node(*cid, nodes);
-                    }
-                }
-            }
-        };
-
+        // nodes are removed from the leaves back to the top, which is
+        // the reverse of the order they are entered
+        let start = nodes.len();
         nodes.push(id);
+        self._recurse(id, &mut |cid, _| nodes.push(cid));
+        nodes[start..].reverse();
     }
 
     // Get nodes that are not none in storage
@@ -283,9 +566,12 @@ impl Store {
         self._recurse_remove_node(id, &mut nodes);
 
         for nid in nodes.iter() {
-            {
-                let parent_id = { self.nodes[*nid].as_mut().unwrap().parent.unwrap() };
-                let parent = self[parent_id].as_mut().unwrap();
+            // the root node has no parent and is never removed
+            let parent_id = match self.nodes[*nid] {
+                Some(Node{parent: Some(x), ..}) => x,
+                _ => continue,
+            };
+            if let Some(ref mut parent) = self[parent_id] {
                 parent.children.retain(|&x| x != *nid);
             }
             self[*nid] = None;
@@ -297,7 +583,7 @@ impl Store {
         F: FnMut(NodeId, usize),
     {
         match self.is_node(id) {
-            true => self._recurse(id, 0, &mut enter),
+            true => self._recurse(id, &mut enter),
             false => (),
         }
     }
@@ -326,15 +612,27 @@ impl ToHTML for Store {
     fn to_html(&self) -> String {
         let mut html = "".to_string().to_owned();
         self._recurse_with_output(ROOT_NODE_ID,&mut |node, output|{
-            output.push_str(node.data().to_html().as_str());
+            let raw = match self.nodes[node.parent.unwrap()].as_ref().unwrap().data {
+                Some(NodeData::Element(ref parent)) => node.is_data() && parent.tag().is_raw_text(),
+                _ => false,
+            };
+
+            match (raw, node.data.as_ref()) {
+                (true, Some(x)) => output.push_str(&x.to_string()),
+                (false, Some(x)) => match *x {
+                    NodeData::Element(ref element) => output.push_str(&element._to_html(is_self_closing(node))),
+                    _ => output.push_str(x.to_html().as_str()),
+                },
+                (_, None) => (),
+            }
         },&mut |node, output|{
-            match node.is_element() {
-                true => {
+            match node.element() {
+                Some(x) if !x.tag().is_void() && !is_self_closing(node) => {
                     output.push_str("</");
-                    output.push_str(node.element().as_ref().unwrap().tag().to_string().as_str());
+                    output.push_str(x.name());
                     output.push_str(">");
                 },
-                false => (),
+                _ => (),
             }
         }, &mut html);
 
@@ -365,6 +663,109 @@ impl ToHTML for Store {
     };
 }
 
+/// Algorithm used by [Dom](struct.Dom.html) to build the tree, see
+/// [DomOptions](struct.DomOptions.html).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TreeBuilderMode {
+    /// The original domx tree builder, which keeps the document as
+    /// written and closes the current element on any end tag.
+    Legacy,
+
+    /// Tree construction of the HTML Living Standard, which builds the
+    /// same tree as a browser does, eg. with implied ```<html>```,
+    /// ```<head>``` and ```<body>``` elements, misnested formatting
+    /// elements repaired and text moved out of tables.
+    Whatwg,
+}
+
+/// Options controlling how [Dom](struct.Dom.html) builds the tree.
+///
+/// # Examples
+///
+/// ```
+/// use domx::{Dom, DomOptions, TreeBuilderMode, ToHTML};
+///
+/// let mut dom = Dom::with_options(&DomOptions { tree_builder: TreeBuilderMode::Whatwg, ..Default::default() });
+/// dom.parse(&mut "<p>a<div>b</p>".as_bytes()).unwrap();
+/// assert_eq!(dom.to_html(), "<html><head></head><body><p>a</p><div>b<p></p></div></body></html>");
+/// ```
+///
+/// Bound the size of the tree built from an untrusted document:
+///
+/// ```
+/// use domx::{Dom, DomOptions, Error, ToHTML};
+///
+/// let options = DomOptions { max_depth: 2, max_nodes: 4, ..Default::default() };
+/// let mut dom = Dom::with_options(&options);
+/// dom.parse(&mut "<div><div><div>a</div></div></div>".as_bytes()).unwrap();
+/// assert_eq!(dom.to_html(), "<div><div><div></div>a</div></div>");
+///
+/// let mut dom = Dom::with_options(&options);
+/// match dom.parse(&mut "<p>a</p><p>b</p><p>c</p>".as_bytes()) {
+///     Err(Error::Limit(_)) => assert_eq!(dom.len(), 4),
+///     x => panic!("unexpected result {:?}", x),
+/// }
+/// ```
+///
+/// Build only the head of a document:
+///
+/// ```
+/// use domx::{Dom, DomOptions, Tag, ToHTML};
+///
+/// let data = "<html><head><title>Hello</title></head><body><p>...</p></body></html>";
+/// let mut dom = Dom::with_options(&DomOptions { stop_after: Some(Tag::HEAD), ..Default::default() });
+/// dom.parse(&mut data.as_bytes()).unwrap();
+/// assert_eq!(dom.to_html(), "<html><head><title>Hello</title></head></html>");
+/// ```
+#[derive(Clone, Debug)]
+pub struct DomOptions {
+    /// Algorithm used to build the tree, the legacy tree builder by
+    /// default. The standard tree builder expects the document to be
+    /// read by the standard tokenizer, which [Dom::parse] selects.
+    ///
+    /// [Dom::parse]: struct.Dom.html#method.parse
+    pub tree_builder: TreeBuilderMode,
+
+    /// Most elements open at once, unlimited by default. An element
+    /// opened deeper is kept empty and its content is added to the
+    /// deepest open element instead, reported as
+    /// [ParseError::NestingTooDeep].
+    ///
+    /// [ParseError::NestingTooDeep]: enum.ParseError.html#variant.NestingTooDeep
+    pub max_depth: usize,
+
+    /// Most nodes created while building the tree, unlimited by
+    /// default. Nodes past the limit are dropped and parsing fails
+    /// with [Error::Limit].
+    ///
+    /// [Error::Limit]: enum.Error.html#variant.Limit
+    pub max_nodes: usize,
+
+    /// Stop parsing at the end tag of an element, eg. ```Tag::HEAD```
+    /// to read only the metadata of a document. The document is parsed
+    /// to the end by default.
+    pub stop_after: Option<Tag>,
+
+    /// Elements beyond HTML with their properties, eg. void custom
+    /// elements or the elements they are allowed in. They are also read
+    /// by the parser of [Dom::parse]. None by default.
+    ///
+    /// [Dom::parse]: struct.Dom.html#method.parse
+    pub tags: TagRegistry,
+}
+
+impl Default for DomOptions {
+    fn default() -> DomOptions {
+        DomOptions {
+            tree_builder: TreeBuilderMode::Legacy,
+            max_depth: usize::MAX,
+            max_nodes: usize::MAX,
+            stop_after: None,
+            tags: TagRegistry::new(),
+        }
+    }
+}
+
 /// DOM tree data structure builder.
 ///
 /// Uses [Parser] to build the tree and provides a set of methods to
@@ -376,24 +777,115 @@ impl ToHTML for Store {
 ///
 pub struct Dom {
     store: Store,
-    current: Option<NodeId>
+    current: Option<NodeId>,
+    quirks_mode: Option<QuirksMode>,
+    error: Option<Error>,
+    parse_errors: Vec<(ParseError, Span)>,
+    encoding: &'static Encoding,
+    tree: Option<TreeBuilder>,
+    max_depth: usize,
+    depth: usize,
+    overflow: usize,
+    stop_after: Option<Tag>,
+    tags: TagRegistry,
 }
 
 impl Dom {
     pub fn new() -> Dom {
+        Dom::with_options(&DomOptions::default())
+    }
+
+    /// Create a DOM using options.
+    pub fn with_options(options: &DomOptions) -> Dom {
         Dom {
-            store: Store::new(),
+            store: Store::new(options.max_nodes),
             current: None,
+            quirks_mode: None,
+            error: None,
+            parse_errors: Vec::new(),
+            encoding: UTF_8,
+            tree: match options.tree_builder {
+                TreeBuilderMode::Legacy => None,
+                TreeBuilderMode::Whatwg => Some(TreeBuilder::new(options)),
+            },
+            max_depth: options.max_depth,
+            depth: 0,
+            overflow: 0,
+            stop_after: options.stop_after.as_ref().map(|x| options.tags.resolve(x).unwrap_or(x.clone())),
+            tags: options.tags.clone(),
         }
     }
 
-    /// Parse a HTML buffer and build DOM tree structure.
+    /// Parse a HTML buffer and build DOM tree structure, returns the
+    /// number of bytes parsed as
+    /// [Parser::parse](struct.Parser.html#method.parse).
     ///
     /// Use the macro [dom!()] for easier use.
     ///
+    /// Data that is not valid UTF-8 is stored with invalid sequences
+    /// replaced by U+FFFD and reported as [Error::Encoding] once the
+    /// whole document is parsed.
+    ///
     /// [dom!()]: macro.dom.html
-    pub fn parse(self: &mut Self, source: &mut dyn std::io::BufRead) -> Result<usize, std::io::Error> {
-        Parser::parse(source, self)
+    /// [Error::Encoding]: enum.Error.html#variant.Encoding
+    pub fn parse(&mut self, source: &mut dyn std::io::BufRead) -> Result<usize, Error> {
+        let options = self._parser_options();
+        self.parse_with_options(source, &options)
+    }
+
+    /// Parse a HTML buffer using options and build DOM tree structure.
+    pub fn parse_with_options(&mut self, source: &mut dyn std::io::BufRead, options: &ParserOptions) -> Result<usize, Error> {
+        let parsed = Parser::parse_with_options(source, self, options)?;
+        match self.error.take() {
+            Some(x) => Err(x),
+            None => Ok(parsed),
+        }
+    }
+
+    /// Parse a HTML document read asynchronously and build DOM tree
+    /// structure.
+    ///
+    /// Only available with the ```tokio``` feature.
+    #[cfg(feature = "tokio")]
+    pub fn parse_async<'a, R>(&'a mut self, source: &'a mut R) -> impl Future<Output = Result<usize, Error>> + 'a
+        where R: AsyncBufRead + Unpin + ?Sized
+    {
+        let options = self._parser_options();
+        self.parse_async_with_options(source, &options)
+    }
+
+    /// Parse a HTML document read asynchronously using options and
+    /// build DOM tree structure.
+    ///
+    /// Only available with the ```tokio``` feature.
+    #[cfg(feature = "tokio")]
+    pub fn parse_async_with_options<'a, R>(&'a mut self, source: &'a mut R, options: &ParserOptions) -> impl Future<Output = Result<usize, Error>> + 'a
+        where R: AsyncBufRead + Unpin + ?Sized
+    {
+        let mut parser = Parser::with_options(options);
+        poll_fn(move |cx| match parser.poll_parse(&mut *source, &mut *self, cx) {
+            Poll::Ready(Ok(parsed)) => Poll::Ready(match self.error.take() {
+                Some(x) => Err(x),
+                None => Ok(parsed),
+            }),
+            x => x,
+        })
+    }
+
+    /// Get node by id, fails if the node does not exist.
+    pub fn node(&self, id: NodeId) -> Result<&Node, Error> {
+        match self.store.is_node(id) {
+            true => Ok(self.store[id].as_ref().unwrap()),
+            false => Err(Error::Structure(format!("node({}) does not exist", id))),
+        }
+    }
+
+    /// Get mutable node by id, fails if the node does not exist.
+    pub fn node_mut(&mut self, id: NodeId) -> Result<&mut Node, Error> {
+        match self.store.is_node(id) {
+            true => Ok(self.store[id].as_mut().unwrap()),
+            false => Err(Error::Structure(format!("node({}) does not exist", id))),
+        }
     }
 
     /// Recurse the DOM with a callback for when entering each node.
@@ -451,37 +943,298 @@ impl Dom {
     pub fn len(&self) -> usize {
         self.store.len() - 1
     }
-}
 
-impl IsParser for Dom {
-    fn handle_starttag(self: &mut Self, tag: &Tag, attributes: &Vec<Attribute>) {
+    // Add node as child of current node
+    fn _add_node(&mut self, mut data: NodeData, span: &Span) -> Option<NodeId> {
+        self.store._intern(&mut data);
         let parent = {
             match self.current {
                 Some(x) => x,
                 None => ROOT_NODE_ID
             }
         };
-        let id = self.store.new_node_with_parent(parent).unwrap();
-        self.store[id].as_mut().unwrap().data = Some(NodeData::Element(NodeElement{
+        match self.store.new_node_with_parent(parent) {
+            Ok(id) => {
+                if let Some(ref mut node) = self.store[id] {
+                    node.data = Some(data);
+                    node.span = *span;
+                }
+                Some(id)
+            },
+            Err(x) => {
+                self._set_error(x);
+                None
+            }
+        }
+    }
+
+    // Get default options of the parser for the tree builder
+    fn _parser_options(&self) -> ParserOptions {
+        let tokenizer = match self.tree {
+            Some(_) => TokenizerMode::Whatwg,
+            None => TokenizerMode::Legacy,
+        };
+        ParserOptions { tokenizer, tags: self.tags.clone(), ..Default::default() }
+    }
+
+    // Close the elements open in the nearest element a custom element
+    // is allowed in, the element stays open if none is
+    fn _close_to_parent(&mut self, tag: &Tag, span: &Span) {
+        let custom = match *tag {
+            Tag::Custom(ref x) if self.overflow == 0 => x,
+            _ => return,
+        };
+
+        let mut parent = self.current;
+        let mut closed = 0;
+        while let Some(id) = parent {
+            match self.store[id].as_ref() {
+                Some(node) if node.element().is_some_and(|x| custom.is_allowed_in(x.tag().as_str())) => break,
+                Some(node) => parent = node.parent,
+                None => return,
+            }
+            closed += 1;
+        }
+        if parent.is_some() && closed > 0 {
+            self.current = parent;
+            self.depth = self.depth.saturating_sub(closed);
+            self.handle_error(ParseError::UnexpectedStartTag, span);
+        }
+    }
+
+    // Keep the first error encountered while building the tree
+    fn _set_error(&mut self, error: Error) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    // Keep the error of the tree builder adding more nodes than allowed
+    fn _check_store(&mut self) {
+        if self.store.is_full() {
+            let error = self.store._limit_error();
+            self._set_error(error);
+        }
+    }
+
+    // Get how parsing goes on, nothing more is built once the store is
+    // full
+    fn _flow(&self) -> Flow {
+        match self.store.is_full() {
+            true => Flow::Stop,
+            false => Flow::Continue,
+        }
+    }
+
+    // Convert data to string, invalid sequences are replaced and the
+    // error is kept.
+    fn _to_string(&mut self, data: &[u8]) -> String {
+        match std::str::from_utf8(data) {
+            Ok(x) => x.to_string(),
+            Err(x) => {
+                self._set_error(Error::Encoding(x));
+                String::from_utf8_lossy(data).into_owned()
+            }
+        }
+    }
+
+    /// Get the character encoding the document was decoded from.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Get the recoverable errors found while parsing the document,
+    /// in document order.
+    pub fn parse_errors(&self) -> &Vec<(ParseError, Span)> {
+        &self.parse_errors
+    }
+
+    /// Get the document mode selected by the first doctype of the
+    /// document. A document without doctype is in quirks mode.
+    pub fn quirks_mode(&self) -> QuirksMode {
+        match self.tree {
+            Some(ref x) => x.quirks_mode(),
+            None => self.quirks_mode.unwrap_or(QuirksMode::Quirks),
+        }
+    }
+}
+
+impl IsParser for Dom {
+    fn handle_starttag(&mut self, tag: &Tag, attributes: &Vec<Attribute>, self_closing: bool, span: &Span) -> Flow {
+        let custom = self.tags.resolve(tag);
+        match custom {
+            // A registered tag written in another case, the name is kept
+            // as written when the case is preserved
+            Some(ref x) if x.as_str() != tag.as_str() => {
+                return self.handle_spelled_starttag(x, tag.as_str(), attributes, self_closing, span);
+            },
+            _ => (),
+        }
+        let tag = custom.as_ref().unwrap_or(tag);
+        if let Some(ref mut tree) = self.tree {
+            tree.start_tag(&mut self.store, tag, attributes, self_closing, span);
+            self.parse_errors.append(&mut tree.errors);
+            self._check_store();
+            return self._flow();
+        }
+
+        self._close_to_parent(tag, span);
+        let id = self._add_node(NodeData::Element(NodeElement{
             tag: tag.clone(),
             attributes: attributes.clone(),
-        }));
-        self.current = Some(id);
+            spelling: None,
+        }), span);
+        // Void elements and self-closing foreign elements never have
+        // children
+        if id.is_none() || tag.is_void() || (self_closing && tag.namespace() != Namespace::Html) {
+            return self._flow();
+        }
+        if self.depth >= self.max_depth {
+            self.overflow += 1;
+            self.handle_error(ParseError::NestingTooDeep, span);
+            return Flow::Continue;
+        }
+        self.current = id;
+        self.depth += 1;
+        Flow::Continue
     }
 
-    fn handle_endtag(self: &mut Self, _tag: &Tag) {
-        self.current = self.store[self.current.unwrap()].as_ref().unwrap().parent;
+    fn handle_spelled_starttag(&mut self, tag: &Tag, spelling: &str, attributes: &Vec<Attribute>, self_closing: bool, span: &Span) -> Flow {
+        // The element of the tag is the one added with the span of the
+        // tag, the tree builder may add others for implied tags
+        let first = self.store.nodes.len();
+        let flow = self.handle_starttag(tag, attributes, self_closing, span);
+        let spelling = self.store.atoms().intern(spelling);
+        for id in first..self.store.nodes.len() {
+            if let Some(Node{data: Some(NodeData::Element(ref mut x)), span: ref s, ..}) = self.store[id] {
+                if s == span && spelling.eq_ignore_ascii_case(x.tag.as_str()) {
+                    x.spelling = Some(spelling.clone());
+                }
+            }
+        }
+        flow
     }
 
-    fn handle_data(self: &mut Self, data: &Vec<u8>) {
-        let parent = {
-            match self.current {
-                Some(x) => x,
-                None => ROOT_NODE_ID
+    fn handle_endtag(&mut self, tag: &Tag, span: &Span) -> Flow {
+        let custom = self.tags.resolve(tag);
+        let tag = custom.as_ref().unwrap_or(tag);
+        let flow = match self.stop_after {
+            Some(ref x) if x == tag => Flow::Stop,
+            _ => Flow::Continue,
+        };
+
+        if let Some(ref mut tree) = self.tree {
+            tree.end_tag(&mut self.store, tag, span);
+            self.parse_errors.append(&mut tree.errors);
+            self._check_store();
+            return match flow {
+                Flow::Stop => flow,
+                _ => self._flow(),
+            };
+        }
+
+        // An end tag of a void element or without open element is ignored
+        if tag.is_void() {
+            self.handle_error(ParseError::UnexpectedEndTag, span);
+            return flow;
+        }
+
+        // The end tag of an element opened too deep closes nothing
+        if self.overflow > 0 {
+            self.overflow -= 1;
+            return flow;
+        }
+
+        self.current = match self.current {
+            Some(x) => {
+                self.depth = self.depth.saturating_sub(1);
+                self.store[x].as_ref().and_then(|node| node.parent)
+            },
+            None => {
+                self.handle_error(ParseError::UnexpectedEndTag, span);
+                None
             }
         };
-        let id = self.store.new_node_with_parent(parent).unwrap();
-        self.store[id].as_mut().unwrap().data = Some(NodeData::Data(String::from_utf8(data.clone()).unwrap()));
+        flow
+    }
+
+    fn handle_data(&mut self, data: &Vec<u8>, span: &Span) -> Flow {
+        let data = self._to_string(data);
+        if let Some(ref mut tree) = self.tree {
+            tree.text(&mut self.store, &data, span);
+            self.parse_errors.append(&mut tree.errors);
+            self._check_store();
+            return self._flow();
+        }
+        self._add_node(NodeData::Data(data), span);
+        self._flow()
+    }
+
+    fn handle_comment(&mut self, comment: &Vec<u8>, span: &Span) -> Flow {
+        let comment = self._to_string(comment);
+        if let Some(ref mut tree) = self.tree {
+            tree.comment(&mut self.store, &comment, span);
+            self.parse_errors.append(&mut tree.errors);
+            self._check_store();
+            return self._flow();
+        }
+        self._add_node(NodeData::Comment(comment), span);
+        self._flow()
+    }
+
+    fn handle_doctype(&mut self, doctype: &Doctype, span: &Span) -> Flow {
+        if let Some(ref mut tree) = self.tree {
+            tree.doctype(&mut self.store, doctype, span);
+            self.parse_errors.append(&mut tree.errors);
+            self._check_store();
+            return self._flow();
+        }
+
+        if self.quirks_mode.is_none() {
+            self.quirks_mode = Some(doctype.quirks_mode());
+        }
+        self._add_node(NodeData::Doctype(doctype.clone()), span);
+        self._flow()
+    }
+
+    fn handle_cdata(&mut self, data: &Vec<u8>, span: &Span) -> Flow {
+        let data = self._to_string(data);
+        if let Some(ref mut tree) = self.tree {
+            tree.text(&mut self.store, &data, span);
+            self.parse_errors.append(&mut tree.errors);
+            self._check_store();
+            return self._flow();
+        }
+        self._add_node(NodeData::CData(data), span);
+        self._flow()
+    }
+
+    fn handle_processing_instruction(&mut self, instruction: &Vec<u8>, span: &Span) -> Flow {
+        let instruction = self._to_string(instruction);
+        if let Some(ref mut tree) = self.tree {
+            tree.processing_instruction(&mut self.store, &instruction, span);
+            self.parse_errors.append(&mut tree.errors);
+            self._check_store();
+            return self._flow();
+        }
+        self._add_node(NodeData::ProcessingInstruction(instruction), span);
+        self._flow()
+    }
+
+    fn handle_error(&mut self, error: ParseError, span: &Span) -> Flow {
+        self.parse_errors.push((error, *span));
+        Flow::Continue
+    }
+
+    fn handle_encoding(&mut self, encoding: &'static Encoding) {
+        self.encoding = encoding;
+    }
+
+    fn handle_eof(&mut self) {
+        if let Some(ref mut tree) = self.tree {
+            tree.eof(&mut self.store);
+            self.parse_errors.append(&mut tree.errors);
+        }
     }
 }
 
@@ -490,12 +1243,16 @@ impl std::fmt::Display for Dom {
         self.recurse(|id, level| {
             let li = vec![0; level];
             let indent = li.iter().fold("".to_string(), |acc, _| acc + "  ");
-            match self.store[id].as_ref().unwrap().data {
+            match self.store[id].as_ref().and_then(|node| node.data.as_ref()) {
                 None => (),
-                Some(ref x) => {
+                Some(x) => {
                     match x {
-                        &NodeData::Element(ref x) => f.write_str(&format!("{}node({}) element: {}\n", indent, id, x)).unwrap(),
-                        &NodeData::Data(ref x)  => f.write_str(&format!("{}node({}) data: {:?}\n", indent, id, x)).unwrap(),
+                        NodeData::Element(x) => f.write_str(&format!("{}node({}) element: {}\n", indent, id, x)).unwrap(),
+                        NodeData::Data(x)  => f.write_str(&format!("{}node({}) data: {:?}\n", indent, id, x)).unwrap(),
+                        NodeData::Comment(x)  => f.write_str(&format!("{}node({}) comment: {:?}\n", indent, id, x)).unwrap(),
+                        NodeData::Doctype(x)  => f.write_str(&format!("{}node({}) doctype: {}\n", indent, id, x)).unwrap(),
+                        NodeData::CData(x)  => f.write_str(&format!("{}node({}) cdata: {:?}\n", indent, id, x)).unwrap(),
+                        NodeData::ProcessingInstruction(x)  => f.write_str(&format!("{}node({}) processing instruction: {:?}\n", indent, id, x)).unwrap(),
                     }
                 }
             };
@@ -505,6 +1262,14 @@ impl std::fmt::Display for Dom {
     }
 }
 
+/// Get node by id.
+///
+/// # Panics
+///
+/// Panics if the node does not exist, use [Dom::node] to get an
+/// error instead.
+///
+/// [Dom::node]: struct.Dom.html#method.node
 impl std::ops::Index<usize> for Dom {
     type Output = Node;
     fn index(&self, idx: usize) -> &Node {
@@ -529,6 +1294,7 @@ impl ToHTML for Dom {
 mod tests {
     use dom::*;
     use tag::Tag;
+    use namespace::Namespace;
     use attribute::Attribute;
     use std::io::BufReader;
 
@@ -542,8 +1308,8 @@ mod tests {
     #[test]
     fn parse_simple_document() {
         let dom = dom!("<html><body><p>Hello <b>World</b>!</p></body></html>");
-        assert_eq!(dom[3].data().to_string(), "<p>");
-        assert_eq!(dom[6].data().to_string(), "World");
+        assert_eq!(dom[3].data().unwrap().to_string(), "<p>");
+        assert_eq!(dom[6].data().unwrap().to_string(), "World");
     }
 
     #[test]
@@ -591,4 +1357,364 @@ mod tests {
 
         assert_eq!(dom.len(), 2);
     }
+
+    #[test]
+    fn dom_to_html_escapes_text_and_attributes() {
+        let dom = dom!("<p title=\"&quot;a&quot; &amp; b\">1 &lt; 2 &amp;&amp; 3 &gt; 2</p>");
+        assert_eq!(dom.to_html(), "<p title=\"&quot;a&quot; &amp; b\">1 &lt; 2 &amp;&amp; 3 &gt; 2</p>");
+    }
+
+    #[test]
+    fn dom_to_html_keeps_raw_text() {
+        let dom = dom!("<script>if (a < b && c > d) {}</script><style>a > b {}</style>");
+        assert_eq!(dom.to_html(), "<script>if (a < b && c > d) {}</script><style>a > b {}</style>");
+    }
+
+    #[test]
+    fn dom_to_html_escapes_noscript() {
+        let html = "<noscript>&lt;img src=x onerror=alert(1)&gt; &amp;</noscript>";
+        let input = format!("<body>{}", html);
+        let whatwg = ::DomOptions { tree_builder: ::TreeBuilderMode::Whatwg, ..Default::default() };
+        for options in &[::DomOptions::default(), whatwg] {
+            let mut dom = ::Dom::with_options(options);
+            dom.parse(&mut input.as_bytes()).unwrap();
+            assert!(dom.to_html().contains(html));
+        }
+    }
+
+    #[test]
+    fn dom_to_html_round_trip() {
+        let html = "<div title=\"a &lt;b&gt; &quot;c&quot;\"><p>x &amp;lt; y&nbsp;z</p><script>a<b</script></div>";
+        let first = dom!(html);
+        let second = dom!(first.to_html());
+        assert_eq!(first.to_html(), second.to_html());
+        assert_eq!(second[3].data().unwrap().to_string(), "x &lt; y\u{a0}z");
+        assert_eq!(second[1].element().unwrap().attributes()[0].value().unwrap(), "a <b> \"c\"");
+    }
+
+    #[test]
+    fn dom_keeps_comments() {
+        let html = "<!-- license header --><html><body><!--[if IE]><p>IE</p><![endif]--></body></html>";
+        let dom = dom!(html);
+        assert!(dom[1].is_comment());
+        assert_eq!(dom[1].data().unwrap().to_string(), "<!-- license header -->");
+        assert_eq!(dom.to_html(), html);
+    }
+
+    #[test]
+    fn dom_keeps_doctype() {
+        let html = "<!DOCTYPE html><html><body><p>Hello</p></body></html>";
+        let dom = dom!(html);
+        assert!(dom[1].is_doctype());
+        assert_eq!(dom.quirks_mode(), QuirksMode::NoQuirks);
+        assert_eq!(dom.to_html(), html);
+    }
+
+    #[test]
+    fn dom_quirks_mode() {
+        assert_eq!(dom!("<html></html>").quirks_mode(), QuirksMode::Quirks);
+        assert_eq!(dom!("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\"><html></html>").quirks_mode(), QuirksMode::LimitedQuirks);
+        assert_eq!(dom!("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 3.2 Final//EN\"><html></html>").quirks_mode(), QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn dom_keeps_cdata_and_processing_instructions() {
+        let html = "<?xml version=\"1.0\"?><html><body><svg><text><![CDATA[a < b]]></text></svg></body></html>";
+        let dom = dom!(html);
+        assert_eq!(dom[1].data().unwrap().to_string(), "<?xml version=\"1.0\"?>");
+        assert_eq!(dom[6].data().unwrap().to_string(), "<![CDATA[a < b]]>");
+        assert_eq!(dom.to_html(), html);
+    }
+
+    #[test]
+    fn dom_node_spans() {
+        let dom = dom!("<html>\n<p id=\"x\">Hello</p></html>");
+        assert_eq!(dom[3].span().start.line, 2);
+        assert_eq!(dom[3].span().len(), 10);
+        assert_eq!(dom[3].element().unwrap().attributes()[0].span().start.column, 4);
+        assert_eq!(dom[4].span().start.offset, 17);
+        assert_eq!(dom[4].span().len(), 5);
+    }
+
+    #[test]
+    fn stray_end_tags_are_ignored() {
+        let dom = dom!("</div><p>Hello</p></p></body></html><b>x</b>");
+        assert_eq!(dom.to_html(), "<p>Hello</p><b>x</b>");
+    }
+
+    #[test]
+    fn invalid_utf8_is_encoding_error() {
+        let mut dom = ::Dom::new();
+        let data = b"<p>a\xFFb</p>".to_vec();
+        let options = ::ParserOptions { detect_encoding: false, ..Default::default() };
+        match dom.parse_with_options(&mut BufReader::new(&data[..]), &options) {
+            Err(::Error::Encoding(_)) => (),
+            _ => panic!("expected encoding error"),
+        }
+        assert_eq!(dom.to_html(), "<p>a\u{FFFD}b</p>");
+    }
+
+    #[test]
+    fn missing_node_is_structure_error() {
+        let dom = dom!("<p>Hello</p>");
+        assert!(dom.node(2).is_ok());
+        assert!(dom.node(3).is_err());
+        assert!(dom.node(0).unwrap().data().is_err());
+    }
+
+    #[test]
+    fn dom_collects_parse_errors() {
+        let dom = dom!("</div><p id=a id=b>x</p><!-- open");
+        let errors: Vec<&str> = dom.parse_errors().iter().map(|x| x.0.code()).collect();
+        assert_eq!(errors, vec!["unexpected-end-tag", "duplicate-attribute", "eof-in-comment"]);
+        assert_eq!(dom.parse_errors()[0].1.start.offset, 0);
+        assert_eq!(dom.parse_errors()[2].1.start.offset, 33);
+    }
+
+    #[test]
+    fn dom_detects_encoding() {
+        let mut dom = ::Dom::new();
+        let data = b"<meta charset=\"iso-8859-15\"><p id=\"\xa4\">Caf\xe9</p>".to_vec();
+        dom.parse(&mut BufReader::new(&data[..])).unwrap();
+        assert_eq!(dom.encoding().name(), "ISO-8859-15");
+        assert_eq!(dom[2].element().unwrap().attributes()[0].value().unwrap(), "€");
+        assert_eq!(dom[3].data().unwrap().to_string(), "Café");
+    }
+
+    #[test]
+    fn dom_uses_transport_encoding() {
+        let mut dom = ::Dom::new();
+        let data = b"<p>\x82\xa0</p>".to_vec();
+        let options = ::ParserOptions { transport_encoding: ::Encoding::for_label(b"shift_jis"), ..Default::default() };
+        dom.parse_with_options(&mut BufReader::new(&data[..]), &options).unwrap();
+        assert_eq!(dom.encoding().name(), "Shift_JIS");
+        assert_eq!(dom.to_html(), "<p>あ</p>");
+    }
+
+    #[test]
+    fn void_elements_have_no_children() {
+        let dom = dom!("<p>a<br>b<img src=\"x\" />c</br><input></p>d");
+        assert_eq!(dom.to_html(), "<p>a<br>b<img src=\"x\">c<input></p>d");
+        assert_eq!(dom.parse_errors().len(), 1);
+        assert_eq!(dom[3].data().unwrap().to_string(), "<br>");
+        assert_eq!(dom[4].parent, Some(1));
+    }
+
+    #[test]
+    fn dom_writes_preserved_case() {
+        let html = "<svg viewBox=\"0 0 1 1\"><foreignObject><Feed xml:Lang=\"en\"></Feed></foreignObject></svg>";
+        let options = ::ParserOptions { preserve_case: true, ..Default::default() };
+        let mut dom = ::Dom::new();
+        dom.parse_with_options(&mut BufReader::new(html.as_bytes()), &options).unwrap();
+        assert_eq!(dom.to_html(), html);
+
+        let html = "<P>a<BR>b<IMG SRC=\"x\">c</P><SCRIPT>a<b</SCRIPT>";
+        for mode in [::TokenizerMode::Legacy, ::TokenizerMode::Whatwg].iter() {
+            let options = ::ParserOptions { tokenizer: *mode, preserve_case: true, ..Default::default() };
+            let mut dom = ::Dom::new();
+            dom.parse_with_options(&mut BufReader::new(html.as_bytes()), &options).unwrap();
+            assert_eq!(dom.to_html(), html);
+            assert_eq!(dom[1].element().unwrap().tag(), &Tag::P);
+            assert_eq!(dom[3].element().unwrap().name(), "BR");
+            assert_eq!(dom[8].data().unwrap().to_string(), "a<b");
+        }
+        let options = ::ParserOptions { preserve_case: true, ..Default::default() };
+        let mut dom = ::Dom::with_options(&::DomOptions { tree_builder: ::TreeBuilderMode::Whatwg, ..Default::default() });
+        dom.parse_with_options(&mut BufReader::new(html.as_bytes()), &options).unwrap();
+        assert_eq!(dom.to_html(), format!("<html><head></head><body>{}</body></html>", html));
+
+        let dom = dom!("<SVG VIEWBOX=\"0 0 1 1\"><FOREIGNOBJECT><P>x</P></FOREIGNOBJECT></SVG>");
+        assert_eq!(dom.to_html(), "<svg viewBox=\"0 0 1 1\"><foreignObject><p>x</p></foreignObject></svg>");
+    }
+
+    #[test]
+    fn dom_keeps_foreign_content() {
+        let html = "<p><svg viewBox=\"0 0 1 1\"><circle r=\"1\"/><style>a &lt; b</style><a xlink:href=\"#x\">link</a></svg>\
+                    <math><mi>x</mi></math></p>";
+        let whatwg = ::DomOptions { tree_builder: ::TreeBuilderMode::Whatwg, ..Default::default() };
+        for options in [::DomOptions::default(), whatwg].iter() {
+            let mut dom = ::Dom::with_options(options);
+            dom.parse(&mut BufReader::new(html.as_bytes())).unwrap();
+            assert!(dom.to_html().contains(html), "{}", dom.to_html());
+
+            let mut elements = Vec::new();
+            dom.recurse(|id, _| if let Some(x) = dom[id].element() {
+                elements.push((x.tag().clone(), x.attributes().iter().map(|a| a.namespace()).collect::<Vec<_>>()));
+            });
+            assert!(elements.contains(&(Tag::Svg("circle".into()), vec![None])));
+            assert!(elements.contains(&(Tag::Svg("a".into()), vec![Some(Namespace::XLink)])));
+            assert!(elements.contains(&(Tag::MathMl("mi".into()), vec![])));
+        }
+    }
+
+    #[test]
+    fn dom_stores_names_once() {
+        let whatwg = ::DomOptions { tree_builder: ::TreeBuilderMode::Whatwg, ..Default::default() };
+        for options in [::DomOptions::default(), whatwg].iter() {
+            let mut dom = ::Dom::with_options(options);
+            dom.parse(&mut BufReader::new(&b"<x-card class=\"a\"></x-card>"[..])).unwrap();
+            dom.parse(&mut BufReader::new(&b"<x-card class=\"b\"></x-card>"[..])).unwrap();
+
+            let mut elements = Vec::new();
+            dom.recurse(|id, _| if let Some(x) = dom[id].element() {
+                if let Tag::Unknown(ref name) = *x.tag() {
+                    elements.push((name.clone(), x.attributes()[0].name.clone()));
+                }
+            });
+            assert_eq!(elements.len(), 2);
+            assert!(elements[0].0.ptr_eq(&elements[1].0) && elements[0].1.ptr_eq(&elements[1].1));
+        }
+    }
+
+    #[test]
+    fn dom_uses_registered_tags() {
+        let mut tags = ::TagRegistry::new();
+        tags.register("x-icon", ::TagProperties { void: true, ..Default::default() });
+        tags.register("x-code", ::TagProperties { raw_text: true, ..Default::default() });
+        tags.register("x-card", ::TagProperties { block: true, ..Default::default() });
+        tags.register("x-item", ::TagProperties { parents: vec!["x-list".to_string()], ..Default::default() });
+        let html = "<p>a<x-card>b</x-card><x-list><x-item><x-icon>c<x-item><x-code><i>&lt;</x-code></x-list>";
+
+        let mut dom = ::Dom::with_options(&::DomOptions { tags: tags.clone(), ..Default::default() });
+        dom.parse(&mut BufReader::new(html.as_bytes())).unwrap();
+        assert_eq!(dom.to_html(), "<p>a<x-card>b</x-card><x-list><x-item><x-icon>c</x-item>\
+                                   <x-item><x-code><i>&lt;</x-code></x-item></x-list></p>");
+        assert_eq!(dom.parse_errors().len(), 1);
+
+        let whatwg = ::DomOptions { tree_builder: ::TreeBuilderMode::Whatwg, tags, ..Default::default() };
+        let mut dom = ::Dom::with_options(&whatwg);
+        dom.parse(&mut BufReader::new(html.as_bytes())).unwrap();
+        assert_eq!(dom.to_html(), "<html><head></head><body><p>a</p><x-card>b</x-card><x-list><x-item><x-icon>c</x-item>\
+                                   <x-item><x-code><i>&lt;</x-code></x-item></x-list></body></html>");
+    }
+
+    #[test]
+    fn dom_closes_registered_tags_to_parent_preserving_case() {
+        let mut tags = ::TagRegistry::new();
+        tags.register("x-item", ::TagProperties { parents: vec!["X-List".to_string()], ..Default::default() });
+        let html = "<X-List><X-Item>a<span>b<X-Item>c</X-List>";
+        let options = ::ParserOptions { preserve_case: true, ..Default::default() };
+        for mode in [::TreeBuilderMode::Legacy, ::TreeBuilderMode::Whatwg].iter() {
+            let mut dom = ::Dom::with_options(&::DomOptions { tree_builder: *mode, tags: tags.clone(), ..Default::default() });
+            dom.parse_with_options(&mut BufReader::new(html.as_bytes()), &options).unwrap();
+            assert!(dom.to_html().contains("<X-List><X-Item>a<span>b</span></X-Item><X-Item>c</X-Item></X-List>"), "{}", dom.to_html());
+        }
+    }
+
+    #[test]
+    fn dom_round_trips_tag_names() {
+        let html = "<blockquote><abbr>a</abbr><kbd>b</kbd><canvas></canvas><mark>c</mark></blockquote>";
+        assert_eq!(dom!(html).to_html(), html);
+        assert_eq!(dom!(dom!(html).to_html()).to_html(), html);
+    }
+
+    #[test]
+    fn dom_round_trips_non_ascii_tag_names() {
+        let html = "<x-caf\u{e9} data-\u{2603}=\"\u{2603}\">\u{fc}</x-caf\u{e9}>";
+        for mode in [::TokenizerMode::Legacy, ::TokenizerMode::Whatwg].iter() {
+            let options = ::ParserOptions { tokenizer: *mode, ..Default::default() };
+            let mut dom = ::Dom::new();
+            dom.parse_with_options(&mut BufReader::new(html.as_bytes()), &options).unwrap();
+            assert_eq!(dom.to_html(), html);
+            assert_eq!(dom[1].element().unwrap().tag().as_str(), "x-caf\u{e9}");
+        }
+    }
+
+    #[test]
+    fn dom_handles_deep_nesting() {
+        let html = "<div>".repeat(100000);
+        let dom = dom!(html);
+        let mut deepest = 0;
+        dom.recurse(|_, level| deepest = std::cmp::max(deepest, level));
+        assert_eq!(deepest, 99999);
+        assert_eq!(dom.to_html().len(), html.len() * 2 + 100000);
+    }
+
+    #[test]
+    fn dom_limits_depth() {
+        let html = "<div>".repeat(2000) + "x";
+        for mode in [::TreeBuilderMode::Legacy, ::TreeBuilderMode::Whatwg].iter() {
+            let mut dom = ::Dom::with_options(&::DomOptions { tree_builder: *mode, max_depth: 64, ..Default::default() });
+            dom.parse(&mut BufReader::new(html.as_bytes())).unwrap();
+            let mut deepest = 0;
+            dom.recurse(|_, level| deepest = std::cmp::max(deepest, level));
+            assert_eq!(deepest, 64);
+            assert!(dom.parse_errors().iter().any(|x| x.0 == ParseError::NestingTooDeep));
+        }
+    }
+
+    #[test]
+    fn dom_limits_nodes() {
+        let html = "<p>x</p>".repeat(1000);
+        for mode in [::TreeBuilderMode::Legacy, ::TreeBuilderMode::Whatwg].iter() {
+            let mut dom = ::Dom::with_options(&::DomOptions { tree_builder: *mode, max_nodes: 100, ..Default::default() });
+            match dom.parse(&mut BufReader::new(html.as_bytes())) {
+                Err(::Error::Limit(_)) => (),
+                x => panic!("unexpected result {:?}", x),
+            }
+            assert_eq!(dom.len(), 100);
+        }
+    }
+
+    #[test]
+    fn dom_stops_after_element() {
+        let html = "<html><head><title>a</title></head><body><p>b</p></body></html>";
+        let expected = [
+            "<html><head><title>a</title></head></html>",
+            "<html><head><title>a</title></head><body></body></html>",
+        ];
+        for (mode, expected) in [::TreeBuilderMode::Legacy, ::TreeBuilderMode::Whatwg].iter().zip(expected.iter()) {
+            let mut dom = ::Dom::with_options(&::DomOptions { tree_builder: *mode, stop_after: Some(Tag::HEAD), ..Default::default() });
+            let parsed = dom.parse(&mut BufReader::new(html.as_bytes())).unwrap();
+            assert_eq!(&html[..parsed], "<html><head><title>a</title></head>");
+            assert_eq!(dom.to_html(), *expected);
+        }
+    }
+
+    fn whatwg_dom(html: &str) -> ::Dom {
+        let mut dom = ::Dom::with_options(&::DomOptions { tree_builder: ::TreeBuilderMode::Whatwg, ..Default::default() });
+        dom.parse(&mut BufReader::new(html.as_bytes())).unwrap();
+        dom
+    }
+
+    #[test]
+    fn whatwg_tree_builder_implies_elements() {
+        let dom = whatwg_dom("<title>x</title><p>a<li>b<li>c</span></p>d");
+        assert_eq!(dom.to_html(), "<html><head><title>x</title></head><body><p>a</p><li>b</li><li>c<p></p>d</li></body></html>");
+        let errors: Vec<&str> = dom.parse_errors().iter().map(|x| x.0.code()).collect();
+        assert_eq!(errors, vec!["missing-doctype", "unexpected-end-tag", "unexpected-end-tag"]);
+        assert_eq!(dom.quirks_mode(), QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn whatwg_tree_builder_repairs_misnested_formatting() {
+        let dom = whatwg_dom("<!DOCTYPE html><b>1<p>2</b>3</p>");
+        assert_eq!(dom.to_html(), "<!DOCTYPE html><html><head></head><body><b>1</b><p><b>2</b>3</p></body></html>");
+        assert_eq!(dom.quirks_mode(), QuirksMode::NoQuirks);
+    }
+
+    #[test]
+    fn whatwg_tree_builder_fosters_table_content() {
+        let dom = whatwg_dom("<table>a<tr><td>b</td><i>c</table>");
+        assert_eq!(dom.to_html(), "<html><head></head><body>a<i>c</i><table><tbody><tr><td>b</td></tr></tbody></table></body></html>");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn parse_async_document() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        let data = b"<html><body><p>Hello <b>World</b>!</p></body></html>";
+        let mut source = &data[..];
+        let mut dom = ::Dom::new();
+        let mut future = Box::pin(dom.parse_async(&mut source));
+        let mut cx = Context::from_waker(Waker::noop());
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(x) => assert_eq!(x.unwrap(), data.len()),
+            Poll::Pending => panic!("in memory source should not wait"),
+        }
+        drop(future);
+        assert_eq!(dom.to_html(), "<html><body><p>Hello <b>World</b>!</p></body></html>");
+    }
 }
@@ -0,0 +1,455 @@
+use std;
+use std::io::{BufRead, Read};
+
+use encoding_rs::{Decoder, Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252, X_USER_DEFINED};
+
+// Number of bytes examined by the meta prescan
+const PRESCAN_LENGTH: usize = 1024;
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\n' || b == b'\r' || b == 0x0C
+}
+
+// Find needle in data starting at pos, returns position after needle
+fn find(data: &[u8], pos: usize, needle: &[u8]) -> Option<usize> {
+    data[pos.min(data.len())..].windows(needle.len())
+        .position(|x| x == needle)
+        .map(|x| pos + x + needle.len())
+}
+
+// Get an attribute at pos, returns name, value and new position or
+// None if the end of the tag was reached.
+fn get_attribute(data: &[u8], mut pos: usize) -> Option<(Vec<u8>, Vec<u8>, usize)> {
+    while pos < data.len() && (is_whitespace(data[pos]) || data[pos] == b'/') {
+        pos += 1;
+    }
+    if pos >= data.len() || data[pos] == b'>' {
+        return None;
+    }
+
+    let mut name = Vec::new();
+    while pos < data.len() {
+        match data[pos] {
+            b'=' if !name.is_empty() => break,
+            b'/' | b'>' => return Some((name, Vec::new(), pos)),
+            x if is_whitespace(x) => break,
+            x => name.push(x.to_ascii_lowercase()),
+        }
+        pos += 1;
+    }
+
+    while pos < data.len() && is_whitespace(data[pos]) {
+        pos += 1;
+    }
+    if pos >= data.len() || data[pos] != b'=' {
+        return Some((name, Vec::new(), pos));
+    }
+    pos += 1;
+    while pos < data.len() && is_whitespace(data[pos]) {
+        pos += 1;
+    }
+
+    let mut value = Vec::new();
+    match data.get(pos) {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            pos += 1;
+            while pos < data.len() && data[pos] != quote {
+                value.push(data[pos].to_ascii_lowercase());
+                pos += 1;
+            }
+            pos += 1;
+        },
+        _ => {
+            while pos < data.len() && !is_whitespace(data[pos]) && data[pos] != b'>' {
+                value.push(data[pos].to_ascii_lowercase());
+                pos += 1;
+            }
+        }
+    }
+
+    Some((name, value, pos))
+}
+
+// Extract the encoding from the content attribute of a meta tag, eg.
+// "text/html; charset=iso-8859-1"
+fn charset_from_content(content: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    loop {
+        pos = find(content, pos, b"charset")?;
+        while pos < content.len() && is_whitespace(content[pos]) {
+            pos += 1;
+        }
+        if content.get(pos) == Some(&b'=') {
+            break;
+        }
+    }
+
+    pos += 1;
+    while pos < content.len() && is_whitespace(content[pos]) {
+        pos += 1;
+    }
+
+    let value = match content.get(pos) {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            let end = content[pos + 1..].iter().position(|&x| x == quote)?;
+            &content[pos + 1..pos + 1 + end]
+        },
+        Some(_) => {
+            let end = content[pos..].iter()
+                .position(|&x| is_whitespace(x) || x == b';')
+                .unwrap_or(content.len() - pos);
+            &content[pos..pos + end]
+        },
+        None => return None,
+    };
+
+    Encoding::for_label(value)
+}
+
+// Examine the attributes of a meta tag at pos, returns the encoding
+// it declares and the position after the tag.
+fn meta_charset(data: &[u8], mut pos: usize) -> (Option<&'static Encoding>, usize) {
+    let mut names: Vec<Vec<u8>> = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value, next)) = get_attribute(data, pos) {
+        pos = next;
+        if names.contains(&name) {
+            continue;
+        }
+
+        match &name[..] {
+            b"http-equiv" => got_pragma = got_pragma || value == b"content-type",
+            b"content" if charset.is_none() => {
+                charset = charset_from_content(&value);
+                if charset.is_some() {
+                    need_pragma = Some(true);
+                }
+            },
+            b"charset" => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            },
+            _ => (),
+        }
+        names.push(name);
+    }
+
+    let encoding = match (need_pragma, charset) {
+        (None, _) | (_, None) => None,
+        (Some(true), _) if !got_pragma => None,
+        (_, Some(x)) if x == UTF_16BE || x == UTF_16LE => Some(UTF_8),
+        (_, Some(x)) if x == X_USER_DEFINED => Some(WINDOWS_1252),
+        (_, x) => x,
+    };
+    (encoding, pos)
+}
+
+/// Prescan the start of a document for a ```<meta>``` tag declaring the
+/// character encoding, eg. ```<meta charset="iso-8859-1">```.
+pub fn prescan(data: &[u8]) -> Option<&'static Encoding> {
+    let data = &data[..data.len().min(PRESCAN_LENGTH)];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        if data[pos] != b'<' {
+            pos += 1;
+            continue;
+        }
+
+        let rest = &data[pos..];
+        if rest.starts_with(b"<!--") {
+            pos = find(data, pos + 4, b"-->")?;
+        } else if rest.len() > 6 && rest[..5].eq_ignore_ascii_case(b"<meta") && (is_whitespace(rest[5]) || rest[5] == b'/') {
+            let (encoding, next) = meta_charset(data, pos + 6);
+            if encoding.is_some() {
+                return encoding;
+            }
+            pos = next;
+        } else if rest.len() > 2 && (rest[1].is_ascii_alphabetic() || (rest[1] == b'/' && rest[2].is_ascii_alphabetic())) {
+            // Skip tag name and attributes of any other tag
+            pos += 1;
+            while pos < data.len() && !is_whitespace(data[pos]) && data[pos] != b'>' {
+                pos += 1;
+            }
+            while let Some((_, _, next)) = get_attribute(data, pos) {
+                pos = next;
+            }
+            pos += 1;
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos = find(data, pos, b">")?;
+        } else {
+            pos += 1;
+        }
+    }
+
+    None
+}
+
+// Test if data is valid UTF-8, a multibyte character cut at the end is
+// taken as valid
+fn is_utf8(data: &[u8]) -> bool {
+    match std::str::from_utf8(data) {
+        Ok(_) => true,
+        Err(ref x) => x.error_len().is_none(),
+    }
+}
+
+// Test if head followed by rest is valid UTF-8 without joining them, a
+// character cut at the end of head is completed from rest
+fn is_utf8_parts(head: &[u8], rest: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(head) {
+        Ok(_) => return is_utf8(rest),
+        Err(ref x) if x.error_len().is_none() => x.valid_up_to(),
+        Err(_) => return false,
+    };
+    let cut = head.len() - valid;
+    let mut joined = head[valid..].to_vec();
+    joined.extend_from_slice(&rest[..rest.len().min(3)]);
+    match std::str::from_utf8(&joined) {
+        Ok(_) => is_utf8(&rest[joined.len() - cut..]),
+        Err(ref x) if x.error_len().is_some() => false,
+        // The character is still cut at the end of rest
+        Err(ref x) if x.valid_up_to() == 0 => true,
+        Err(ref x) => is_utf8(&rest[x.valid_up_to() - cut..]),
+    }
+}
+
+/// Determine the character encoding of a document from its first
+/// bytes in head and the bytes available after them in rest.
+///
+/// A byte order mark takes precedence over the encoding given by the
+/// transport layer, eg. a HTTP ```Content-Type``` header, which in
+/// turn takes precedence over a ```<meta>``` declaration in the first
+/// 1024 bytes. Without any of these the document is assumed to be
+/// UTF-8 if head and rest are valid UTF-8, otherwise windows-1252. A
+/// document read in pieces may still have bytes that are not UTF-8
+/// after the bytes available, which are decoded to U+FFFD.
+pub fn sniff(head: &[u8], rest: &[u8], transport: Option<&'static Encoding>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(head) {
+        return encoding;
+    }
+
+    if let Some(encoding) = transport {
+        return encoding;
+    }
+
+    if let Some(encoding) = prescan(head) {
+        return encoding;
+    }
+
+    match is_utf8_parts(head, rest) {
+        true => UTF_8,
+        false => WINDOWS_1252,
+    }
+}
+
+// Decode data appending UTF-8 to output, returns bytes read
+fn decode_into(decoder: &mut Decoder, data: &[u8], last: bool, output: &mut Vec<u8>) -> usize {
+    let start = output.len();
+    let length = decoder.max_utf8_buffer_length(data.len()).unwrap_or(data.len() * 3 + 16);
+    output.resize(start + length, 0);
+    let (_, read, written, _) = decoder.decode_to_utf8(data, &mut output[start..], last);
+    output.truncate(start + written);
+    read
+}
+
+/// Reader that detects the encoding of a source and transcodes it to
+/// UTF-8.
+pub struct DecodingReader<R: BufRead> {
+    source: std::io::Chain<std::io::Cursor<Vec<u8>>, R>,
+    encoding: &'static Encoding,
+    decoder: Decoder,
+    output: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl<R: BufRead> DecodingReader<R> {
+    /// Create reader detecting the encoding from the start of source
+    pub fn new(mut source: R, transport: Option<&'static Encoding>) -> std::io::Result<DecodingReader<R>> {
+        let mut head = Vec::with_capacity(PRESCAN_LENGTH);
+        source.by_ref().take(PRESCAN_LENGTH as u64).read_to_end(&mut head)?;
+
+        let encoding = sniff(&head, source.fill_buf()?, transport);
+        Ok(DecodingReader {
+            source: std::io::Cursor::new(head).chain(source),
+            encoding,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            output: Vec::new(),
+            pos: 0,
+            finished: false,
+        })
+    }
+
+    /// Get the detected encoding
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+}
+
+impl<R: BufRead> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.output.len() && !self.finished {
+            let consumed = {
+                let data = self.source.fill_buf()?;
+                let last = data.is_empty();
+                self.output.clear();
+                self.pos = 0;
+                self.finished = last;
+                decode_into(&mut self.decoder, data, last, &mut self.output)
+            };
+            self.source.consume(consumed);
+        }
+
+        let length = buf.len().min(self.output.len() - self.pos);
+        buf[..length].copy_from_slice(&self.output[self.pos..self.pos + length]);
+        self.pos += length;
+        Ok(length)
+    }
+}
+
+/// Decoder for a document fed in chunks that detects the encoding from
+/// the start of the document and transcodes it to UTF-8.
+///
+/// The start of the document is held back until enough of it is fed to
+/// detect the encoding, a multibyte character split across chunks is
+/// decoded once the rest of it is fed.
+pub struct ChunkDecoder {
+    transport: Option<&'static Encoding>,
+    head: Vec<u8>,
+    decoder: Option<Decoder>,
+}
+
+impl ChunkDecoder {
+    /// Create decoder using the encoding given by the transport layer
+    pub fn new(transport: Option<&'static Encoding>) -> ChunkDecoder {
+        ChunkDecoder {
+            transport,
+            head: Vec::new(),
+            decoder: None,
+        }
+    }
+
+    /// Get the detected encoding, None until enough is fed to detect it
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        self.decoder.as_ref().map(|x| x.encoding())
+    }
+
+    /// Decode chunk appending UTF-8 to output, last is set for the end
+    /// of the document
+    pub fn decode(&mut self, chunk: &[u8], last: bool, output: &mut Vec<u8>) {
+        if let Some(ref mut decoder) = self.decoder {
+            decode_into(decoder, chunk, last, output);
+            return;
+        }
+
+        self.head.extend_from_slice(chunk);
+        if self.head.len() < PRESCAN_LENGTH && !last {
+            return;
+        }
+
+        let encoding = sniff(&self.head, &[], self.transport);
+        let mut decoder = encoding.new_decoder_with_bom_removal();
+        decode_into(&mut decoder, &self.head, last, output);
+        self.head = Vec::new();
+        self.decoder = Some(decoder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding::{sniff, prescan, DecodingReader, ChunkDecoder};
+    use encoding_rs::{Encoding, UTF_8, UTF_16LE, WINDOWS_1252, ISO_8859_2, SHIFT_JIS};
+    use std::io::{BufReader, Read};
+
+    fn decode(data: &[u8], transport: Option<&'static Encoding>) -> (String, &'static Encoding) {
+        let mut reader = DecodingReader::new(BufReader::new(data), transport).unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        (output, reader.encoding())
+    }
+
+    #[test]
+    fn prescan_meta_charset() {
+        assert_eq!(prescan(b"<html><head><meta charset=\"iso-8859-2\">"), Some(ISO_8859_2));
+        assert_eq!(prescan(b"<META CHARSET=Shift_JIS>"), Some(SHIFT_JIS));
+        assert_eq!(prescan(b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=latin2\">"), Some(ISO_8859_2));
+        assert_eq!(prescan(b"<meta content=\"text/html; charset=latin2\">"), None);
+        assert_eq!(prescan(b"<meta charset=\"utf-16le\">"), Some(UTF_8));
+        assert_eq!(prescan(b"<!-- <meta charset=\"latin2\"> --><p>"), None);
+        assert_eq!(prescan(b"<p title=\"<meta charset=latin2>\">"), None);
+        assert_eq!(prescan(b"<meta name=\"x\"><meta charset=\"latin2\">"), Some(ISO_8859_2));
+    }
+
+    #[test]
+    fn sniff_order() {
+        assert_eq!(sniff(b"\xFF\xFE<\x00", b"", Some(ISO_8859_2)), UTF_16LE);
+        assert_eq!(sniff(b"<meta charset=\"latin2\">", b"", Some(SHIFT_JIS)), SHIFT_JIS);
+        assert_eq!(sniff(b"<meta charset=\"latin2\">", b"", None), ISO_8859_2);
+        assert_eq!(sniff("<p>💖</p>".as_bytes(), b"", None), UTF_8);
+        assert_eq!(sniff(b"<p>\xe5\xe4\xf6</p>", b"", None), WINDOWS_1252);
+    }
+
+    #[test]
+    fn sniff_validates_available_bytes() {
+        assert_eq!(sniff(b"<p>", b"\xe5</p>", None), WINDOWS_1252);
+        assert_eq!(sniff(b"<p>\xF0\x9F", b"\x92\x96</p>", None), UTF_8);
+        assert_eq!(sniff(b"<p>\xF0\x9F", b"\x92", None), UTF_8);
+        assert_eq!(sniff(b"<p>\xF0\x9F", b"\x92<", None), WINDOWS_1252);
+        assert_eq!(sniff(b"<p>\xF0", b"\x9F\x92\x96\xe5<", None), WINDOWS_1252);
+        assert_eq!(sniff(b"<p>\xF0", b"\x9F\x92\x96\xc3\xa5", None), UTF_8);
+
+        // Text after the prescanned start of the document
+        let mut data = "<p>".repeat(500).into_bytes();
+        data.extend_from_slice("\u{e5}\u{2603}".as_bytes());
+        assert_eq!(decode(&data, None).1, UTF_8);
+        assert!(decode(&data, None).0.ends_with("\u{e5}\u{2603}"));
+        data.extend_from_slice(b"\xe5</p>");
+        assert_eq!(decode(&data, None).1, WINDOWS_1252);
+    }
+
+    #[test]
+    fn decode_to_utf8() {
+        assert_eq!(decode(b"<p>\xe5\xe4\xf6</p>", None), ("<p>åäö</p>".to_string(), WINDOWS_1252));
+        assert_eq!(decode(b"\xEF\xBB\xBF<p>\xF0\x9F\x92\x96</p>", None), ("<p>💖</p>".to_string(), UTF_8));
+        assert_eq!(decode(b"\xFF\xFE<\x00p\x00>\x00", None), ("<p>".to_string(), UTF_16LE));
+        assert_eq!(decode(b"<p>\x82\xa0</p>", Some(SHIFT_JIS)), ("<p>あ</p>".to_string(), SHIFT_JIS));
+    }
+
+    #[test]
+    fn decode_large_document() {
+        let mut data = b"<meta charset=\"windows-1252\">".to_vec();
+        for _ in 0..1000 {
+            data.extend_from_slice(b"<p>\xe5</p>");
+        }
+        let (output, encoding) = decode(&data, None);
+        assert_eq!(encoding, WINDOWS_1252);
+        assert_eq!(output.matches("<p>å</p>").count(), 1000);
+    }
+
+    #[test]
+    fn decode_chunks() {
+        let mut data = b"<p>\xe5</p>".to_vec();
+        data.extend_from_slice("<p>\u{2603}</p>".repeat(200).as_bytes());
+
+        let mut decoder = ChunkDecoder::new(None);
+        let mut output = Vec::new();
+        for chunk in data.chunks(7) {
+            decoder.decode(chunk, false, &mut output);
+        }
+        decoder.decode(b"", true, &mut output);
+        assert_eq!(decoder.encoding(), Some(WINDOWS_1252));
+        assert_eq!(output, decode(&data, None).0.into_bytes());
+
+        let mut decoder = ChunkDecoder::new(None);
+        let mut output = Vec::new();
+        decoder.decode(b"\xF0\x9F", false, &mut output);
+        assert_eq!(decoder.encoding(), None);
+        decoder.decode(b"\x92\x96", true, &mut output);
+        assert_eq!(decoder.encoding(), Some(UTF_8));
+        assert_eq!(output, "\u{1F496}".as_bytes());
+    }
+}
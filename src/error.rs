@@ -0,0 +1,218 @@
+use std;
+
+/// Error returned when parsing or accessing a document fails.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from the source failed
+    Io(std::io::Error),
+
+    /// Data is not valid UTF-8
+    Encoding(std::str::Utf8Error),
+
+    /// Operation on the document structure failed, eg. a node that
+    /// does not exist
+    Structure(String),
+
+    /// The document exceeds a limit set in the options, eg. the number
+    /// of nodes
+    Limit(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Error::Io(ref x) => f.write_str(&format!("I/O error: {}", x)),
+            Error::Encoding(ref x) => f.write_str(&format!("Encoding error: {}", x)),
+            Error::Structure(ref x) => f.write_str(&format!("Structure error: {}", x)),
+            Error::Limit(ref x) => f.write_str(&format!("Limit exceeded: {}", x)),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Io(ref x) => Some(x),
+            Error::Encoding(ref x) => Some(x),
+            Error::Structure(_) => None,
+            Error::Limit(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(error: std::str::Utf8Error) -> Error {
+        Error::Encoding(error)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(error: std::string::FromUtf8Error) -> Error {
+        Error::Encoding(error.utf8_error())
+    }
+}
+
+/// Recoverable error in the document that the parser worked around.
+///
+/// The errors are named after the parse errors of the WHATWG HTML
+/// standard, see [code](#method.code). The standard does not name the
+/// errors found while building the tree, which are reported as eg.
+/// ```unexpected-start-tag```, nor the errors for parts of the
+/// document dropped at a limit set in the options, eg.
+/// ```too-many-attributes```.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ParseError {
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    AttributeValueTooLong,
+    CdataInHtmlContent,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    EofWithOpenElements,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctype,
+    MissingDoctypeName,
+    MissingDoctypePublicIdentifier,
+    MissingDoctypeSystemIdentifier,
+    MissingEndTagName,
+    MissingQuoteBeforeDoctypePublicIdentifier,
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceAfterDoctypePublicKeyword,
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+    MissingWhitespaceBeforeDoctypeName,
+    MissingWhitespaceBetweenAttributes,
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+    NameTooLong,
+    NestedComment,
+    NestingTooDeep,
+    NonVoidHtmlElementStartTagWithTrailingSolidus,
+    NoncharacterCharacterReference,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    TooManyAttributes,
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedDoctype,
+    UnexpectedEndTag,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnexpectedStartTag,
+    UnexpectedText,
+    UnknownNamedCharacterReference,
+}
+
+impl ParseError {
+    /// Get the WHATWG name of the error, eg. "eof-in-comment"
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ParseError::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            ParseError::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            ParseError::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            ParseError::AbsenceOfDigitsInNumericCharacterReference => "absence-of-digits-in-numeric-character-reference",
+            ParseError::AttributeValueTooLong => "attribute-value-too-long",
+            ParseError::CdataInHtmlContent => "cdata-in-html-content",
+            ParseError::CharacterReferenceOutsideUnicodeRange => "character-reference-outside-unicode-range",
+            ParseError::ControlCharacterReference => "control-character-reference",
+            ParseError::DuplicateAttribute => "duplicate-attribute",
+            ParseError::EndTagWithAttributes => "end-tag-with-attributes",
+            ParseError::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            ParseError::EofBeforeTagName => "eof-before-tag-name",
+            ParseError::EofInCdata => "eof-in-cdata",
+            ParseError::EofInComment => "eof-in-comment",
+            ParseError::EofInDoctype => "eof-in-doctype",
+            ParseError::EofInScriptHtmlCommentLikeText => "eof-in-script-html-comment-like-text",
+            ParseError::EofInTag => "eof-in-tag",
+            ParseError::EofWithOpenElements => "eof-with-open-elements",
+            ParseError::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            ParseError::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            ParseError::InvalidCharacterSequenceAfterDoctypeName => "invalid-character-sequence-after-doctype-name",
+            ParseError::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            ParseError::MissingAttributeValue => "missing-attribute-value",
+            ParseError::MissingDoctype => "missing-doctype",
+            ParseError::MissingDoctypeName => "missing-doctype-name",
+            ParseError::MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            ParseError::MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            ParseError::MissingEndTagName => "missing-end-tag-name",
+            ParseError::MissingQuoteBeforeDoctypePublicIdentifier => "missing-quote-before-doctype-public-identifier",
+            ParseError::MissingQuoteBeforeDoctypeSystemIdentifier => "missing-quote-before-doctype-system-identifier",
+            ParseError::MissingSemicolonAfterCharacterReference => "missing-semicolon-after-character-reference",
+            ParseError::MissingWhitespaceAfterDoctypePublicKeyword => "missing-whitespace-after-doctype-public-keyword",
+            ParseError::MissingWhitespaceAfterDoctypeSystemKeyword => "missing-whitespace-after-doctype-system-keyword",
+            ParseError::MissingWhitespaceBeforeDoctypeName => "missing-whitespace-before-doctype-name",
+            ParseError::MissingWhitespaceBetweenAttributes => "missing-whitespace-between-attributes",
+            ParseError::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers => "missing-whitespace-between-doctype-public-and-system-identifiers",
+            ParseError::NameTooLong => "name-too-long",
+            ParseError::NestedComment => "nested-comment",
+            ParseError::NestingTooDeep => "nesting-too-deep",
+            ParseError::NonVoidHtmlElementStartTagWithTrailingSolidus => "non-void-html-element-start-tag-with-trailing-solidus",
+            ParseError::NoncharacterCharacterReference => "noncharacter-character-reference",
+            ParseError::NullCharacterReference => "null-character-reference",
+            ParseError::SurrogateCharacterReference => "surrogate-character-reference",
+            ParseError::TooManyAttributes => "too-many-attributes",
+            ParseError::UnexpectedCharacterAfterDoctypeSystemIdentifier => "unexpected-character-after-doctype-system-identifier",
+            ParseError::UnexpectedCharacterInAttributeName => "unexpected-character-in-attribute-name",
+            ParseError::UnexpectedCharacterInUnquotedAttributeValue => "unexpected-character-in-unquoted-attribute-value",
+            ParseError::UnexpectedDoctype => "unexpected-doctype",
+            ParseError::UnexpectedEndTag => "unexpected-end-tag",
+            ParseError::UnexpectedEqualsSignBeforeAttributeName => "unexpected-equals-sign-before-attribute-name",
+            ParseError::UnexpectedNullCharacter => "unexpected-null-character",
+            ParseError::UnexpectedQuestionMarkInsteadOfTagName => "unexpected-question-mark-instead-of-tag-name",
+            ParseError::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            ParseError::UnexpectedStartTag => "unexpected-start-tag",
+            ParseError::UnexpectedText => "unexpected-text",
+            ParseError::UnknownNamedCharacterReference => "unknown-named-character-reference",
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::{Error, ParseError};
+
+    #[test]
+    fn error_from_invalid_utf8() {
+        let error: Error = String::from_utf8(vec![b'a', 0xFF]).unwrap_err().into();
+        match error {
+            Error::Encoding(ref x) => assert_eq!(x.valid_up_to(), 1),
+            _ => panic!("expected encoding error"),
+        }
+        assert_eq!(error.to_string(), "Encoding error: invalid utf-8 sequence of 1 bytes from index 1");
+    }
+
+    #[test]
+    fn parse_error_code() {
+        assert_eq!(ParseError::EofInComment.code(), "eof-in-comment");
+        assert_eq!(ParseError::UnexpectedQuestionMarkInsteadOfTagName.to_string(), "unexpected-question-mark-instead-of-tag-name");
+    }
+}
@@ -0,0 +1,45 @@
+/// Escape text for use as content of an element.
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\u{a0}' => escaped.push_str("&nbsp;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape text for use as a double quoted attribute value.
+pub fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\u{a0}' => escaped.push_str("&nbsp;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use escape::{escape_text, escape_attribute};
+
+    #[test]
+    fn escape_text_content() {
+        assert_eq!(escape_text("a < b && c > \"d\"\u{a0}"), "a &lt; b &amp;&amp; c &gt; \"d\"&nbsp;");
+    }
+
+    #[test]
+    fn escape_attribute_value() {
+        assert_eq!(escape_attribute("say \"hi\" & 'bye' <b>"), "say &quot;hi&quot; &amp; 'bye' &lt;b&gt;");
+    }
+}
@@ -8,31 +8,85 @@
 //!
 //! The [Parser] itself runs through the HTML document and using the
 //! trait [IsParser], implemented by the caller as handler, you will
-//! be notified when a opening tag, closing tag and data is parsed.
+//! be notified when a opening tag, closing tag, comment and data is
+//! parsed.
 //! Information through the callback is provided as [Tag], a vector of
 //! [Attribute] and data as a vector of u8. See example below how to
 //! use the [Parser] and a simple implementation of [IsParser].
+//! The callbacks return a [Flow] to skip the content of an element or
+//! stop parsing, eg. once the metadata in `<head>` is read.
+//! Elements inside `<svg>` and `<math>` are read as SVG and MathML
+//! content, see [Namespace], with their own rules for names,
+//! self-closing tags and CDATA sections.
+//! Tag and attribute names are interned as [Atom]s, each name is
+//! stored once for a document and compared by pointer.
+//! Elements of vocabularies beyond HTML can be registered in a
+//! [TagRegistry] with the properties standard elements have built in.
+//! Character references, eg. `&amp;`, in data and attribute values
+//! are decoded unless disabled through [ParserOptions], which also
+//! selects a tokenizer following the HTML Living Standard for reading
+//! malformed documents exactly as a browser does.
+//!
+//! Instead of callbacks the document can be pulled as a sequence of
+//! [Token]s from a [Tokenizer], which is the tokenizer used by the
+//! [Parser]. For documents already in memory the
+//! [BorrowedTokenizer] produces the same tokens borrowing their data
+//! from the document instead of copying it.
+//!
+//! Documents arriving in chunks, eg. the body of a HTTP response, are
+//! fed chunk by chunk to a [Parser] instance which keeps its state
+//! between the chunks. With the `tokio` feature enabled documents are
+//! also parsed from a tokio `AsyncBufRead` by [Parser::parse_async] and
+//! [Dom::parse_async].
+//!
+//! The character encoding of the document is detected from a byte
+//! order mark, an encoding given by the caller or a `<meta charset>`
+//! declaration and the document is transcoded to UTF-8 before it is
+//! parsed.
 //!
 //! The [DOM] builder uses the parser to build up a tree data
 //! structure of the HTML document. Which you can traverse and perform
 //! operations on such as cleaning up the document or just simplify
 //! it. Running a broken HTML, eg missing closing tags, into DOM and
 //! then saving it you will get a nice consistent and valid HTML file.
+//! With [DomOptions] the tree is built following the tree construction
+//! of the HTML Living Standard, which gives the same tree as a browser
+//! does.
 //!
 //! __domx__ is licensed under GPLv3
 //!
 //! [DOM]: struct.Dom.html
+//! [DomOptions]: struct.DomOptions.html
 //! [Parser]: struct.Parser.html
 //! [IsParser]: trait.IsParser.html
+//! [Flow]: enum.Flow.html
+//! [ParserOptions]: struct.ParserOptions.html
+//! [Tokenizer]: struct.Tokenizer.html
+//! [Token]: enum.Token.html
+//! [BorrowedTokenizer]: struct.BorrowedTokenizer.html
+//! [Parser::parse_async]: struct.Parser.html#method.parse_async
+//! [Dom::parse_async]: struct.Dom.html#method.parse_async
 //! [Tag]: enum.Tag.html
 //! [Attribute]: struct.Attribute.html
+//! [Namespace]: enum.Namespace.html
+//! [Atom]: struct.Atom.html
+//! [TagRegistry]: struct.TagRegistry.html
 //!
 //!
-//! # Panics
+//! # Errors
 //!
-//! There is only one place a panic!() is called and that is were an
-//! unknown HTML tag is encountered. This is temporary and will be
-//! removed in stable release.
+//! Parsing and accessors return [Error] on failure, eg. when reading
+//! the source fails or data is not valid UTF-8. No input should cause
+//! the parser or the [DOM] builder to panic.
+//!
+//! For untrusted documents [ParserOptions] bound the length of names
+//! and attribute values and the number of attributes of a tag, and
+//! [DomOptions] bound the nesting depth and the number of nodes of the
+//! tree. Parsing fails with [Error::Limit] when the tree grows past the
+//! number of nodes, the other limits truncate the document.
+//!
+//! [Error]: enum.Error.html
+//! [Error::Limit]: enum.Error.html#variant.Limit
 //!
 //!
 //! # Examples
@@ -76,13 +130,13 @@
 //! ```
 //! extern crate domx;
 //!
-//! use domx::{Parser, IsParser, Tag, Attribute};
+//! use domx::{Parser, IsParser, Flow, Tag, Attribute, Span};
 //! use std::fs::File;
 //! use std::io::BufReader;
 //!
 //! struct MyParser;
 //! impl IsParser for MyParser {
-//!     fn handle_starttag(self: &mut Self, tag: &Tag, attributes: &Vec<Attribute>) {
+//!     fn handle_starttag(self: &mut Self, tag: &Tag, attributes: &Vec<Attribute>, _self_closing: bool, _span: &Span) -> Flow {
 //!         let mut av: Vec<String> = Vec::new();
 //!
 //!         av.push(tag.to_string());
@@ -92,14 +146,17 @@
 //!         }
 //!
 //!         print!("<{}>", av.join(" ").as_str());
+//!         Flow::Continue
 //!     }
 //!
-//!     fn handle_endtag(self: &mut Self, tag: &Tag) {
+//!     fn handle_endtag(self: &mut Self, tag: &Tag, _span: &Span) -> Flow {
 //!         print!("{}", tag.clone());
+//!         Flow::Continue
 //!     }
 //!
-//!     fn handle_data(self: &mut Self, data: &Vec<u8>) {
+//!     fn handle_data(self: &mut Self, data: &Vec<u8>, _span: &Span) -> Flow {
 //!         print!("{}", String::from_utf8(data.clone()).unwrap());
+//!         Flow::Continue
 //!     }
 //! }
 //!
@@ -117,21 +174,60 @@
 //! }
 //! ```
 
+extern crate encoding_rs;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(test)]
+extern crate serde_json;
+
 mod traits;
 pub use traits::{ToHTML};
 
+mod error;
+pub use error::{Error, ParseError};
+
+mod atom;
+pub use atom::{Atom};
+
 mod tag;
 pub use tag::{Tag};
 
+mod registry;
+pub use registry::{TagRegistry, TagProperties, CustomTag};
+
+mod namespace;
+pub use namespace::{Namespace};
+
 mod attribute;
 pub use attribute::{Attribute};
 
+mod doctype;
+pub use doctype::{Doctype, QuirksMode};
+
+mod position;
+pub use position::{Position, Span};
+
+mod entities;
+mod charref;
+mod escape;
+
+mod encoding;
+pub use encoding_rs::{Encoding};
+
+mod tokenizer;
+mod whatwg;
+pub use tokenizer::{Tokenizer, Token};
+
+mod borrowed;
+pub use borrowed::{BorrowedTokenizer, BorrowedToken, BorrowedAttribute};
+
 mod parser;
-pub use parser::{Parser, IsParser};
+pub use parser::{Parser, ParserOptions, TokenizerMode, IsParser, Flow};
 
 #[macro_use]
+mod treebuilder;
 mod dom;
-pub use dom::{Dom};
+pub use dom::{Dom, DomOptions, TreeBuilderMode};
 
 
 
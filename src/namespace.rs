@@ -0,0 +1,256 @@
+use std;
+use tag::{Tag};
+
+/// Namespace of an element or attribute.
+///
+/// Elements are in the HTML namespace but the content of ```<svg>```
+/// and ```<math>```, which is in the SVG and MathML namespace. Some
+/// attributes of those elements, eg. ```xlink:href```, are in the XLink,
+/// XML or XMLNS namespace.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+    XLink,
+    Xml,
+    XmlNs,
+}
+
+impl Namespace {
+    /// Get the URI of the namespace, eg.
+    /// ```http://www.w3.org/2000/svg```.
+    pub fn uri(&self) -> &'static str {
+        match *self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::Xml => "http://www.w3.org/XML/1998/namespace",
+            Namespace::XmlNs => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+}
+
+/// The name commonly used for the namespace, eg. ```svg``` or
+/// ```xlink```.
+impl std::fmt::Display for Namespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match *self {
+            Namespace::Html => "html",
+            Namespace::Svg => "svg",
+            Namespace::MathMl => "math",
+            Namespace::XLink => "xlink",
+            Namespace::Xml => "xml",
+            Namespace::XmlNs => "xmlns",
+        })
+    }
+}
+
+// Test if a start tag in foreign content leaves it, closing the foreign
+// elements, eg. <p> in <svg>
+fn is_breakout<'a, I>(tag: &Tag, attributes: I) -> bool
+    where I: IntoIterator<Item = (&'a [u8], &'a [u8])>
+{
+    match *tag {
+        Tag::B | Tag::BIG | Tag::BLOCKQUOTE | Tag::BODY | Tag::BR | Tag::CENTER | Tag::CODE |
+        Tag::DD | Tag::DIV | Tag::DL | Tag::DT | Tag::EM | Tag::EMBED | Tag::H1 | Tag::H2 |
+        Tag::H3 | Tag::H4 | Tag::H5 | Tag::H6 | Tag::HEAD | Tag::HR | Tag::I | Tag::IMG |
+        Tag::LI | Tag::LISTING | Tag::MENU | Tag::META | Tag::NOBR | Tag::OL | Tag::P |
+        Tag::PRE | Tag::RUBY | Tag::S | Tag::SMALL | Tag::SPAN | Tag::STRONG | Tag::STRIKE |
+        Tag::SUB | Tag::SUP | Tag::TABLE | Tag::TT | Tag::U | Tag::UL | Tag::VAR => true,
+        Tag::FONT => attributes.into_iter().any(|(name, _)| matches!(name, b"color" | b"face" | b"size")),
+        _ => false,
+    }
+}
+
+/// Test if a foreign element holds HTML, eg. ```<foreignObject>``` in
+/// SVG or ```<annotation-xml encoding="text/html">``` in MathML.
+pub fn is_html_integration_point<'a, I>(namespace: Namespace, name: &str, attributes: I) -> bool
+    where I: IntoIterator<Item = (&'a [u8], &'a [u8])>
+{
+    match namespace {
+        Namespace::Svg => ["foreignObject", "desc", "title"].iter().any(|x| x.eq_ignore_ascii_case(name)),
+        Namespace::MathMl if name.eq_ignore_ascii_case("annotation-xml") => {
+            attributes.into_iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case(b"encoding") &&
+                    (value.eq_ignore_ascii_case(b"text/html") || value.eq_ignore_ascii_case(b"application/xhtml+xml"))
+            })
+        },
+        _ => false,
+    }
+}
+
+/// Test if a MathML element holds text and HTML phrasing content, eg.
+/// ```<mi>```.
+pub fn is_mathml_text_integration_point(namespace: Namespace, name: &str) -> bool {
+    namespace == Namespace::MathMl && ["mi", "mo", "mn", "ms", "mtext"].iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+// Element opened in foreign content
+#[derive(Clone)]
+struct Open {
+    namespace: Namespace,
+    name: String,
+    integration: bool,
+}
+
+/// Namespaces of the elements read by a tokenizer, tracked from the
+/// start and end tags alone.
+///
+/// The elements from a ```<svg>``` or ```<math>``` start tag to its end
+/// tag are kept as the tree builder of the HTML Living Standard would
+/// have them open, elements in HTML content are not tracked.
+#[derive(Clone)]
+pub struct ForeignContent {
+    open: Vec<Open>,
+}
+
+impl ForeignContent {
+    pub fn new() -> ForeignContent {
+        ForeignContent {
+            open: Vec::new(),
+        }
+    }
+
+    /// Test if the current element is a foreign element, which content
+    /// is never raw text and may have CDATA sections.
+    pub fn is_foreign(&self) -> bool {
+        self.open.last().is_some_and(|x| x.namespace != Namespace::Html)
+    }
+
+    // Test if a start tag is read as HTML content
+    fn _is_html(&self, tag: &Tag) -> bool {
+        match self.open.last() {
+            None => true,
+            Some(x) if x.namespace == Namespace::Html || x.integration => true,
+            Some(x) if is_mathml_text_integration_point(x.namespace, &x.name) => {
+                !["mglyph", "malignmark"].contains(&tag.as_str())
+            },
+            Some(x) => x.namespace == Namespace::MathMl && x.name.eq_ignore_ascii_case("annotation-xml") &&
+                tag.namespace() == Namespace::Svg,
+        }
+    }
+
+    // Close the foreign elements up to one holding HTML content
+    fn _close_foreign(&mut self) {
+        while let Some(x) = self.open.last() {
+            if x.namespace == Namespace::Html || x.integration || is_mathml_text_integration_point(x.namespace, &x.name) {
+                break;
+            }
+            self.open.pop();
+        }
+    }
+
+    /// Get the namespace of the element of a start tag, the element is
+    /// open until its end tag unless self-closing.
+    pub fn start_tag<'a, I>(&mut self, tag: &Tag, attributes: I, self_closing: bool) -> Namespace
+        where I: IntoIterator<Item = (&'a [u8], &'a [u8])> + Clone
+    {
+        if !self._is_html(tag) && is_breakout(tag, attributes.clone()) {
+            self._close_foreign();
+        }
+
+        let namespace = match self.open.last() {
+            _ if self._is_html(tag) => tag.namespace(),
+            Some(x) => x.namespace,
+            None => Namespace::Html,
+        };
+        let open = match namespace {
+            Namespace::Html => !self.open.is_empty() && !tag.is_void(),
+            _ => true,
+        };
+        if open && !self_closing {
+            self.open.push(Open {
+                namespace,
+                name: tag.as_str().to_string(),
+                integration: is_html_integration_point(namespace, tag.as_str(), attributes),
+            });
+        }
+        namespace
+    }
+
+    /// Get the namespace of the element of an end tag and close it.
+    pub fn end_tag(&mut self, tag: &Tag) -> Namespace {
+        let name = tag.as_str();
+        if self.is_foreign() {
+            if *tag == Tag::BR || *tag == Tag::P {
+                self._close_foreign();
+            }
+            for index in (0..self.open.len()).rev() {
+                if self.open[index].namespace == Namespace::Html {
+                    break;
+                }
+                if self.open[index].name.eq_ignore_ascii_case(name) {
+                    let namespace = self.open[index].namespace;
+                    self.open.truncate(index);
+                    return namespace;
+                }
+            }
+        }
+
+        // End tag of an element in HTML content
+        for index in (0..self.open.len()).rev() {
+            if self.open[index].namespace != Namespace::Html {
+                break;
+            }
+            if self.open[index].name.eq_ignore_ascii_case(name) {
+                self.open.truncate(index);
+                break;
+            }
+        }
+        Namespace::Html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tag::{Tag};
+    use namespace::{Namespace, ForeignContent};
+
+    fn start(foreign: &mut ForeignContent, name: &str) -> Namespace {
+        foreign.start_tag(&name.parse::<Tag>().unwrap(), Vec::new(), false)
+    }
+
+    fn end(foreign: &mut ForeignContent, name: &str) -> Namespace {
+        foreign.end_tag(&name.parse::<Tag>().unwrap())
+    }
+
+    #[test]
+    fn foreign_content_from_root_to_end_tag() {
+        let mut foreign = ForeignContent::new();
+        assert_eq!(start(&mut foreign, "div"), Namespace::Html);
+        assert_eq!(start(&mut foreign, "svg"), Namespace::Svg);
+        assert!(foreign.is_foreign());
+        assert_eq!(start(&mut foreign, "title"), Namespace::Svg);
+        assert_eq!(start(&mut foreign, "b"), Namespace::Html);
+        assert!(!foreign.is_foreign());
+        assert_eq!(end(&mut foreign, "b"), Namespace::Html);
+        assert_eq!(end(&mut foreign, "title"), Namespace::Svg);
+        assert_eq!(end(&mut foreign, "svg"), Namespace::Svg);
+        assert_eq!(end(&mut foreign, "div"), Namespace::Html);
+        assert!(!foreign.is_foreign());
+
+        assert_eq!(start(&mut foreign, "math"), Namespace::MathMl);
+        assert_eq!(start(&mut foreign, "mi"), Namespace::MathMl);
+        assert_eq!(start(&mut foreign, "svg"), Namespace::Svg);
+        assert_eq!(end(&mut foreign, "svg"), Namespace::Svg);
+        assert_eq!(start(&mut foreign, "mglyph"), Namespace::MathMl);
+    }
+
+    #[test]
+    fn foreign_content_closed_by_html_element() {
+        let mut foreign = ForeignContent::new();
+        assert_eq!(start(&mut foreign, "svg"), Namespace::Svg);
+        assert_eq!(start(&mut foreign, "g"), Namespace::Svg);
+        assert_eq!(start(&mut foreign, "p"), Namespace::Html);
+        assert!(!foreign.is_foreign());
+        assert_eq!(end(&mut foreign, "g"), Namespace::Html);
+
+        let mut foreign = ForeignContent::new();
+        let color: Vec<(&[u8], &[u8])> = vec![(b"color", b"red")];
+        assert_eq!(start(&mut foreign, "svg"), Namespace::Svg);
+        assert_eq!(foreign.start_tag(&Tag::FONT, Vec::new(), false), Namespace::Svg);
+        assert_eq!(foreign.start_tag(&Tag::FONT, color, false), Namespace::Html);
+    }
+}
@@ -1,54 +1,294 @@
-use std;
 use std::io::BufRead;
+#[cfg(feature = "tokio")]
+use std::future::{Future, poll_fn};
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncBufRead;
 
 use tag::{Tag};
+use registry::{TagRegistry};
 use attribute::{Attribute};
+use doctype::{Doctype};
+use error::{Error, ParseError};
+use encoding_rs::{Encoding, UTF_8};
+use encoding::{ChunkDecoder};
+use position::{Span};
+use tokenizer::{Tokenizer, Token, Scanner};
 
-#[derive(Clone)]
-struct ParserTag {
-    name: String,
-    pub id: Option<Tag>,
-    closing: bool,
-    data: Vec<u8>,
-    pub attributes: Vec<Attribute>
-}
-
-impl std::fmt::Display for ParserTag {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self.closing {
-            false => f.write_str("<"),
-            true => f.write_str("</")
-        }.unwrap();
-
-        f.write_str(&format!("{}",self.id.as_ref().unwrap())).unwrap();
+// Size of the blocks passed to the scanner
+const BLOCK_SIZE: usize = 2048;
 
-        let mut av: Vec<String> = Vec::new();
-        for ref attr in self.attributes.clone() {
-            av.push(format!("{}", attr));
-        }
-        if av.len() != 0 {
-            f.write_str(" ").unwrap();
-            f.write_str(av.join(" ").as_str()).unwrap();
-        }
 
-        f.write_str(">")
-    }
+/// Value returned by the methods of [IsParser](trait.IsParser.html) to
+/// control how the parser goes on with the document.
+///
+/// # Examples
+///
+/// Read the title of a document without parsing the rest of it:
+///
+/// ```
+/// use domx::{Parser, IsParser, Flow, Tag, Attribute, Span};
+///
+/// struct Title(String, bool);
+/// impl IsParser for Title {
+///     fn handle_starttag(self: &mut Self, tag: &Tag, _attributes: &Vec<Attribute>, _self_closing: bool, _span: &Span) -> Flow {
+///         self.1 = *tag == Tag::TITLE;
+///         Flow::Continue
+///     }
+///
+///     fn handle_endtag(self: &mut Self, tag: &Tag, _span: &Span) -> Flow {
+///         match *tag {
+///             Tag::TITLE | Tag::HEAD => Flow::Stop,
+///             _ => Flow::Continue,
+///         }
+///     }
+///
+///     fn handle_data(self: &mut Self, data: &Vec<u8>, _span: &Span) -> Flow {
+///         if self.1 {
+///             self.0.push_str(&String::from_utf8_lossy(data));
+///         }
+///         Flow::Continue
+///     }
+/// }
+///
+/// let data = "<html><head><title>Hello</title></head><body>...</body></html>";
+/// let mut title = Title(String::new(), false);
+/// Parser::parse(&mut data.as_bytes(), &mut title).unwrap();
+/// assert_eq!(title.0, "Hello");
+/// ```
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Flow {
+    /// Go on parsing the document.
+    Continue,
+
+    /// Skip the content of the element, returned from
+    /// [handle_starttag](trait.IsParser.html#tymethod.handle_starttag).
+    /// No methods are called for the content, the parser goes on with
+    /// the end tag of the element. An element with an optional end tag,
+    /// eg. ```<li>``` or ```<p>```, also ends at a tag implying its end,
+    /// eg. the next ```<li>``` or ```</ul>```. Skipping a void or self-closing
+    /// element, or returned from any other method, it is the same as
+    /// ```Continue```.
+    Skip,
+
+    /// Stop parsing the document,
+    /// [handle_eof](trait.IsParser.html#method.handle_eof) is called
+    /// and the number of bytes parsed up to the end of the current
+    /// token is returned, see [Parser::parse](struct.Parser.html#method.parse).
+    Stop,
 }
 
 /// A trait for handling callbacks from [Parser](struct.Parser.html).
 ///
+/// The methods called for the content of the document return a
+/// [Flow](enum.Flow.html) to go on parsing, skip the content of an
+/// element or stop parsing.
 pub trait IsParser {
     /// This method is called to handle the start tag.
-    fn handle_starttag(self: &mut Self, tag: &Tag, attributes: &Vec<Attribute>);
+    ///
+    /// The flag self_closing is set for a tag closed with ```/>```, eg.
+    /// ```<br/>```. The span covers the tag from ```<``` to ```>```,
+    /// each attribute carries its own span.
+    fn handle_starttag(&mut self, tag: &Tag, attributes: &Vec<Attribute>, self_closing: bool, span: &Span) -> Flow;
+
+    /// This method is called to handle a start tag written other than
+    /// the name of the tag, eg. ```<BR>``` read with the case preserved.
+    ///
+    /// The default implementation passes the tag to
+    /// [handle_starttag](#tymethod.handle_starttag).
+    fn handle_spelled_starttag(&mut self, tag: &Tag, _spelling: &str, attributes: &Vec<Attribute>, self_closing: bool, span: &Span) -> Flow {
+        self.handle_starttag(tag, attributes, self_closing, span)
+    }
 
     /// This method is called to handle the end tag of a element.
-    fn handle_endtag(self: &mut Self, tag: &Tag);
+    fn handle_endtag(&mut self, tag: &Tag, span: &Span) -> Flow;
+
+    /// This method is called to handle a comment, eg. the text
+    /// between ```<!--``` and ```-->```.
+    ///
+    /// The default implementation ignores comments.
+    fn handle_comment(&mut self, _comment: &Vec<u8>, _span: &Span) -> Flow {
+        Flow::Continue
+    }
+
+    /// This method is called to handle a ```<!DOCTYPE>``` declaration.
+    ///
+    /// The default implementation ignores the doctype.
+    fn handle_doctype(&mut self, _doctype: &Doctype, _span: &Span) -> Flow {
+        Flow::Continue
+    }
+
+    /// This method is called to handle the content of a CDATA
+    /// section, eg. ```<![CDATA[ ... ]]>```.
+    ///
+    /// The default implementation passes the content to
+    /// [handle_data](#tymethod.handle_data).
+    fn handle_cdata(&mut self, data: &Vec<u8>, span: &Span) -> Flow {
+        self.handle_data(data, span)
+    }
+
+    /// This method is called to handle a processing instruction, eg.
+    /// the text between ```<?``` and ```?>```.
+    ///
+    /// The default implementation ignores processing instructions.
+    fn handle_processing_instruction(&mut self, _instruction: &Vec<u8>, _span: &Span) -> Flow {
+        Flow::Continue
+    }
+
+    /// This method is called to report a recoverable error in the
+    /// document, the parser continues after working around it unless
+    /// stopped.
+    ///
+    /// The default implementation ignores errors.
+    fn handle_error(&mut self, _error: ParseError, _span: &Span) -> Flow {
+        Flow::Continue
+    }
+
+    /// This method is called with the character encoding of the
+    /// document before any other method is called.
+    ///
+    /// The default implementation ignores the encoding.
+    fn handle_encoding(&mut self, _encoding: &'static Encoding) {}
+
+    /// This method is called once the whole document is parsed, or
+    /// parsing is stopped, after any other method.
+    ///
+    /// The default implementation does nothing.
+    fn handle_eof(&mut self) {}
 
     /// This method is called to process arbitrary data.
     ///
     /// Data beeing text nodes and the content of ```<script>...</script>```
     /// and ```<style>...</style>``` tags.
-    fn handle_data(self: &mut Self, _data: &Vec<u8>);
+    fn handle_data(&mut self, _data: &Vec<u8>, span: &Span) -> Flow;
+}
+
+/// Tokenizer state machine used to read the document, see
+/// [ParserOptions](struct.ParserOptions.html).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TokenizerMode {
+    /// The original domx tokenizer, which is fast and forgiving but
+    /// reads some malformed markup differently than a browser.
+    Legacy,
+
+    /// Tokenizer following the tokenization states of the HTML Living
+    /// Standard, which reads any input the same way as a browser.
+    /// CDATA sections and processing instructions are read as bogus
+    /// comments.
+    Whatwg,
+}
+
+/// Options controlling the behaviour of [Parser](struct.Parser.html).
+///
+/// # Examples
+///
+/// ```
+/// use domx::ParserOptions;
+///
+/// // Keep character references as is, eg. for passthrough of documents
+/// let options = ParserOptions { decode_entities: false, ..Default::default() };
+///
+/// // Tokenize the document as a browser does
+/// use domx::TokenizerMode;
+/// let options = ParserOptions { tokenizer: TokenizerMode::Whatwg, ..Default::default() };
+///
+/// // Keep the case of names, eg. for XML vocabularies
+/// let options = ParserOptions { preserve_case: true, ..Default::default() };
+///
+/// // Bound the memory used for each tag of an untrusted document
+/// let options = ParserOptions {
+///     max_name_length: 256,
+///     max_attributes: 64,
+///     max_attribute_value_length: 64 * 1024,
+///     ..Default::default()
+/// };
+///
+/// // Read the content of a custom element as raw text
+/// use domx::{TagRegistry, TagProperties};
+/// let mut tags = TagRegistry::new();
+/// tags.register("x-code", TagProperties { raw_text: true, ..Default::default() });
+/// let options = ParserOptions { tags: tags, ..Default::default() };
+/// ```
+#[derive(Clone, Debug)]
+pub struct ParserOptions {
+    /// Decode character references such as ```&amp;``` and
+    /// ```&#x1F496;``` in data and attribute values, enabled by
+    /// default.
+    pub decode_entities: bool,
+
+    /// Skip comments without calling
+    /// [IsParser::handle_comment](trait.IsParser.html#method.handle_comment),
+    /// which is faster if comments are of no interest.
+    pub skip_comments: bool,
+
+    /// Detect the character encoding of the document and transcode it
+    /// to UTF-8 before parsing, enabled by default. Positions are then
+    /// counted in the transcoded document. When disabled the document
+    /// is assumed to be UTF-8 and parsed as is.
+    pub detect_encoding: bool,
+
+    /// Encoding given by the transport layer, eg. the charset of a HTTP
+    /// ```Content-Type``` header. It takes precedence over a
+    /// ```<meta>``` declaration but not over a byte order mark.
+    pub transport_encoding: Option<&'static Encoding>,
+
+    /// Tokenizer used to read the document, the legacy tokenizer by
+    /// default. [BorrowedTokenizer](struct.BorrowedTokenizer.html)
+    /// always reads the document as the legacy tokenizer.
+    pub tokenizer: TokenizerMode,
+
+    /// Keep tag and attribute names as written in the document, eg. for
+    /// XML vocabularies. Tags are still matched case-insensitively, so
+    /// ```<BR>``` is ```Tag::BR```, and the name as written is passed
+    /// to [IsParser::handle_spelled_starttag]. By default tag names are
+    /// lowercased and SVG and MathML names are adjusted to their
+    /// spelling in the HTML standard, eg. ```foreignObject``` and
+    /// ```viewBox```.
+    ///
+    /// [IsParser::handle_spelled_starttag]: trait.IsParser.html#method.handle_spelled_starttag
+    pub preserve_case: bool,
+
+    /// Longest tag or attribute name in bytes, longer names are
+    /// truncated and reported as
+    /// [ParseError::NameTooLong](enum.ParseError.html). Not limited by
+    /// default.
+    pub max_name_length: usize,
+
+    /// Most attributes read for a tag, further attributes are dropped
+    /// and reported as
+    /// [ParseError::TooManyAttributes](enum.ParseError.html). Not
+    /// limited by default.
+    pub max_attributes: usize,
+
+    /// Longest attribute value in bytes, longer values are truncated
+    /// and reported as
+    /// [ParseError::AttributeValueTooLong](enum.ParseError.html). Not
+    /// limited by default.
+    pub max_attribute_value_length: usize,
+
+    /// Elements beyond HTML with their properties, eg. void or raw text
+    /// custom elements, read as ```Tag::Custom```. None by default.
+    pub tags: TagRegistry,
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {
+            decode_entities: true,
+            skip_comments: false,
+            detect_encoding: true,
+            transport_encoding: None,
+            tokenizer: TokenizerMode::Legacy,
+            preserve_case: false,
+            max_name_length: usize::MAX,
+            max_attributes: usize::MAX,
+            max_attribute_value_length: usize::MAX,
+            tags: TagRegistry::new(),
+        }
+    }
 }
 
 /// Parse a HTML document and provide data through handler [IsParser].
@@ -56,438 +296,340 @@ pub trait IsParser {
 /// The parser serves the basis for parsing text files formatted in
 /// HTML and XHTML. The parser is not based on SGML.
 ///
+/// A document is either parsed from a ```BufRead``` by
+/// [parse](#method.parse) or fed in chunks, as they arrive, to a parser
+/// instance through [feed](#method.feed) and [finish](#method.finish).
+/// Tags, comments and characters split across chunks parse the same as
+/// when read in one go.
+///
+/// The handler controls how the parser goes on through the
+/// [Flow](enum.Flow.html) returned by its methods, once it stops
+/// parsing the rest of the document is ignored.
+///
 /// [IsParser]: trait.IsParser.html
 ///
-pub struct Parser;
-
-enum ParserState {
-    FindParserTag,
-    SkipComment,
-    ReadParserTagName,
-    ReadData,
-    ReadRawData,
-    ReadAttributeName,
-    ReadAttributeValue,
+/// # Examples
+///
+/// ```
+/// use domx::{Parser, Dom, ToHTML};
+///
+/// let mut dom = Dom::new();
+/// let mut parser = Parser::new();
+/// parser.feed(b"<p class=\"in", &mut dom);
+/// parser.feed(b"fo\">Hello</p>", &mut dom);
+/// parser.finish(&mut dom);
+/// assert_eq!(dom.to_html(), "<p class=\"info\">Hello</p>");
+/// ```
+pub struct Parser {
+    options: ParserOptions,
+    decoder: Option<ChunkDecoder>,
+    decoded: Vec<u8>,
+    scanner: Scanner,
+    started: bool,
+    control: Control,
 }
 
-impl Parser {
+// Flow control asked for by the handler
+#[derive(Default)]
+struct Control {
+    // Element which content is skipped and the elements open in it
+    skip: Option<(Tag, Vec<Tag>)>,
+    // Bytes parsed up to the end of the token the handler stopped at
+    stopped: Option<usize>,
+}
 
-    fn _state_find_tag(buf: &Vec<u8>, tag: &mut ParserTag, state: &mut ParserState) -> usize {
-        let mut processed = 0;
-        for b in buf {
-            if *b == '<' as u8 {
-                tag.name = "".to_string();
-                tag.id = None;
-                tag.data.clear();
-                tag.closing = false;
-                *state = ParserState::ReadParserTagName;
-                break;
-            }
-            processed += 1;
+impl Control {
+    // Call handler for token unless it is skipped, returns false once
+    // the handler stopped parsing
+    fn handle(&mut self, token: Token, handler: &mut dyn IsParser) -> bool {
+        if self.stopped.is_some() {
+            return false;
         }
-        return processed;
-    }
-
-    fn _state_skip_comment(buf: &Vec<u8>, state: &mut ParserState) -> usize {
-        let mut processed = 0;
-        loop {
-            if (processed + 3) > buf.len() {
-                // we need more data to continue
-                break;
-            }
-
-            if buf[processed + 0] == '-' as u8 &&
-                buf[processed + 1] == '-' as u8 &&
-                buf[processed + 2] == '>' as u8 {
-
-                *state = ParserState::ReadData;
-                processed += 3;
-                break;
-            }
-
-            processed += 1
+        if self._skipped(&token) {
+            return true;
         }
 
-        return processed;
+        let end = token.span().end.offset;
+        let flow = match token {
+            Token::StartTag { tag, spelling, attributes, self_closing, span } => {
+                let flow = match spelling {
+                    Some(x) => handler.handle_spelled_starttag(&tag, &x, &attributes, self_closing, &span),
+                    None => handler.handle_starttag(&tag, &attributes, self_closing, &span),
+                };
+                if flow == Flow::Skip && !self_closing && !tag.is_void() {
+                    self.skip = Some((tag, Vec::new()));
+                }
+                flow
+            },
+            Token::EndTag { tag, span } => handler.handle_endtag(&tag, &span),
+            Token::Text { data, span } => handler.handle_data(&data, &span),
+            Token::Comment { data, span } => handler.handle_comment(&data, &span),
+            Token::Doctype { doctype, span } => handler.handle_doctype(&doctype, &span),
+            Token::CData { data, span } => handler.handle_cdata(&data, &span),
+            Token::ProcessingInstruction { data, span } => handler.handle_processing_instruction(&data, &span),
+            Token::Error { error, span } => handler.handle_error(error, &span),
+        };
+        if flow == Flow::Stop {
+            self.stopped = Some(end);
+        }
+        self.stopped.is_none()
     }
 
-    fn _state_read_tag_name(buf: &Vec<u8>, tag: &mut ParserTag, state: &mut ParserState) -> usize {
-        let mut processed = 0;
-
-        for b in buf {
-
-            match *b as char {
-                // Skip begin of tag
-                '<' => processed += 1,
-
-                '\r' | '\n' => {
-                    processed += 1;
-                },
-
-                // Comment detected
-                '!' => {
-                    processed += 1;
-                    *state = match buf[processed] as char {
-                        '-' => ParserState::SkipComment,
-                        _   => ParserState::FindParserTag
-                    };
-                    break;
-                },
-
-                // Closing tag detected
-                '/' => {
-                    tag.closing = true;
-                    processed += 1;
+    // Test if token is in the content of the element skipped. The end
+    // tag of the element ends the skipping, as do the tags implying the
+    // end of an element with an optional end tag, eg. <li> or </ul>
+    // after <li>, which are not skipped.
+    fn _skipped(&mut self, token: &Token) -> bool {
+        let (done, skipped) = match self.skip {
+            None => return false,
+            Some((ref skipped, ref mut open)) => match *token {
+                Token::StartTag { ref tag, .. } if open.iter().all(|x| x.is_phrasing()) && implies_end(skipped, tag) => (true, false),
+                Token::StartTag { ref tag, self_closing: false, .. } if !tag.is_void() => {
+                    open.push(tag.clone());
+                    (false, true)
                 },
-
-                // Complete tag name read 
-                '>' | ' ' => {
-                    tag.attributes.clear();
-                    tag.attributes.push(Attribute{name: Vec::new(), value: Vec::new()});
-                    *state = ParserState::ReadAttributeName;
-                    break;
+                Token::EndTag { ref tag, .. } => match open.iter().rposition(|x| x == tag) {
+                    Some(x) => {
+                        open.truncate(x);
+                        (false, true)
+                    },
+                    None if tag == skipped => (true, false),
+                    None => (ends(skipped, tag), !ends(skipped, tag)),
                 },
-
-                _ => {
-                    tag.name.push(*b as char);
-                    processed += 1;
-                }
-            }
+                _ => (false, true),
+            },
+        };
+        if done {
+            self.skip = None;
         }
-
-        return processed;
+        skipped
     }
+}
 
-    fn _is_element(buf: &Vec<u8>, el: &Option<Tag>) -> Result<bool, ()> {
-        if el.is_none() {
-            return Ok(false);
-        }
-
-        let t = el.as_ref().unwrap().to_string().into_bytes();
-
-        // println!("buf: {}, tag: {}", buf.len(), t.len());
-        if buf.len() < t.len() + 3 {
-            return Err(())
-        }
-
-        let b = match buf[1] as char {
-            '/' => buf[2..2+t.len()].to_vec(),
-            _ => buf[..t.len()].to_vec()
-        };
-
-        Ok(t == b)
+// Test if the end tag end implies the end of an open element with tag,
+// eg. </ul> ends <li> and </div> ends <p>
+fn ends(tag: &Tag, end: &Tag) -> bool {
+    match *tag {
+        Tag::LI => matches!(*end, Tag::UL | Tag::OL | Tag::MENU | Tag::BODY | Tag::HTML),
+        Tag::DD | Tag::DT => matches!(*end, Tag::DL | Tag::BODY | Tag::HTML),
+        Tag::P => (end.is_flow() && end.is_block()) || matches!(*end, Tag::LI | Tag::DD | Tag::DT | Tag::TD | Tag::TH | Tag::BODY | Tag::HTML),
+        Tag::OPTION => matches!(*end, Tag::SELECT | Tag::OPTGROUP | Tag::DATALIST | Tag::BODY | Tag::HTML),
+        Tag::OPTGROUP => matches!(*end, Tag::SELECT | Tag::BODY | Tag::HTML),
+        Tag::TR => matches!(*end, Tag::TBODY | Tag::THEAD | Tag::TFOOT | Tag::TABLE | Tag::BODY | Tag::HTML),
+        Tag::TD | Tag::TH => matches!(*end, Tag::TR | Tag::TBODY | Tag::THEAD | Tag::TFOOT | Tag::TABLE | Tag::BODY | Tag::HTML),
+        Tag::THEAD | Tag::TBODY | Tag::TFOOT => matches!(*end, Tag::TABLE | Tag::BODY | Tag::HTML),
+        Tag::RT | Tag::RP => matches!(*end, Tag::RUBY | Tag::BODY | Tag::HTML),
+        _ => false,
     }
+}
 
-    // Read data until next inline element
-    fn _state_read_data(buf: &Vec<u8>, tag: &mut ParserTag, state: &mut ParserState, handler: &mut dyn IsParser) -> usize {
-        let mut processed = 0;
-
-        for b in buf {
-            match *b as char {
-                // Found begin of new tag which means we have read
-                // available data
-                '<' => {
-                    if tag.data.len() > 0 {
-                        handler.handle_data(&tag.data);
-                    }
-                    *state = ParserState::FindParserTag;
-                    break;
-                },
+// Test if the start tag next implies the end of an open element with
+// tag, eg. <li> ends <li> and <div> ends <p>
+fn implies_end(tag: &Tag, next: &Tag) -> bool {
+    match *tag {
+        Tag::LI => *next == Tag::LI,
+        Tag::DD | Tag::DT => *next == Tag::DD || *next == Tag::DT,
+        Tag::P => (next.is_flow() && next.is_block()) || matches!(*next, Tag::LI | Tag::DD | Tag::DT | Tag::FIGCAPTION | Tag::SUMMARY),
+        Tag::OPTION => *next == Tag::OPTION || *next == Tag::OPTGROUP,
+        Tag::OPTGROUP => *next == Tag::OPTGROUP,
+        Tag::TR => matches!(*next, Tag::TR | Tag::TBODY | Tag::THEAD | Tag::TFOOT),
+        Tag::TD | Tag::TH => matches!(*next, Tag::TD | Tag::TH | Tag::TR | Tag::TBODY | Tag::THEAD | Tag::TFOOT),
+        Tag::THEAD | Tag::TBODY | Tag::TFOOT => *next == Tag::TBODY || *next == Tag::TFOOT,
+        Tag::RT | Tag::RP => *next == Tag::RT || *next == Tag::RP,
+        _ => false,
+    }
+}
 
-//                '\r' | '\n' => {
-//                    processed += 1;
-//                },
+impl Parser {
 
-                _ => {
-                    tag.data.push(*b);
-                    processed += 1;
-                }
-            }
-        }
+    /// Parse a HTML document and call handlers, returns the number of
+    /// bytes parsed.
+    ///
+    /// The bytes are counted in the document decoded to UTF-8, as the
+    /// offsets of spans. For a document in another encoding or with a
+    /// byte order mark this is not an offset in the bytes read from
+    /// source.
+    pub fn parse(source: &mut dyn BufRead, handler: &mut dyn IsParser) -> Result<usize, Error> {
+        Parser::parse_with_options(source, handler, &ParserOptions::default())
+    }
 
-        return processed;
-    }
-
-    // Read data until closing element, eg <script></script>
-    fn _state_read_raw_data(buf: &Vec<u8>, tag: &mut ParserTag, state: &mut ParserState, handler: &mut dyn IsParser) -> usize {
-        let mut processed = 0;
-
-        for b in buf {
-
-            match *b as char {
-
-                // Found possible begin of closing tag
-                '<' => {
-
-                    match Parser::_is_element(&buf[processed..].to_vec(), &tag.id) {
-                        Err(_) => break,
-                        Ok(x) => {
-                            match x {
-                                true => {
-                                    // Found closing tag, lets handle data
-                                    if tag.data.len() > 0 {
-                                        handler.handle_data(&tag.data);
-                                    }
-                                    *state = ParserState::FindParserTag;
-                                    break;
-                                },
-                                false => {
-                                    // Does not match, just byte add to data
-                                    tag.data.push(*b);
-                                    processed += 1; 
-                                }
-                            }
-                        }
-                    }
-                },
+    /// Parse a HTML document using options and call handlers, returns
+    /// the number of bytes parsed as [parse](#method.parse).
+    pub fn parse_with_options(source: &mut dyn BufRead, handler: &mut dyn IsParser, options: &ParserOptions) -> Result<usize, Error> {
+        let mut tokenizer = Tokenizer::with_options(source, options);
+        handler.handle_encoding(tokenizer.encoding());
 
-                _ => {
-                    tag.data.push(*b);
-                    processed += 1;
-                }
+        let mut control = Control::default();
+        for token in tokenizer.by_ref() {
+            if !control.handle(token?, handler) {
+                break;
             }
         }
+        handler.handle_eof();
 
-        return processed;
-    }
-
-
-    fn _state_read_attribute_value(buf: &Vec<u8>, tag: &mut ParserTag, state: &mut ParserState, handler: &mut dyn IsParser ) -> usize {
-        let mut processed = 0;
-        for b in buf {
-            match *b as char {
-                '>' => {
-                    // pop last attribute if it is an empty placeholder
-                    if tag.attributes.last().as_ref().unwrap().name() == "" {
-                        tag.attributes.pop();
-                    }
-
-                    tag.id = Some(tag.name
-                        .to_lowercase()
-                        .parse::<Tag>().unwrap());
-
-                    *state = match tag.closing {
-                        false => {
-                            handler.handle_starttag(tag.id.as_ref().unwrap(), &tag.attributes);
-                            match *tag.id.as_ref().unwrap() {
-                                Tag::SCRIPT => ParserState::ReadRawData,
-                                Tag::STYLE => ParserState::ReadRawData,
-                                _ => ParserState::ReadData,
-                            }
-                        },
-                        true => {
-                            handler.handle_endtag(tag.id.as_ref().unwrap());
-                            ParserState::ReadData
-                        }
-                    };
-                    tag.attributes.clear();
-                    processed += 1;
-                    break;
-                },
-
-                '"' | '\'' => {
-
-                    let have_value = !(tag.attributes.last().as_mut().unwrap().value() == "");
-
-                    if have_value && *b != ' ' as u8 {
-
-                        {
-                            // Trim " and ' from attribute value
-                            let ref mut value = tag.attributes.last_mut().unwrap().value;
-                            if value.len() != 0 && (value[0] == '\'' as u8 || value[0] == '"' as u8) {
-                                *value = value[1..].to_vec();
-                            }
-
-                            if value.len() != 0 && (value[value.len() - 1] == '\'' as u8 || value[value.len() - 1] == '"' as u8) {
-                                *value = value[..value.len() - 1].to_vec();
-                            }
-
-                        }
-
-                        tag.attributes.push(Attribute{name: Vec::new(), value: Vec::new()});
-                        *state = ParserState::ReadAttributeName;
-                        processed += 1;
-                        break;
-
-                    } else {
-
-                        let ref mut value = tag.attributes.last_mut().unwrap().value;
-                        value.push(*b);
-                        processed += 1;
-                    }
-                },
-
-                ' ' => {
-
-                    let (have_value, is_quoted) = {
-                        let ref mut value = tag.attributes.last_mut().unwrap().value;
-                        match value.is_empty() {
-                            true => (false, false),
-                            false => (true, (value[0] == '"' as u8 || value[0] == '\'' as u8)),
-                        }
-                    };
-
-                    if have_value && !is_quoted {
+        Ok(control.stopped.unwrap_or(tokenizer.parsed()))
+    }
 
-                        tag.attributes.push(Attribute{name: Vec::new(), value: Vec::new()});
-                        *state = ParserState::ReadAttributeName;
-                        processed += 1;
-                        break;
+    /// Parse a HTML document read asynchronously and call handlers.
+    ///
+    /// Only available with the ```tokio``` feature.
+    #[cfg(feature = "tokio")]
+    pub fn parse_async<'a, R>(source: &'a mut R, handler: &'a mut dyn IsParser) -> impl Future<Output = Result<usize, Error>> + 'a
+        where R: AsyncBufRead + Unpin + ?Sized
+    {
+        Parser::parse_async_with_options(source, handler, &ParserOptions::default())
+    }
 
-                    } else {
+    /// Parse a HTML document read asynchronously using options and
+    /// call handlers.
+    ///
+    /// Only available with the ```tokio``` feature.
+    #[cfg(feature = "tokio")]
+    pub fn parse_async_with_options<'a, R>(source: &'a mut R, handler: &'a mut dyn IsParser, options: &ParserOptions) -> impl Future<Output = Result<usize, Error>> + 'a
+        where R: AsyncBufRead + Unpin + ?Sized
+    {
+        let mut parser = Parser::with_options(options);
+        poll_fn(move |cx| parser.poll_parse(&mut *source, &mut *handler, cx))
+    }
 
-                        let ref mut value = tag.attributes.last_mut().unwrap().value;
-                        value.push(*b);
-                        processed += 1;
-                    }
-                }
+    /// Feed what can be read from source to the parser and finish the
+    /// document at the end of source, which returns the number of bytes
+    /// parsed.
+    ///
+    /// This is the building block of [parse_async](#method.parse_async)
+    /// for use in hand written futures. Only available with the
+    /// ```tokio``` feature.
+    #[cfg(feature = "tokio")]
+    pub fn poll_parse<R>(&mut self, source: &mut R, handler: &mut dyn IsParser, cx: &mut Context) -> Poll<Result<usize, Error>>
+        where R: AsyncBufRead + Unpin + ?Sized
+    {
+        loop {
+            let length = {
+                let chunk = match Pin::new(&mut *source).poll_fill_buf(cx) {
+                    Poll::Ready(Ok(x)) => x,
+                    Poll::Ready(Err(x)) => return Poll::Ready(Err(Error::Io(x))),
+                    Poll::Pending => return Poll::Pending,
+                };
 
-                _ => {
-                    let ref mut value = tag.attributes.last_mut().unwrap().value;
-                    value.push(*b);
-                    processed += 1;
+                if chunk.is_empty() {
+                    return Poll::Ready(Ok(self.finish(handler)));
                 }
+                self.feed(chunk, handler);
+                chunk.len()
+            };
+            Pin::new(&mut *source).consume(length);
+
+            // Nothing more of source is needed once stopped
+            if self.is_stopped() {
+                return Poll::Ready(Ok(self.finish(handler)));
             }
         }
-        return processed;
     }
 
-    fn _state_read_attribute_name(buf: &Vec<u8>, tag: &mut ParserTag, state: &mut ParserState, handler: &mut dyn IsParser) -> usize {
-        let mut processed = 0;
-
-        for b in buf {
-
-            match *b as char {
-
-                // Found closing, lets finish up
-                '>' | '/' => {
-
-                    {
-                        if tag.attributes.last().unwrap().name.is_empty() {
-                            // pop last attribute if it is an empty placeholder
-                            tag.attributes.pop();
-                        }
-                    }
+    /// Create a parser for a document fed in chunks.
+    pub fn new() -> Parser {
+        Parser::with_options(&ParserOptions::default())
+    }
 
-                    match tag.name.to_lowercase().parse::<Tag>() {
-                        Ok(x) => tag.id = Some(x),
-                        Err(_) => panic!("Failed to parse element '{}' to enum", tag.name),
-                    }
+    /// Create a parser using options for a document fed in chunks.
+    pub fn with_options(options: &ParserOptions) -> Parser {
+        Parser {
+            options: options.clone(),
+            decoder: Parser::_decoder(options),
+            decoded: Vec::new(),
+            scanner: Scanner::new(),
+            started: false,
+            control: Control::default(),
+        }
+    }
 
-                    *state = match tag.closing {
-                        false => {
-                            handler.handle_starttag(tag.id.as_ref().unwrap(), &tag.attributes);
-                            match *tag.id.as_ref().unwrap() {
-                                Tag::SCRIPT => ParserState::ReadRawData,
-                                _ => ParserState::ReadData,
-                            }
-                        },
-                        true => {
-                            handler.handle_endtag(tag.id.as_ref().unwrap());
-                            ParserState::ReadData
-                        }
-                    };
+    /// Feed the next chunk of the document and call handlers for what
+    /// could be parsed.
+    ///
+    /// A token not complete at the end of the chunk is kept until the
+    /// rest of it is fed. When the encoding is detected the start of
+    /// the document is kept until enough of it is fed to detect it.
+    pub fn feed(&mut self, chunk: &[u8], handler: &mut dyn IsParser) {
+        self._feed(chunk, false, handler);
+    }
 
-                    processed += 1;
-                    break;
-                },
+    /// Test if the handler stopped parsing, chunks fed after are
+    /// ignored.
+    pub fn is_stopped(&self) -> bool {
+        self.control.stopped.is_some()
+    }
 
-                '=' => {
-                    *state = ParserState::ReadAttributeValue;
-                    processed += 1;
+    /// Finish the document, call handlers for what is left and return
+    /// the number of bytes parsed, counted as by [parse](#method.parse).
+    ///
+    /// The parser is reset and can be used for another document.
+    pub fn finish(&mut self, handler: &mut dyn IsParser) -> usize {
+        self._feed(&[], true, handler);
+        if !self.is_stopped() {
+            self.scanner.run(&self.options, true);
+            self.scanner.finish(&self.options);
+            while let Some(token) = self.scanner.next_token() {
+                if !self.control.handle(token, handler) {
                     break;
-                },
-
-                ' ' => {
-                    if !tag.attributes.last().unwrap().name.is_empty() {
-                        tag.attributes.push(Attribute{name: Vec::new(), value: Vec::new()});
-                        *state = ParserState::ReadAttributeName;
-                    }
-
-                    processed += 1;
-                },
-
-                '\n' | '\r' | '\t' => {
-                    processed += 1;
-                },
-
-                _ => {
-                    let ref mut name = tag.attributes.last_mut().unwrap().name;
-                    name.push(*b);
-                    processed += 1;
                 }
             }
         }
+        handler.handle_eof();
+
+        let parsed = self.control.stopped.unwrap_or(self.scanner.parsed());
+        self.decoder = Parser::_decoder(&self.options);
+        self.scanner = Scanner::new();
+        self.started = false;
+        self.control = Control::default();
+        parsed
+    }
 
-        return processed;
+    fn _decoder(options: &ParserOptions) -> Option<ChunkDecoder> {
+        match options.detect_encoding {
+            true => Some(ChunkDecoder::new(options.transport_encoding)),
+            false => None,
+        }
     }
 
-    /// Parse a HTML document and call handlers.
-    pub fn parse(source: &mut dyn BufRead, handler: &mut dyn IsParser) -> Result<usize, std::io::Error> {
-        let mut total_parsed = 0;
-        let mut state = ParserState::FindParserTag;
+    // Decode chunk and pass it block by block through the scanner
+    fn _feed(&mut self, chunk: &[u8], last: bool, handler: &mut dyn IsParser) {
+        if self.is_stopped() {
+            return;
+        }
 
-        let mut tag = ParserTag {
-            name: "".to_string(),
-            id: None,
-            closing: false,
-            data: Vec::new(),
-            attributes: Vec::new()
+        self.decoded.clear();
+        let (data, encoding) = match self.decoder {
+            Some(ref mut x) => {
+                x.decode(chunk, last, &mut self.decoded);
+                (&self.decoded[..], x.encoding())
+            },
+            None => (chunk, Some(UTF_8)),
         };
 
-
-        let mut buf = Vec::new();
-        let mut end_of_file = false;
-        loop {
-
-            // If buffer is low and there is still data to be read, read block
-            if !end_of_file && buf.len() < 64 {
-                let mut block = [0; 2048];
-                let bytes_read = match source.read(&mut block[..]) {
-                    Ok(x) => x,
-                    Err(x) => return Err(x)
-                };
-
-                match bytes_read {
-                    0 => end_of_file = true,
-                    _ => {
-                        let size = buf.len();
-                        buf.extend_from_slice(&block);
-                        buf = buf[..size+bytes_read].to_vec();
-                    }
-                };
+        if !self.started {
+            match encoding {
+                Some(x) => handler.handle_encoding(x),
+                // Wait for enough of the document to detect the encoding
+                None => return,
             }
+            self.started = true;
+        }
 
-            // Break out of loop if buffer is empty
-            if buf.len() == 0 {
-                break;
-            }
-
-            loop {
-                let processed = match state {
-                    ParserState::FindParserTag => Parser::_state_find_tag(&buf, &mut tag, &mut state),
-                    ParserState::SkipComment => Parser::_state_skip_comment(&buf, &mut state),
-                    ParserState::ReadParserTagName => Parser::_state_read_tag_name(&buf, &mut tag, &mut state),
-                    ParserState::ReadData => Parser::_state_read_data(&buf, &mut tag, &mut state, handler),
-                    ParserState::ReadRawData => Parser::_state_read_raw_data(&buf, &mut tag, &mut state, handler),
-                    ParserState::ReadAttributeName => Parser::_state_read_attribute_name(&buf, &mut tag, &mut state, handler),
-                    ParserState::ReadAttributeValue => Parser::_state_read_attribute_value(&buf, &mut tag, &mut state, handler),
-                };
-
-                if processed == 0 {
-                    break;
-                }
-
-                buf.drain(..processed);
-                total_parsed += processed;
-
-                if buf.len() == 0 {
-                    break;
+        for block in data.chunks(BLOCK_SIZE) {
+            self.scanner.push(block);
+            self.scanner.run(&self.options, false);
+            while let Some(token) = self.scanner.next_token() {
+                if !self.control.handle(token, handler) {
+                    return;
                 }
             }
         }
+    }
+}
 
-        Ok(total_parsed)
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
     }
 }
 
@@ -495,18 +637,38 @@ impl Parser {
 mod tests {
     use attribute::Attribute;
     use tag::Tag;
-    use parser::{IsParser};
+    use doctype::Doctype;
+    use position::Span;
+    use parser::{IsParser, ParserOptions, TokenizerMode, Parser, Flow};
+    use error::ParseError;
+    use encoding_rs::{Encoding, WINDOWS_1252};
     use std::io::BufReader;
+    #[cfg(feature = "tokio")]
+    use std::future::Future;
+    #[cfg(feature = "tokio")]
+    use std::pin::Pin;
+    #[cfg(feature = "tokio")]
+    use std::task::{Context, Poll, Waker};
+    #[cfg(feature = "tokio")]
+    use tokio::io::{AsyncRead, AsyncBufRead, ReadBuf};
 
     struct TestTag {
         tag: Tag,
-        attributes: Vec<Attribute>
+        attributes: Vec<Attribute>,
+        self_closing: bool,
+        span: Span,
     }
 
     struct Dummy {
         starttag: Vec<TestTag>,
         endtag: Vec<TestTag>,
         data: Vec<Vec<u8>>,
+        data_spans: Vec<Span>,
+        comments: Vec<Vec<u8>>,
+        doctypes: Vec<Doctype>,
+        cdata: Vec<Vec<u8>>,
+        instructions: Vec<Vec<u8>>,
+        errors: Vec<(ParseError, Span)>,
     }
 
     impl Dummy {
@@ -515,25 +677,144 @@ mod tests {
                 starttag: Vec::new(),
                 endtag: Vec::new(),
                 data: Vec::new(),
+                data_spans: Vec::new(),
+                comments: Vec::new(),
+                doctypes: Vec::new(),
+                cdata: Vec::new(),
+                instructions: Vec::new(),
+                errors: Vec::new(),
             }
         }
     }
 
     impl IsParser for Dummy {
-        fn handle_starttag(self: &mut Self, tag: &Tag, attributes: &Vec<Attribute>) {
+        fn handle_starttag(&mut self, tag: &Tag, attributes: &Vec<Attribute>, self_closing: bool, span: &Span) -> Flow {
             self.starttag.push(TestTag{
                 tag: tag.clone(),
                 attributes: attributes.clone(),
+                self_closing,
+                span: *span,
             });
+            Flow::Continue
         }
 
-        fn handle_endtag(self: &mut Self, tag: &Tag) {
-            self.endtag.push(TestTag{tag: tag.clone(), attributes: Vec::new()});
+        fn handle_endtag(&mut self, tag: &Tag, span: &Span) -> Flow {
+            self.endtag.push(TestTag{tag: tag.clone(), attributes: Vec::new(), self_closing: false, span: *span});
+            Flow::Continue
         }
 
-        fn handle_data(self: &mut Self, data: &Vec<u8>) {
+        fn handle_data(&mut self, data: &Vec<u8>, span: &Span) -> Flow {
             self.data.push(data.clone());
+            self.data_spans.push(*span);
+            Flow::Continue
+        }
+
+        fn handle_comment(&mut self, comment: &Vec<u8>, _span: &Span) -> Flow {
+            self.comments.push(comment.clone());
+            Flow::Continue
+        }
+
+        fn handle_doctype(&mut self, doctype: &Doctype, _span: &Span) -> Flow {
+            self.doctypes.push(doctype.clone());
+            Flow::Continue
+        }
+
+        fn handle_cdata(&mut self, data: &Vec<u8>, _span: &Span) -> Flow {
+            self.cdata.push(data.clone());
+            Flow::Continue
+        }
+
+        fn handle_processing_instruction(&mut self, instruction: &Vec<u8>, _span: &Span) -> Flow {
+            self.instructions.push(instruction.clone());
+            Flow::Continue
+        }
+
+        fn handle_error(&mut self, error: ParseError, span: &Span) -> Flow {
+            self.errors.push((error, *span));
+            Flow::Continue
+        }
+    }
+
+    // Records every callback, for comparing how documents are parsed
+    struct Events(Vec<String>);
+
+    impl IsParser for Events {
+        fn handle_starttag(&mut self, tag: &Tag, attributes: &Vec<Attribute>, self_closing: bool, span: &Span) -> Flow {
+            self.0.push(format!("start {} {:?} {} {}", tag, attributes, self_closing, span));
+            Flow::Continue
+        }
+
+        fn handle_endtag(&mut self, tag: &Tag, span: &Span) -> Flow {
+            self.0.push(format!("end {} {}", tag, span));
+            Flow::Continue
+        }
+
+        fn handle_data(&mut self, data: &Vec<u8>, span: &Span) -> Flow {
+            self.0.push(format!("data {:?} {}", String::from_utf8_lossy(data), span));
+            Flow::Continue
+        }
+
+        fn handle_comment(&mut self, comment: &Vec<u8>, span: &Span) -> Flow {
+            self.0.push(format!("comment {:?} {}", String::from_utf8_lossy(comment), span));
+            Flow::Continue
+        }
+
+        fn handle_error(&mut self, error: ParseError, span: &Span) -> Flow {
+            self.0.push(format!("error {} {}", error, span));
+            Flow::Continue
+        }
+
+        fn handle_encoding(&mut self, encoding: &'static Encoding) {
+            self.0.push(format!("encoding {}", encoding.name()));
+        }
+    }
+
+    // Records start and end tags and data, skipping the content of one
+    // tag and stopping at the end tag of another
+    struct Flows {
+        events: Vec<String>,
+        skip: Tag,
+        stop: Tag,
+        eof: bool,
+    }
+
+    impl Flows {
+        fn new(skip: Tag, stop: Tag) -> Flows {
+            Flows { events: Vec::new(), skip, stop, eof: false }
+        }
+    }
+
+    impl IsParser for Flows {
+        fn handle_starttag(&mut self, tag: &Tag, _attributes: &Vec<Attribute>, _self_closing: bool, _span: &Span) -> Flow {
+            self.events.push(format!("<{}>", tag));
+            match *tag == self.skip {
+                true => Flow::Skip,
+                false => Flow::Continue,
+            }
+        }
+
+        fn handle_endtag(&mut self, tag: &Tag, _span: &Span) -> Flow {
+            self.events.push(format!("</{}>", tag));
+            match *tag == self.stop {
+                true => Flow::Stop,
+                false => Flow::Continue,
+            }
         }
+
+        fn handle_data(&mut self, data: &Vec<u8>, _span: &Span) -> Flow {
+            self.events.push(String::from_utf8_lossy(data).into_owned());
+            Flow::Continue
+        }
+
+        fn handle_eof(&mut self) {
+            self.eof = true;
+        }
+    }
+
+    fn parse_errors(data: &str) -> Vec<&'static str> {
+        let mut p = Dummy::new();
+        ::Parser::parse(&mut BufReader::new(data.as_bytes()), &mut p).unwrap();
+        p.errors.iter().map(|x| x.0.code()).collect()
     }
 
 
@@ -568,10 +849,11 @@ mod tests {
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 186);
         assert_eq!(p.starttag.len(), 6);
         assert_eq!(p.endtag.len(), 6);
-        assert_eq!(p.data.len(), 4);
+        assert_eq!(p.data.len(), 3);
         assert_eq!(p.starttag[5].attributes.len(), 1);
-        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "Simple");
-        assert_eq!(String::from_utf8(p.data[1].clone()).unwrap(), " Example");
+        // The content of <title> is text, a comment in it is not parsed
+        assert_eq!(p.comments.len(), 0);
+        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "Simple<!-- title --> Example");
     }
 
     #[test]
@@ -583,6 +865,37 @@ mod tests {
         assert_eq!(p.endtag.len(), 6);
         assert_eq!(p.data.len(), 5);
         assert_eq!(p.starttag[5].attributes.len(), 1);
+        assert_eq!(p.comments.len(), 1);
+        assert_eq!(String::from_utf8(p.comments[0].clone()).unwrap(), " set a title ");
+    }
+
+    #[test]
+    fn parse_document_with_empty_comment() {
+        let mut p = Dummy::new();
+        let data = b"<p>a<!---->b</p>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.comments.len(), 1);
+        assert_eq!(p.comments[0].len(), 0);
+        assert_eq!(p.data.len(), 2);
+    }
+
+    #[test]
+    fn parse_document_with_unterminated_comment() {
+        let mut p = Dummy::new();
+        let data = b"<p>a</p><!-- never closed";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.endtag.len(), 1);
+        assert_eq!(p.comments.len(), 0);
+    }
+
+    #[test]
+    fn parse_document_skipping_comments() {
+        let mut p = Dummy::new();
+        let data = b"<p><!-- skip me -->Hello</p>";
+        let options = ParserOptions { skip_comments: true, ..Default::default() };
+        ::Parser::parse_with_options(&mut BufReader::new(&data[..]), &mut p, &options).unwrap();
+        assert_eq!(p.comments.len(), 0);
+        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "Hello");
     }
 
     #[test]
@@ -599,8 +912,8 @@ mod tests {
         let data = "<p id='💖'>Sparkle heart</p>".to_string().into_bytes();
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 30);
         assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "Sparkle heart");
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "💖");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "💖");
     }
 
     #[test]
@@ -608,8 +921,8 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p id=1>Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 23);
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "1");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "1");
     }
 
     #[test]
@@ -617,8 +930,8 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p id=\"1\">Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 25);
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "1");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "1");
     }
 
     #[test]
@@ -626,8 +939,8 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p id='1'>Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 25);
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "1");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "1");
     }
 
     #[test]
@@ -635,8 +948,8 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p class=\"info error\">Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 37);
-        assert_eq!(p.starttag[0].attributes[0].name(), "class");
-        assert_eq!(p.starttag[0].attributes[0].value(), "info error");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "class");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "info error");
     }
 
     #[test]
@@ -644,8 +957,8 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p class='info error'>Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 37);
-        assert_eq!(p.starttag[0].attributes[0].name(), "class");
-        assert_eq!(p.starttag[0].attributes[0].value(), "info error");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "class");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "info error");
     }
 
     #[test]
@@ -653,10 +966,10 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p id=test class=info>Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 37);
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "test");
-        assert_eq!(p.starttag[0].attributes[1].name(), "class");
-        assert_eq!(p.starttag[0].attributes[1].value(), "info");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "test");
+        assert_eq!(p.starttag[0].attributes[1].name().unwrap(), "class");
+        assert_eq!(p.starttag[0].attributes[1].value().unwrap(), "info");
     }
 
     #[test]
@@ -664,8 +977,8 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p id=test >Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 27);
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "test");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "test");
     }
 
     #[test]
@@ -673,10 +986,10 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p id=\"myid\" class='info'>Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 41);
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "myid");
-        assert_eq!(p.starttag[0].attributes[1].name(), "class");
-        assert_eq!(p.starttag[0].attributes[1].value(), "info");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "myid");
+        assert_eq!(p.starttag[0].attributes[1].name().unwrap(), "class");
+        assert_eq!(p.starttag[0].attributes[1].value().unwrap(), "info");
     }
 
     #[test]
@@ -684,10 +997,10 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<p id=\"myid\" \n\t class='info'>Hello world</p>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 44);
-        assert_eq!(p.starttag[0].attributes[0].name(), "id");
-        assert_eq!(p.starttag[0].attributes[0].value(), "myid");
-        assert_eq!(p.starttag[0].attributes[1].name(), "class");
-        assert_eq!(p.starttag[0].attributes[1].value(), "info");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "myid");
+        assert_eq!(p.starttag[0].attributes[1].name().unwrap(), "class");
+        assert_eq!(p.starttag[0].attributes[1].value().unwrap(), "info");
     }
 
     #[test]
@@ -695,7 +1008,7 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<option selected>Hello world</option>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 37);
-        assert_eq!(p.starttag[0].attributes[0].name(), "selected");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "selected");
         assert_eq!(p.starttag[0].attributes[0].is_boolean(), true);
     }
 
@@ -704,7 +1017,7 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<option selected >Hello world</option>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 38);
-        assert_eq!(p.starttag[0].attributes[0].name(), "selected");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "selected");
         assert_eq!(p.starttag[0].attributes[0].is_boolean(), true);
     }
 
@@ -713,10 +1026,458 @@ mod tests {
         let mut p = Dummy::new();
         let data = b"<option selected id=\"myid\">Hello world</option>";
         assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 47);
-        assert_eq!(p.starttag[0].attributes[0].name(), "selected");
+        assert_eq!(p.starttag[0].attributes[0].name().unwrap(), "selected");
         assert_eq!(p.starttag[0].attributes[0].is_boolean(), true);
-        assert_eq!(p.starttag[0].attributes[1].name(), "id");
-        assert_eq!(p.starttag[0].attributes[1].value(), "myid");
+        assert_eq!(p.starttag[0].attributes[1].name().unwrap(), "id");
+        assert_eq!(p.starttag[0].attributes[1].value().unwrap(), "myid");
+    }
+
+    #[test]
+    fn parse_self_closing_tags() {
+        let mut p = Dummy::new();
+        let data = b"<p>a<br/>b<img src=\"x\" />c<hr>d</p>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.starttag.len(), 4);
+        assert_eq!(p.endtag.len(), 1);
+        assert_eq!(p.starttag[1].tag, Tag::BR);
+        assert!(p.starttag[1].self_closing);
+        assert_eq!(p.starttag[2].attributes[0].value().unwrap(), "x");
+        assert!(p.starttag[2].self_closing);
+        assert!(!p.starttag[3].self_closing);
+        assert_eq!(p.data.len(), 4);
+    }
+
+    #[test]
+    fn parse_raw_text_elements() {
+        let mut p = Dummy::new();
+        let data = b"<script>if (a<b && c</scripts) {}</SCRIPT ><style>p > b {}</style id=x>\
+            <xmp><b>&amp;</b></xmp><textarea><p>&amp;</p></TextArea><title>a<b</title/>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.starttag.len(), 5);
+        assert_eq!(p.endtag.len(), 5);
+        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "if (a<b && c</scripts) {}");
+        assert_eq!(String::from_utf8(p.data[1].clone()).unwrap(), "p > b {}");
+        assert_eq!(String::from_utf8(p.data[2].clone()).unwrap(), "<b>&amp;</b>");
+        assert_eq!(String::from_utf8(p.data[3].clone()).unwrap(), "<p>&</p>");
+        assert_eq!(String::from_utf8(p.data[4].clone()).unwrap(), "a<b");
+        assert_eq!(p.endtag[0].tag, Tag::SCRIPT);
+        assert_eq!(p.endtag[3].tag, Tag::TEXTAREA);
+    }
+
+    #[test]
+    fn parse_plaintext_to_end_of_document() {
+        let mut p = Dummy::new();
+        let data = b"<p>a</p><plaintext><b>&amp;</plaintext></p>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.starttag.len(), 2);
+        assert_eq!(p.endtag.len(), 1);
+        assert_eq!(String::from_utf8(p.data[1].clone()).unwrap(), "<b>&amp;</plaintext></p>");
+    }
+
+    #[test]
+    fn parse_data_with_character_references() {
+        let mut p = Dummy::new();
+        let data = b"<p>Fish &amp; chips &lt;3 &#x1F496;&nbsp;&copy</p>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "Fish & chips <3 💖\u{a0}©");
+    }
+
+    #[test]
+    fn parse_attribute_with_character_references() {
+        let mut p = Dummy::new();
+        let data = b"<a href=\"?a=1&amp;b=2&copy=3\" title='&quot;x&quot;'>link</a>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "?a=1&b=2&copy=3");
+        assert_eq!(p.starttag[0].attributes[1].value().unwrap(), "\"x\"");
+    }
+
+    #[test]
+    fn parse_without_decoding_character_references() {
+        let mut p = Dummy::new();
+        let data = b"<p title=\"&amp;\">&lt;raw&gt;</p>";
+        let options = ParserOptions { decode_entities: false, ..Default::default() };
+        ::Parser::parse_with_options(&mut BufReader::new(&data[..]), &mut p, &options).unwrap();
+        assert_eq!(p.starttag[0].attributes[0].value().unwrap(), "&amp;");
+        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "&lt;raw&gt;");
+    }
+
+    #[test]
+    fn parse_document_with_doctype() {
+        let mut p = Dummy::new();
+        let data = b"<!DOCTYPE html>\n<html><p>Hello</p></html>";
+        assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 41);
+        assert_eq!(p.doctypes.len(), 1);
+        assert_eq!(p.doctypes[0].name(), Some("html"));
+        assert_eq!(p.starttag[0].tag, Tag::HTML);
+        assert_eq!(p.data.len(), 2);
+    }
+
+    #[test]
+    fn parse_document_with_lowercase_legacy_doctype() {
+        let mut p = Dummy::new();
+        let data = b"<!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\"><p>Hello</p>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.doctypes.len(), 1);
+        assert_eq!(p.doctypes[0].name(), Some("html"));
+        assert_eq!(p.doctypes[0].public_id(), Some("-//W3C//DTD HTML 4.01//EN"));
+        assert_eq!(p.starttag[0].tag, Tag::P);
+    }
+
+    #[test]
+    fn parse_document_with_cdata_section() {
+        let mut p = Dummy::new();
+        let data = b"<svg><desc>a &amp; b</desc><text><![CDATA[<x>&amp;]]></text></svg>";
+        assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), 66);
+        assert_eq!(p.cdata.len(), 1);
+        assert_eq!(String::from_utf8(p.cdata[0].clone()).unwrap(), "<x>&amp;");
+        assert_eq!(p.starttag.len(), 3);
+        assert_eq!(p.endtag.len(), 3);
+    }
+
+    #[test]
+    fn parse_document_with_processing_instruction() {
+        let mut p = Dummy::new();
+        let data = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<html><p>Hello</p></html>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.instructions.len(), 1);
+        assert_eq!(String::from_utf8(p.instructions[0].clone()).unwrap(), "xml version=\"1.0\" encoding=\"UTF-8\"");
+        assert_eq!(p.starttag[0].tag, Tag::HTML);
+        assert_eq!(p.starttag.len(), 2);
+    }
+
+    #[test]
+    fn parse_cdata_section_as_data_by_default() {
+        struct Text(Vec<u8>);
+        impl IsParser for Text {
+            fn handle_starttag(&mut self, _tag: &Tag, _attributes: &Vec<Attribute>, _self_closing: bool, _span: &Span) -> Flow { Flow::Continue }
+            fn handle_endtag(&mut self, _tag: &Tag, _span: &Span) -> Flow { Flow::Continue }
+            fn handle_data(&mut self, data: &Vec<u8>, _span: &Span) -> Flow {
+                self.0.extend_from_slice(data);
+                Flow::Continue
+            }
+        }
+
+        let mut p = Text(Vec::new());
+        let data = b"<p>a<![CDATA[<b>]]>c</p>";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(String::from_utf8(p.0).unwrap(), "a<b>c");
+    }
+
+    #[test]
+    fn parse_document_with_positions() {
+        let mut p = Dummy::new();
+        let data = "<html>\n  <p class=\"info\" hidden>💖 text</p>\n</html>".to_string().into_bytes();
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+
+        let span = p.starttag[1].span;
+        assert_eq!((span.start.offset, span.start.line, span.start.column), (9, 2, 3));
+        assert_eq!((span.end.offset, span.end.line, span.end.column), (32, 2, 26));
+
+        let span = p.starttag[1].attributes[0].span;
+        assert_eq!(&data[span.start.offset..span.end.offset], b"class=\"info\"");
+        assert_eq!((span.start.line, span.start.column), (2, 6));
+
+        let span = p.starttag[1].attributes[1].span;
+        assert_eq!(&data[span.start.offset..span.end.offset], b"hidden");
+
+        let span = p.data_spans[1];
+        assert_eq!(&data[span.start.offset..span.end.offset], "💖 text".as_bytes());
+        assert_eq!((span.end.line, span.end.column), (2, 32));
+
+        let span = p.endtag[1].span;
+        assert_eq!(&data[span.start.offset..span.end.offset], b"</html>");
+        assert_eq!((span.start.line, span.start.column), (3, 1));
+    }
+
+    #[test]
+    fn parse_positions_across_buffer_boundaries() {
+        let mut p = Dummy::new();
+        let mut data = Vec::new();
+        for _ in 0..500 {
+            data.extend_from_slice(b"<p id=\"a\">line</p>\n");
+        }
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.starttag.len(), 500);
+        for (i, tag) in p.starttag.iter().enumerate() {
+            assert_eq!(tag.span.start.offset, i * 19);
+            assert_eq!((tag.span.start.line, tag.span.start.column), (i + 1, 1));
+            assert_eq!(&data[tag.span.start.offset..tag.span.end.offset], b"<p id=\"a\">");
+            let attr = tag.attributes[0].span;
+            assert_eq!(&data[attr.start.offset..attr.end.offset], b"id=\"a\"");
+        }
+    }
+
+    #[test]
+    fn parse_errors_in_tags() {
+        assert_eq!(parse_errors("<p id=\"a\" class=x ID=b>"), vec!["duplicate-attribute"]);
+        assert_eq!(parse_errors("<p></p id=a>"), vec!["end-tag-with-attributes"]);
+        assert_eq!(parse_errors("<p id=>"), vec!["missing-attribute-value"]);
+        assert_eq!(parse_errors("a <3 b>"), vec!["invalid-first-character-of-tag-name"]);
+        assert_eq!(parse_errors("a < b>"), vec!["invalid-first-character-of-tag-name"]);
+        assert_eq!(parse_errors("</>"), vec!["missing-end-tag-name"]);
+        assert_eq!(parse_errors("<?php echo 1 ?>"), vec!["unexpected-question-mark-instead-of-tag-name"]);
+        assert_eq!(parse_errors("<!x>"), vec!["incorrectly-opened-comment"]);
+        assert_eq!(parse_errors("<p a/b>"), vec!["unexpected-solidus-in-tag"]);
+        assert_eq!(parse_errors("<br/></p/>"), vec!["end-tag-with-trailing-solidus"]);
+    }
+
+    #[test]
+    fn parse_errors_at_end_of_file() {
+        assert_eq!(parse_errors("<p>text<!-- comment"), vec!["eof-in-comment"]);
+        assert_eq!(parse_errors("<!DOCTYPE html"), vec!["eof-in-doctype"]);
+        assert_eq!(parse_errors("<![CDATA[x"), vec!["eof-in-cdata"]);
+        assert_eq!(parse_errors("<p class=\"x"), vec!["eof-in-tag"]);
+        assert_eq!(parse_errors("<p"), vec!["eof-in-tag"]);
+        assert_eq!(parse_errors("text<"), vec!["eof-before-tag-name"]);
+    }
+
+    #[test]
+    fn parse_error_span() {
+        let mut p = Dummy::new();
+        let data = "<p>\n<b id=1 id=2>".to_string().into_bytes();
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(p.errors.len(), 1);
+        let span = p.errors[0].1;
+        assert_eq!(&data[span.start.offset..span.end.offset], b"id=2");
+        assert_eq!((span.start.line, span.start.column), (2, 9));
+        assert_eq!(p.starttag[1].attributes.len(), 1);
+        assert_eq!(p.starttag[1].attributes[0].value().unwrap(), "1");
+    }
+
+    #[test]
+    fn parse_text_at_end_of_document() {
+        let mut p = Dummy::new();
+        let data = b"<p>Hello</p> world &amp; all";
+        ::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap();
+        assert_eq!(String::from_utf8(p.data[1].clone()).unwrap(), " world & all");
+        assert_eq!(&data[p.data_spans[1].start.offset..p.data_spans[1].end.offset], b" world &amp; all");
+
+        let mut p = Dummy::new();
+        ::Parser::parse(&mut BufReader::new(&b"<script>if (a <b"[..]), &mut p).unwrap();
+        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "if (a <b");
+    }
+
+    #[test]
+    fn feed_document_in_chunks() {
+        let data = "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><!-- a comment --></head>\
+            <body><p class=\"info\" id=x>Sparkle 💖 &amp; heart</p><br/><script>if (a < b) {}</script>\
+            <![CDATA[raw]]><?pi x?></body></html> trailing";
+
+        let mut expected = Events(Vec::new());
+        ::Parser::parse(&mut BufReader::new(data.as_bytes()), &mut expected).unwrap();
+
+        for size in 1..data.len() + 1 {
+            let mut events = Events(Vec::new());
+            let mut parser = Parser::new();
+            for chunk in data.as_bytes().chunks(size) {
+                parser.feed(chunk, &mut events);
+            }
+            assert_eq!(parser.finish(&mut events), data.len());
+            assert_eq!(events.0, expected.0, "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn feed_document_in_chunks_with_whatwg_tokenizer() {
+        let data = "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\">\r\n<p class=a\r\nid = 'b'>\
+            a < b &notin; &amp &#x1F496 <!-- c --!> <? pi ?></p ><script><!--<script></script>-->x</script>";
+        let options = ParserOptions { tokenizer: TokenizerMode::Whatwg, ..Default::default() };
+
+        let mut expected = Events(Vec::new());
+        ::Parser::parse_with_options(&mut BufReader::new(data.as_bytes()), &mut expected, &options).unwrap();
+        assert!(expected.0.iter().any(|x| x.starts_with("data \"a < b ∉ & 💖 \"")), "{:?}", expected.0);
+
+        for size in 1..data.len() + 1 {
+            let mut events = Events(Vec::new());
+            let mut parser = Parser::with_options(&options);
+            for chunk in data.as_bytes().chunks(size) {
+                parser.feed(chunk, &mut events);
+            }
+            assert_eq!(parser.finish(&mut events), data.len());
+            assert_eq!(events.0, expected.0, "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn feed_document_with_detected_encoding() {
+        let mut data = b"<meta charset=\"windows-1252\"><p>\xe5</p>".to_vec();
+        data.extend_from_slice(&[b' '; 2000]);
+
+        let mut p = Dummy::new();
+        let mut parser = Parser::new();
+        for chunk in data.chunks(100) {
+            parser.feed(chunk, &mut p);
+        }
+        parser.finish(&mut p);
+        assert_eq!(String::from_utf8(p.data[0].clone()).unwrap(), "å");
+
+        let mut events = Events(Vec::new());
+        let options = ParserOptions { transport_encoding: Some(WINDOWS_1252), ..Default::default() };
+        let mut parser = Parser::with_options(&options);
+        parser.feed(b"<p>\xe5", &mut events);
+        assert_eq!(events.0.len(), 0);
+        parser.finish(&mut events);
+        assert_eq!(events.0, vec!["encoding windows-1252", "start p [] false 1:1-1:4", "data \"å\" 1:4-1:5"]);
     }
-}
 
+    #[test]
+    fn feed_parser_is_reset_by_finish() {
+        let mut parser = Parser::new();
+        let mut p = Dummy::new();
+        parser.feed(b"<p>one</p><!-- open", &mut p);
+        parser.finish(&mut p);
+        parser.feed(b"<b>two</b>", &mut p);
+        assert_eq!(parser.finish(&mut p), 10);
+        assert_eq!(p.starttag[1].tag, Tag::B);
+        assert_eq!(p.starttag[1].span.start.offset, 0);
+        assert_eq!(p.errors.len(), 1);
+    }
+
+    #[test]
+    fn parse_skips_element_content() {
+        let data = b"<div>a<div>b</div><br><div/>c</div><p>d</p><div>e</div>";
+        let mut p = Flows::new(Tag::DIV, Tag::BODY);
+        assert_eq!(::Parser::parse(&mut BufReader::new(&data[..]), &mut p).unwrap(), data.len());
+        assert_eq!(p.events, ["<div>", "</div>", "<p>", "d", "</p>", "<div>", "</div>"]);
+
+        let mut p = Flows::new(Tag::BR, Tag::BODY);
+        ::Parser::parse(&mut BufReader::new(&b"<br>a<p>b</p>"[..]), &mut p).unwrap();
+        assert_eq!(p.events, ["<br>", "a", "<p>", "b", "</p>"]);
+
+        let mut p = Flows::new(Tag::SCRIPT, Tag::BODY);
+        ::Parser::parse(&mut BufReader::new(&b"<script>a</p></script>b"[..]), &mut p).unwrap();
+        assert_eq!(p.events, ["<script>", "</script>", "b"]);
+    }
+
+    #[test]
+    fn parse_skips_elements_with_optional_end_tag() {
+        let mut p = Flows::new(Tag::LI, Tag::BODY);
+        ::Parser::parse(&mut BufReader::new(&b"<ul><li>a<b>x<li>b<ul><li>c</ul></ul><p>after</p>"[..]), &mut p).unwrap();
+        assert_eq!(p.events, ["<ul>", "<li>", "<li>", "</ul>", "<p>", "after", "</p>"]);
+
+        let mut p = Flows::new(Tag::P, Tag::BODY);
+        ::Parser::parse(&mut BufReader::new(&b"<div><p>a<b>x</b><div>y</div></div><section><p>b</section>c<p>d</p>"[..]), &mut p).unwrap();
+        assert_eq!(p.events, ["<div>", "<p>", "<div>", "y", "</div>", "</div>", "<section>", "<p>", "</section>", "c", "<p>", "</p>"]);
+    }
+
+    #[test]
+    fn parse_skips_stray_end_tags_in_elements_with_optional_end_tag() {
+        let mut p = Flows::new(Tag::LI, Tag::BODY);
+        ::Parser::parse(&mut BufReader::new(&b"<ul><li>secret</b>more secret</li></ul>after"[..]), &mut p).unwrap();
+        assert_eq!(p.events, ["<ul>", "<li>", "</li>", "</ul>", "after"]);
+
+        let mut p = Flows::new(Tag::P, Tag::BODY);
+        ::Parser::parse(&mut BufReader::new(&b"<div><p>a</span>b</p>c</div>"[..]), &mut p).unwrap();
+        assert_eq!(p.events, ["<div>", "<p>", "</p>", "c", "</div>"]);
+
+        let mut p = Flows::new(Tag::TD, Tag::BODY);
+        ::Parser::parse(&mut BufReader::new(&b"<table><tr><td>a</li>b</tr><tr><td>c</table>d"[..]), &mut p).unwrap();
+        assert_eq!(p.events, ["<table>", "<tr>", "<td>", "</tr>", "<tr>", "<td>", "</table>", "d"]);
+    }
+
+    #[test]
+    fn parse_stops_at_handler() {
+        let data = b"<head><title>a</title></head><body><p>b</p></body>";
+        for mode in [TokenizerMode::Legacy, TokenizerMode::Whatwg].iter() {
+            let options = ParserOptions { tokenizer: *mode, ..Default::default() };
+            let mut p = Flows::new(Tag::BODY, Tag::HEAD);
+            let parsed = ::Parser::parse_with_options(&mut BufReader::new(&data[..]), &mut p, &options).unwrap();
+            assert_eq!(&data[..parsed], b"<head><title>a</title></head>");
+            assert_eq!(p.events, ["<head>", "<title>", "a", "</title>", "</head>"]);
+            assert!(p.eof);
+        }
+    }
+
+    #[test]
+    fn parse_stops_at_offset_in_decoded_document() {
+        let data = b"<p>caf\xe9</p><p>b</p>";
+        let options = ParserOptions { transport_encoding: Some(WINDOWS_1252), ..Default::default() };
+        let mut p = Flows::new(Tag::BODY, Tag::P);
+        let parsed = ::Parser::parse_with_options(&mut BufReader::new(&data[..]), &mut p, &options).unwrap();
+        assert_eq!(parsed, "<p>caf\u{e9}</p>".len());
+        assert_eq!(p.events, ["<p>", "caf\u{e9}", "</p>"]);
+    }
+
+    #[test]
+    fn feed_stops_at_handler() {
+        let data = b"<head><title>a</title></head><body><p>b</p></body>";
+        for size in 1..data.len() + 1 {
+            let mut p = Flows::new(Tag::TITLE, Tag::HEAD);
+            let mut parser = Parser::with_options(&ParserOptions { detect_encoding: false, ..Default::default() });
+            for chunk in data.chunks(size) {
+                parser.feed(chunk, &mut p);
+            }
+            assert!(parser.is_stopped());
+            assert_eq!(parser.finish(&mut p), 29, "chunk size {}", size);
+            assert_eq!(p.events, ["<head>", "<title>", "</title>", "</head>"], "chunk size {}", size);
+            assert!(p.eof);
+            assert!(!parser.is_stopped());
+        }
+    }
+
+    // Run future to completion, for readers that never wait on anything
+    #[cfg(feature = "tokio")]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+                return x;
+            }
+        }
+    }
+
+    // Reader returning a few bytes at a time and pending in between
+    #[cfg(feature = "tokio")]
+    struct Trickle<'a> {
+        data: &'a [u8],
+        pending: bool,
+    }
+
+    #[cfg(feature = "tokio")]
+    impl<'a> AsyncRead for Trickle<'a> {
+        fn poll_read(self: Pin<&mut Self>, _cx: &mut Context, buf: &mut ReadBuf) -> Poll<std::io::Result<()>> {
+            let length = buf.remaining().min(self.data.len());
+            buf.put_slice(&self.data[..length]);
+            self.get_mut().data = &self.data[length..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    impl<'a> AsyncBufRead for Trickle<'a> {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<&[u8]>> {
+            let this = self.get_mut();
+            this.pending = !this.pending;
+            if this.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(Ok(&this.data[..this.data.len().min(3)]))
+        }
+
+        fn consume(self: Pin<&mut Self>, amount: usize) {
+            let this = self.get_mut();
+            this.data = &this.data[amount..];
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn parse_async_document() {
+        let data = "<html><head><title>Async</title></head><body><p class=\"info\">Sparkle 💖 heart</p><!-- end --></body></html>";
+
+        let mut expected = Events(Vec::new());
+        ::Parser::parse(&mut BufReader::new(data.as_bytes()), &mut expected).unwrap();
+
+        let mut events = Events(Vec::new());
+        let mut source = data.as_bytes();
+        assert_eq!(block_on(::Parser::parse_async(&mut source, &mut events)).unwrap(), data.len());
+        assert_eq!(events.0, expected.0);
+
+        let mut events = Events(Vec::new());
+        let mut source = Trickle { data: data.as_bytes(), pending: false };
+        assert_eq!(block_on(::Parser::parse_async(&mut source, &mut events)).unwrap(), data.len());
+        assert_eq!(events.0, expected.0);
+    }
+}
@@ -0,0 +1,137 @@
+use std;
+
+/// Position in a source document.
+///
+/// The offset is counted in bytes from start of document, line and
+/// column starts at 1 and column is counted in characters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// Create position representing start of a document
+    pub fn new() -> Position {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Advance position past the bytes of data
+    pub fn advance(&mut self, data: &[u8]) {
+        for b in data {
+            self.offset += 1;
+            if *b == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else if (*b & 0xC0) != 0x80 {
+                // Do not count utf8 continuation bytes
+                self.column += 1;
+            }
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position::new()
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&format!("{}:{}", self.line, self.column))
+    }
+}
+
+/// Span of a source document from start up to, but not including, end.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// Create new span
+    pub fn new(start: Position, end: Position) -> Span {
+        Span {
+            start,
+            end,
+        }
+    }
+
+    /// Get length of span in bytes
+    pub fn len(&self) -> usize {
+        self.end.offset - self.start.offset
+    }
+
+    /// Test if span is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&format!("{}-{}", self.start, self.end))
+    }
+}
+
+/// Tracks the position of bytes in a buffer, advancing from the
+/// position of the first byte.
+#[derive(Clone, Copy)]
+pub struct Cursor {
+    base: Position,
+    index: usize,
+    position: Position,
+}
+
+impl Cursor {
+    /// Create new cursor for a buffer starting at position
+    pub fn new(base: Position) -> Cursor {
+        Cursor {
+            base,
+            index: 0,
+            position: base,
+        }
+    }
+
+    /// Get position of byte at index in buffer
+    pub fn at(&mut self, buf: &[u8], index: usize) -> Position {
+        if index < self.index {
+            self.index = 0;
+            self.position = self.base;
+        }
+
+        self.position.advance(&buf[self.index..index]);
+        self.index = index;
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use position::{Position, Cursor};
+
+    #[test]
+    fn advance_position() {
+        let mut p = Position::new();
+        p.advance("ab\ncd💖e".as_bytes());
+        assert_eq!(p.offset, 10);
+        assert_eq!(p.line, 2);
+        assert_eq!(p.column, 5);
+    }
+
+    #[test]
+    fn cursor_position() {
+        let buf = b"<p>\n<b>";
+        let mut c = Cursor::new(Position::new());
+        assert_eq!(c.at(buf, 4), Position{offset: 4, line: 2, column: 1});
+        assert_eq!(c.at(buf, 6), Position{offset: 6, line: 2, column: 3});
+        assert_eq!(c.at(buf, 1), Position{offset: 1, line: 1, column: 2});
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tag::{Tag};
+use atom::{Atom};
+
+/// Properties of an element registered in a [TagRegistry].
+///
+/// [TagRegistry]: struct.TagRegistry.html
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TagProperties {
+    /// The element has no content and no end tag, eg. as ```<br>```.
+    pub void: bool,
+
+    /// The content of the element is text up to its end tag, read
+    /// without markup nor character references and serialized without
+    /// escaping, eg. as ```<script>```.
+    pub raw_text: bool,
+
+    /// The element is rendered as a block, eg. as ```<div>```, else it
+    /// is inline.
+    pub block: bool,
+
+    /// Names of the elements the element is allowed in, any element by
+    /// default. The elements open in the nearest allowed parent are
+    /// closed by the start tag of the element.
+    pub parents: Vec<String>,
+}
+
+/// Element registered in a [TagRegistry], read as
+/// ```Tag::Custom```.
+///
+/// [TagRegistry]: struct.TagRegistry.html
+#[derive(Clone, Debug)]
+pub struct CustomTag {
+    name: Atom,
+    properties: Arc<TagProperties>,
+}
+
+impl CustomTag {
+    /// Get the name of the element.
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Get the properties the element is registered with.
+    pub fn properties(&self) -> &TagProperties {
+        &self.properties
+    }
+
+    /// Test if the element is allowed in an element with the name
+    /// parent in any case, see
+    /// [TagProperties::parents](struct.TagProperties.html#structfield.parents).
+    pub fn is_allowed_in(&self, parent: &str) -> bool {
+        self.properties.parents.is_empty() || self.properties.parents.iter().any(|x| x.eq_ignore_ascii_case(parent))
+    }
+}
+
+impl PartialEq for CustomTag {
+    fn eq(&self, other: &CustomTag) -> bool {
+        self.name == other.name && self.properties == other.properties
+    }
+}
+
+/// Registry of the elements of a vocabulary beyond HTML, eg. the
+/// custom elements of templates, with the properties the standard
+/// elements have built in.
+///
+/// Elements read with a registered name are ```Tag::Custom``` instead
+/// of ```Tag::Unknown``` and have the metadata of their properties, eg.
+/// [Tag::is_void](enum.Tag.html#method.is_void). They are read by the
+/// tokenizer, placed in the tree and serialized as the standard
+/// elements with these properties. Names are registered in lowercase
+/// and matched ignoring case, names of standard elements can not be
+/// registered.
+///
+/// # Examples
+///
+/// ```
+/// use domx::{Dom, DomOptions, TagRegistry, TagProperties, ToHTML};
+///
+/// let mut tags = TagRegistry::new();
+/// tags.register("x-icon", TagProperties { void: true, ..Default::default() });
+/// tags.register("x-item", TagProperties { parents: vec!["x-list".to_string()], ..Default::default() });
+///
+/// let mut dom = Dom::with_options(&DomOptions { tags: tags, ..Default::default() });
+/// dom.parse(&mut "<x-list><x-item><x-icon>a<x-item>b</x-list>".as_bytes()).unwrap();
+/// assert_eq!(dom.to_html(), "<x-list><x-item><x-icon>a</x-item><x-item>b</x-item></x-list>");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TagRegistry {
+    tags: HashMap<String, CustomTag>,
+}
+
+impl TagRegistry {
+    pub fn new() -> TagRegistry {
+        TagRegistry {
+            tags: HashMap::new(),
+        }
+    }
+
+    /// Register an element name with its properties, the properties of
+    /// a name registered before are replaced. Returns false for the
+    /// name of a standard element, which is not registered.
+    pub fn register(&mut self, name: &str, mut properties: TagProperties) -> bool {
+        let name = name.to_lowercase();
+        for parent in properties.parents.iter_mut() {
+            *parent = parent.to_lowercase();
+        }
+        match name.parse::<Tag>() {
+            Ok(Tag::Unknown(x)) => {
+                self.tags.insert(name, CustomTag { name: x, properties: Arc::new(properties) });
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Get the tag of a registered element name.
+    pub fn get(&self, name: &str) -> Option<Tag> {
+        let tag = match name.chars().any(|x| x.is_uppercase()) {
+            true => self.tags.get(&name.to_lowercase()),
+            false => self.tags.get(name),
+        };
+        tag.map(|x| Tag::Custom(x.clone()))
+    }
+
+    /// Get the registered tag for an unknown tag, None for other tags
+    /// and names not registered.
+    pub fn resolve(&self, tag: &Tag) -> Option<Tag> {
+        match *tag {
+            Tag::Unknown(ref x) if !self.tags.is_empty() => self.get(x),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tag::{Tag};
+    use registry::{TagRegistry, TagProperties};
+
+    #[test]
+    fn registry_gives_metadata_to_unknown_tags() {
+        let mut tags = TagRegistry::new();
+        assert!(tags.register("x-card", TagProperties { block: true, ..Default::default() }));
+        assert!(tags.register("x-code", TagProperties { raw_text: true, ..Default::default() }));
+        assert!(!tags.register("div", TagProperties { void: true, ..Default::default() }));
+        assert!(!tags.register("DIV", TagProperties { void: true, ..Default::default() }));
+
+        let card = tags.resolve(&"x-card".parse::<Tag>().unwrap()).unwrap();
+        assert_eq!(card.as_str(), "x-card");
+        assert!(card.is_block() && card.is_flow() && !card.is_inline() && !card.is_void());
+        let code = tags.get("x-code").unwrap();
+        assert!(code.is_raw_text() && code.is_inline() && code.is_phrasing());
+
+        assert_eq!(tags.resolve(&Tag::DIV), None);
+        assert_eq!(tags.resolve(&"x-other".parse::<Tag>().unwrap()), None);
+        assert_ne!(card, "x-card".parse::<Tag>().unwrap());
+
+        // Names are matched ignoring case
+        assert!(tags.register("X-Icon", TagProperties { void: true, parents: vec!["X-List".to_string()], ..Default::default() }));
+        let icon = tags.resolve(&"x-icon".parse::<Tag>().unwrap()).unwrap();
+        assert!(icon.is_void());
+        assert_eq!(icon.as_str(), "x-icon");
+        assert_eq!(tags.get("X-ICON"), Some(icon.clone()));
+        match icon {
+            Tag::Custom(ref x) => assert!(x.is_allowed_in("x-list")),
+            _ => panic!("expected custom tag"),
+        }
+    }
+}
@@ -1,263 +1,501 @@
 use std;
+use namespace::{Namespace};
+use atom::{Atom, Atoms};
+use registry::{CustomTag};
 
-/// Enumeration that represents HTML tags.
-#[derive(Clone, PartialEq, Debug)]
-pub enum Tag {
-    Unknown(String),
-    A,
-    ABBR,
-    ACRONYM,
-    ADDRESS,
-    ARTICLE, // HTML5
-    ASIDE, // HTML5
-    B,
-    BDO,
-    BIG,
-    BLOCKQUOTE,
-    BODY,
-    BR,
-    BUTTON,
-    CANVAS, // HTML5
-    CITE,
-    CODE,
-    DD,
-    DFN,
-    DIV,
-    DL,
-    DT,
-    EM,
-    FIELDSET,
-    FIGCAPTION, // HTML5
-    FIGURE, // HTML5
-    FOOTER, // HTML5
-    FORM,
-    H1, H2, H3, H4, H5, H6,
-    HEAD,
-    HEADER, // HTML5
-    HGROUP, // HTML5
-    HR,
-    HTML,
-    I,
-    IFRAME,
-    IMG,
-    INPUT,
-    KBD,
-    LABEL,
-    LI,
-    LINK,
-    MAP,
-    MAIN,
-    META,
-    NAV,
-    NOSCRIPT,
-    OBJECT,
-    OL,
-    OPTION,
-    OUTPUT, // HTML5
-    P,
-    PRE,
-    Q,
-    SAMP,
-    SCRIPT,
-    SECTION, // HTML5
-    SELECT,
-    SMALL,
-    SPAN,
-    STRONG,
-    STYLE,
-    SUB,
-    SUP,
-    TABLE,
-    TEXTAREA,
-    TFOOT,
-    TIME,
-    TITLE,
-    TT,
-    UL,
-    VAR,
-    VIDEO, // HTML5
-    WBR, // HTML5
+// Metadata of the elements, see the methods of Tag
+const VOID: u16 = 1 << 0;
+const RAW_TEXT: u16 = 1 << 1;
+const ESCAPABLE_RAW_TEXT: u16 = 1 << 2;
+const FLOW: u16 = 1 << 3;
+const PHRASING: u16 = 1 << 4;
+const HEADING: u16 = 1 << 5;
+const SECTIONING: u16 = 1 << 6;
+const INTERACTIVE: u16 = 1 << 7;
+const FORM_ASSOCIATED: u16 = 1 << 8;
+const BLOCK: u16 = 1 << 9;
+const HIDDEN: u16 = 1 << 10;
+
+// Declare Tag, its name, numeric id and metadata from a table with one
+// line per element
+macro_rules! tags {
+    ( $( $tag:ident = $id:literal, $name:literal, $flags:expr; )* ) => {
+        /// Enumeration that represents HTML tags.
+        ///
+        /// Elements in SVG and MathML content are ```Svg``` and ```MathMl```
+        /// with their name, starting with the ```svg``` and ```math```
+        /// roots. The names of unknown and foreign elements are
+        /// [Atom](struct.Atom.html)s. Elements registered in a
+        /// [TagRegistry](struct.TagRegistry.html) are ```Custom```.
+        #[derive(Clone, PartialEq, Debug)]
+        pub enum Tag {
+            Unknown(Atom),
+            Svg(Atom),
+            MathMl(Atom),
+            Custom(CustomTag),
+            $( $tag, )*
+        }
+
+        // Known tags in the order of the table
+        #[cfg(test)]
+        const TAGS: &[Tag] = &[ $( Tag::$tag, )* ];
+
+        impl Tag {
+            /// Get the numeric id of the tag, 0 for an unknown tag.
+            ///
+            /// The ids are stable, a tag keeps its id in later versions
+            /// and ids of removed tags are not reused.
+            pub fn id(&self) -> u16 {
+                match *self {
+                    Tag::Unknown(_) | Tag::Svg(_) | Tag::MathMl(_) | Tag::Custom(_) => 0,
+                    $( Tag::$tag => $id, )*
+                }
+            }
+
+            /// Get the tag with a numeric id, see [id](#method.id).
+            pub fn from_id(id: u16) -> Option<Tag> {
+                match id {
+                    $( $id => Some(Tag::$tag), )*
+                    _ => None,
+                }
+            }
+
+            /// Get the name of the tag, as written in a document.
+            pub fn as_str(&self) -> &str {
+                match *self {
+                    Tag::Unknown(ref x) | Tag::Svg(ref x) | Tag::MathMl(ref x) => x.as_str(),
+                    Tag::Custom(ref x) => x.name(),
+                    $( Tag::$tag => $name, )*
+                }
+            }
+
+            // Get the tag for a name, the name of an unknown or foreign
+            // tag is made into an atom by intern
+            fn _from_name<F>(name: &str, intern: F) -> Tag
+                where F: FnOnce(&str) -> Atom
+            {
+                match name {
+                    $( $name => Tag::$tag, )*
+                    "svg" => Tag::Svg(intern(name)),
+                    "math" => Tag::MathMl(intern(name)),
+                    _ => Tag::Unknown(intern(name)),
+                }
+            }
+
+            fn _flags(&self) -> u16 {
+                match *self {
+                    Tag::Svg(ref x) if x == "svg" => FLOW | PHRASING,
+                    Tag::MathMl(ref x) if x == "math" => FLOW | PHRASING,
+                    Tag::Custom(ref x) => {
+                        let properties = x.properties();
+                        let mut flags = match properties.block {
+                            true => FLOW | BLOCK,
+                            false => FLOW | PHRASING,
+                        };
+                        if properties.void {
+                            flags |= VOID;
+                        }
+                        if properties.raw_text {
+                            flags |= RAW_TEXT;
+                        }
+                        flags
+                    },
+                    Tag::Unknown(_) | Tag::Svg(_) | Tag::MathMl(_) => 0,
+                    $( Tag::$tag => $flags, )*
+                }
+            }
+        }
+
+        /// Parse an Tag type from string
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// use domx;
+        /// let e = "code".parse::<domx::Tag>().unwrap();
+        /// println!("<{}>", e);
+        /// ```
+        impl std::str::FromStr for Tag {
+            type Err = ();
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Ok(Tag::_from_name(s, |x| Atom::from(x)))
+            }
+        }
+    };
+}
+
+// Elements of the HTML Living Standard and obsolete elements still
+// handled by browsers, with the numeric id, name and metadata of each.
+// New elements are added with the next free id.
+tags! {
+    A = 1,           "a",          FLOW | PHRASING | INTERACTIVE;
+    ABBR = 2,        "abbr",       FLOW | PHRASING;
+    ACRONYM = 3,     "acronym",    FLOW | PHRASING;
+    ADDRESS = 4,     "address",    FLOW | BLOCK;
+    APPLET = 5,      "applet",     0; // obsolete
+    AREA = 6,        "area",       VOID | FLOW | PHRASING | HIDDEN;
+    ARTICLE = 7,     "article",    FLOW | SECTIONING | BLOCK; // HTML5
+    ASIDE = 8,       "aside",      FLOW | SECTIONING | BLOCK; // HTML5
+    AUDIO = 9,       "audio",      FLOW | PHRASING | INTERACTIVE; // HTML5
+    B = 10,          "b",          FLOW | PHRASING;
+    BASE = 11,       "base",       VOID | HIDDEN;
+    BASEFONT = 12,   "basefont",   VOID | HIDDEN; // obsolete
+    BDI = 13,        "bdi",        FLOW | PHRASING; // HTML5
+    BDO = 14,        "bdo",        FLOW | PHRASING;
+    BGSOUND = 15,    "bgsound",    VOID | HIDDEN; // obsolete
+    BIG = 16,        "big",        FLOW | PHRASING;
+    BLOCKQUOTE = 17, "blockquote", FLOW | BLOCK;
+    BODY = 18,       "body",       BLOCK;
+    BR = 19,         "br",         VOID | FLOW | PHRASING;
+    BUTTON = 20,     "button",     FLOW | PHRASING | INTERACTIVE | FORM_ASSOCIATED;
+    CANVAS = 21,     "canvas",     FLOW | PHRASING; // HTML5
+    CAPTION = 22,    "caption",    BLOCK;
+    CENTER = 23,     "center",     FLOW | BLOCK; // obsolete
+    CITE = 24,       "cite",       FLOW | PHRASING;
+    CODE = 25,       "code",       FLOW | PHRASING;
+    COL = 26,        "col",        VOID | BLOCK;
+    COLGROUP = 27,   "colgroup",   BLOCK;
+    DATA = 28,       "data",       FLOW | PHRASING; // HTML5
+    DATALIST = 29,   "datalist",   FLOW | PHRASING | HIDDEN; // HTML5
+    DD = 30,         "dd",         BLOCK;
+    DEL = 31,        "del",        FLOW | PHRASING;
+    DETAILS = 32,    "details",    FLOW | INTERACTIVE | BLOCK; // HTML5
+    DFN = 33,        "dfn",        FLOW | PHRASING;
+    DIALOG = 34,     "dialog",     FLOW | BLOCK; // HTML5
+    DIR = 35,        "dir",        FLOW | BLOCK; // obsolete
+    DIV = 36,        "div",        FLOW | BLOCK;
+    DL = 37,         "dl",         FLOW | BLOCK;
+    DT = 38,         "dt",         BLOCK;
+    EM = 39,         "em",         FLOW | PHRASING;
+    EMBED = 40,      "embed",      VOID | FLOW | PHRASING | INTERACTIVE; // HTML5
+    FIELDSET = 41,   "fieldset",   FLOW | FORM_ASSOCIATED | BLOCK;
+    FIGCAPTION = 42, "figcaption", BLOCK; // HTML5
+    FIGURE = 43,     "figure",     FLOW | BLOCK; // HTML5
+    FONT = 44,       "font",       FLOW | PHRASING; // obsolete
+    FOOTER = 45,     "footer",     FLOW | BLOCK; // HTML5
+    FORM = 46,       "form",       FLOW | BLOCK;
+    FRAME = 47,      "frame",      VOID | BLOCK; // obsolete
+    FRAMESET = 48,   "frameset",   BLOCK; // obsolete
+    H1 = 49,         "h1",         FLOW | HEADING | BLOCK;
+    H2 = 50,         "h2",         FLOW | HEADING | BLOCK;
+    H3 = 51,         "h3",         FLOW | HEADING | BLOCK;
+    H4 = 52,         "h4",         FLOW | HEADING | BLOCK;
+    H5 = 53,         "h5",         FLOW | HEADING | BLOCK;
+    H6 = 54,         "h6",         FLOW | HEADING | BLOCK;
+    HEAD = 55,       "head",       HIDDEN;
+    HEADER = 56,     "header",     FLOW | BLOCK; // HTML5
+    HGROUP = 57,     "hgroup",     FLOW | HEADING | BLOCK; // HTML5
+    HR = 58,         "hr",         VOID | FLOW | BLOCK;
+    HTML = 59,       "html",       BLOCK;
+    I = 60,          "i",          FLOW | PHRASING;
+    IFRAME = 61,     "iframe",     RAW_TEXT | FLOW | PHRASING | INTERACTIVE;
+    IMG = 62,        "img",        VOID | FLOW | PHRASING | INTERACTIVE | FORM_ASSOCIATED;
+    INPUT = 63,      "input",      VOID | FLOW | PHRASING | INTERACTIVE | FORM_ASSOCIATED;
+    INS = 64,        "ins",        FLOW | PHRASING;
+    KBD = 65,        "kbd",        FLOW | PHRASING;
+    KEYGEN = 66,     "keygen",     VOID | FLOW | PHRASING | INTERACTIVE | FORM_ASSOCIATED; // obsolete
+    LABEL = 67,      "label",      FLOW | PHRASING | INTERACTIVE | FORM_ASSOCIATED;
+    LEGEND = 68,     "legend",     BLOCK;
+    LI = 69,         "li",         BLOCK;
+    LINK = 70,       "link",       VOID | HIDDEN;
+    LISTING = 71,    "listing",    FLOW | BLOCK; // obsolete
+    MAIN = 72,       "main",       FLOW | BLOCK;
+    MAP = 73,        "map",        FLOW | PHRASING;
+    MARK = 74,       "mark",       FLOW | PHRASING; // HTML5
+    MARQUEE = 75,    "marquee",    FLOW; // obsolete
+    MENU = 76,       "menu",       FLOW | BLOCK;
+    META = 77,       "meta",       VOID | HIDDEN;
+    METER = 78,      "meter",      FLOW | PHRASING; // HTML5
+    NAV = 79,        "nav",        FLOW | SECTIONING | BLOCK;
+    NOBR = 80,       "nobr",       FLOW | PHRASING; // obsolete
+    NOEMBED = 81,    "noembed",    RAW_TEXT | HIDDEN; // obsolete
+    NOFRAMES = 82,   "noframes",   RAW_TEXT | HIDDEN; // obsolete
+    NOSCRIPT = 83,   "noscript",   FLOW | PHRASING | HIDDEN;
+    OBJECT = 84,     "object",     FLOW | PHRASING | FORM_ASSOCIATED;
+    OL = 85,         "ol",         FLOW | BLOCK;
+    OPTGROUP = 86,   "optgroup",   BLOCK;
+    OPTION = 87,     "option",     BLOCK;
+    OUTPUT = 88,     "output",     FLOW | PHRASING | FORM_ASSOCIATED; // HTML5
+    P = 89,          "p",          FLOW | BLOCK;
+    PARAM = 90,      "param",      VOID | HIDDEN; // obsolete
+    PICTURE = 91,    "picture",    FLOW | PHRASING; // HTML5
+    PLAINTEXT = 92,  "plaintext",  RAW_TEXT | FLOW | BLOCK; // obsolete
+    PRE = 93,        "pre",        FLOW | BLOCK;
+    PROGRESS = 94,   "progress",   FLOW | PHRASING; // HTML5
+    Q = 95,          "q",          FLOW | PHRASING;
+    RB = 96,         "rb",         0; // obsolete
+    RP = 97,         "rp",         HIDDEN; // HTML5
+    RT = 98,         "rt",         0; // HTML5
+    RTC = 99,        "rtc",        0; // obsolete
+    RUBY = 100,      "ruby",       FLOW | PHRASING; // HTML5
+    S = 101,         "s",          FLOW | PHRASING;
+    SAMP = 102,      "samp",       FLOW | PHRASING;
+    SCRIPT = 103,    "script",     RAW_TEXT | FLOW | PHRASING | HIDDEN;
+    SEARCH = 104,    "search",     FLOW | BLOCK; // HTML5
+    SECTION = 105,   "section",    FLOW | SECTIONING | BLOCK; // HTML5
+    SELECT = 106,    "select",     FLOW | PHRASING | INTERACTIVE | FORM_ASSOCIATED;
+    SLOT = 107,      "slot",       FLOW | PHRASING; // HTML5
+    SMALL = 108,     "small",      FLOW | PHRASING;
+    SOURCE = 109,    "source",     VOID | HIDDEN; // HTML5
+    SPAN = 110,      "span",       FLOW | PHRASING;
+    STRIKE = 111,    "strike",     FLOW | PHRASING; // obsolete
+    STRONG = 112,    "strong",     FLOW | PHRASING;
+    STYLE = 113,     "style",      RAW_TEXT | HIDDEN;
+    SUB = 114,       "sub",        FLOW | PHRASING;
+    SUMMARY = 115,   "summary",    BLOCK; // HTML5
+    SUP = 116,       "sup",        FLOW | PHRASING;
+    TABLE = 117,     "table",      FLOW | BLOCK;
+    TBODY = 118,     "tbody",      BLOCK;
+    TD = 119,        "td",         BLOCK;
+    TEMPLATE = 120,  "template",   FLOW | PHRASING | HIDDEN; // HTML5
+    TEXTAREA = 121,  "textarea",   ESCAPABLE_RAW_TEXT | FLOW | PHRASING | INTERACTIVE | FORM_ASSOCIATED;
+    TFOOT = 122,     "tfoot",      BLOCK;
+    TH = 123,        "th",         BLOCK;
+    THEAD = 124,     "thead",      BLOCK;
+    TIME = 125,      "time",       FLOW | PHRASING;
+    TITLE = 126,     "title",      ESCAPABLE_RAW_TEXT | HIDDEN;
+    TR = 127,        "tr",         BLOCK;
+    TRACK = 128,     "track",      VOID | HIDDEN; // HTML5
+    TT = 129,        "tt",         FLOW | PHRASING;
+    U = 130,         "u",          FLOW | PHRASING;
+    UL = 131,        "ul",         FLOW | BLOCK;
+    VAR = 132,       "var",        FLOW | PHRASING;
+    VIDEO = 133,     "video",      FLOW | PHRASING | INTERACTIVE; // HTML5
+    WBR = 134,       "wbr",        VOID | FLOW | PHRASING; // HTML5
+    XMP = 135,       "xmp",        RAW_TEXT | FLOW | BLOCK; // obsolete
 }
 
 impl std::fmt::Display for Tag {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Tag::A => f.write_str("a"),
-            Tag::ABBR => f.write_str("abr"),
-            Tag::ACRONYM => f.write_str("acronym"),
-            Tag::ADDRESS => f.write_str("address"),
-            Tag::ARTICLE => f.write_str("article"),
-            Tag::ASIDE => f.write_str("aside"),
-            Tag::B => f.write_str("b"),
-            Tag::BDO => f.write_str("bdo"),
-            Tag::BIG => f.write_str("big"),
-            Tag::BLOCKQUOTE => f.write_str("BLOCKQUOTE"),
-            Tag::BODY => f.write_str("body"),
-            Tag::BR => f.write_str("br"),
-            Tag::BUTTON => f.write_str("button"),
-            Tag::CANVAS => f.write_str("cavas"),
-            Tag::CITE => f.write_str("cite"),
-            Tag::CODE => f.write_str("code"),
-            Tag::DD => f.write_str("dd"),
-            Tag::DFN => f.write_str("dfn"),
-            Tag::DIV => f.write_str("div"),
-            Tag::DL => f.write_str("dl"),
-            Tag::DT => f.write_str("dt"),
-            Tag::EM => f.write_str("em"),
-            Tag::FIELDSET => f.write_str("fieldset"),
-            Tag::FIGCAPTION => f.write_str("figcaption"),
-            Tag::FIGURE => f.write_str("figure"),
-            Tag::FOOTER => f.write_str("footer"),
-            Tag::FORM => f.write_str("form"),
-            Tag::H1 => f.write_str("h1"),
-            Tag::H2 => f.write_str("h2"),
-            Tag::H3 => f.write_str("h3"),
-            Tag::H4 => f.write_str("h4"),
-            Tag::H5 => f.write_str("h5"),
-            Tag::H6 => f.write_str("h6"),
-            Tag::HEAD => f.write_str("head"),
-            Tag::HEADER => f.write_str("header"),
-            Tag::HGROUP => f.write_str("hgroup"),
-            Tag::HR => f.write_str("hr"),
-            Tag::HTML => f.write_str("html"),
-            Tag::I => f.write_str("i"),
-            Tag::IFRAME => f.write_str("iframe"),
-            Tag::INPUT => f.write_str("input"),
-            Tag::IMG => f.write_str("img"),
-            Tag::KBD => f.write_str("kdb"),
-            Tag::LABEL => f.write_str("label"),
-            Tag::LI => f.write_str("li"),
-            Tag::LINK => f.write_str("link"),
-            Tag::MAIN => f.write_str("main"),
-            Tag::MAP => f.write_str("map"),
-            Tag::META => f.write_str("meta"),
-            Tag::NAV => f.write_str("nav"),
-            Tag::NOSCRIPT => f.write_str("noscript"),
-            Tag::OBJECT => f.write_str("object"),
-            Tag::OL => f.write_str("ol"),
-            Tag::OPTION => f.write_str("option"),
-            Tag::OUTPUT => f.write_str("output"),
-            Tag::P => f.write_str("p"),
-            Tag::PRE => f.write_str("pre"),
-            Tag::Q => f.write_str("q"),
-            Tag::SAMP => f.write_str("samp"),
-            Tag::SCRIPT => f.write_str("script"),
-            Tag::SECTION => f.write_str("section"),
-            Tag::SELECT => f.write_str("select"),
-            Tag::SMALL => f.write_str("small"),
-            Tag::SPAN => f.write_str("span"),
-            Tag::STRONG => f.write_str("strong"),
-            Tag::STYLE => f.write_str("style"),
-            Tag::SUB => f.write_str("sub"),
-            Tag::SUP => f.write_str("sup"),
-            Tag::TABLE => f.write_str("table"),
-            Tag::TEXTAREA => f.write_str("textarea"),
-            Tag::TFOOT => f.write_str("tfoot"),
-            Tag::TIME => f.write_str("time"),
-            Tag::TITLE => f.write_str("title"),
-            Tag::TT => f.write_str("tt"),
-            Tag::UL => f.write_str("ul"),
-            Tag::VAR => f.write_str("var"),
-            Tag::VIDEO => f.write_str("video"),
-            Tag::WBR => f.write_str("wbr"),
-            Tag::Unknown(s) => f.write_str(s.as_str()),
-        }
+        f.write_str(self.as_str())
     }
 }
 
-/// Parse an Tag type from string
+/// Metadata of the elements from the HTML Living Standard.
+///
+/// Content categories follow the standard, an element in a category
+/// only with some attribute, eg. ```audio``` with ```controls``` being
+/// interactive content, is taken to be in it. Obsolete elements are in
+/// the categories of the elements replacing them, eg. ```tt``` as
+/// ```code```. Unknown and foreign elements are in no category but the
+/// SVG and MathML roots, ```svg``` and ```math```, which are phrasing
+/// content. Custom elements are flow content and either blocks or
+/// phrasing content, as registered.
 ///
 /// # Examples
 ///
 /// ```
-/// use domx;
-/// let e = "code".parse::<domx::Tag>().unwrap();
-/// println!("<{}>", e);
+/// use domx::Tag;
+///
+/// assert!(Tag::BR.is_void());
+/// assert!(Tag::H1.is_heading() && Tag::H1.is_flow() && !Tag::H1.is_phrasing());
+/// assert!(Tag::LI.is_block() && Tag::EM.is_inline());
 /// ```
-impl std::str::FromStr for Tag {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "a" => Ok(Tag::A),
-            "abbr" => Ok(Tag::ABBR),
-            "acronym" => Ok(Tag::ACRONYM),
-            "address" => Ok(Tag::ADDRESS),
-            "article" => Ok(Tag::ARTICLE),
-            "aside" => Ok(Tag::ASIDE),
-            "b" => Ok(Tag::B),
-            "bdo" => Ok(Tag::BDO),
-            "big" => Ok(Tag::BIG),
-            "blockquote" => Ok(Tag::BLOCKQUOTE),
-            "body" => Ok(Tag::BODY),
-            "br" => Ok(Tag::BR),
-            "button" => Ok(Tag::BUTTON),
-            "canvas" => Ok(Tag::CANVAS),
-            "cite" => Ok(Tag::CITE),
-            "code" => Ok(Tag::CODE),
-            "dd" => Ok(Tag::DD),
-            "dfn" => Ok(Tag::DFN),
-            "div" => Ok(Tag::DIV),
-            "dl" => Ok(Tag::DL),
-            "dt" => Ok(Tag::DT),
-            "em" => Ok(Tag::EM),
-            "fieldset" => Ok(Tag::FIELDSET),
-            "figcaption" => Ok(Tag::FIGCAPTION),
-            "figure" => Ok(Tag::FIGURE),
-            "footer" => Ok(Tag::FOOTER),
-            "form" => Ok(Tag::FORM),
-            "h1" => Ok(Tag::H1),
-            "h2" => Ok(Tag::H2),
-            "h3" => Ok(Tag::H3),
-            "h4" => Ok(Tag::H4),
-            "h5" => Ok(Tag::H5),
-            "h6" => Ok(Tag::H6),
-            "head" => Ok(Tag::HEAD),
-            "header" => Ok(Tag::HEADER),
-            "hgroup" => Ok(Tag::HGROUP),
-            "hr" => Ok(Tag::HR),
-            "html" => Ok(Tag::HTML),
-            "i" => Ok(Tag::I),
-            "iframe" => Ok(Tag::IFRAME),
-            "input" => Ok(Tag::INPUT),
-            "img" => Ok(Tag::IMG),
-            "kbd" => Ok(Tag::KBD),
-            "label" => Ok(Tag::LABEL),
-            "li" => Ok(Tag::LI),
-            "link" => Ok(Tag::LINK),
-            "map" => Ok(Tag::MAP),
-            "main" => Ok(Tag::MAIN),
-            "meta" => Ok(Tag::META),
-            "nav" => Ok(Tag::NAV),
-            "noscript" => Ok(Tag::NOSCRIPT),
-            "object" => Ok(Tag::OBJECT),
-            "ol" => Ok(Tag::OL),
-            "option" => Ok(Tag::OPTION),
-            "output" => Ok(Tag::OUTPUT),
-            "p" => Ok(Tag::P),
-            "pre" => Ok(Tag::PRE),
-            "q" => Ok(Tag::Q),
-            "samp" => Ok(Tag::SAMP),
-            "script" => Ok(Tag::SCRIPT),
-            "section" => Ok(Tag::SECTION),
-            "select" => Ok(Tag::SELECT),
-            "small" => Ok(Tag::SMALL),
-            "span" => Ok(Tag::SPAN),
-            "strong" => Ok(Tag::STRONG),
-            "style" => Ok(Tag::STYLE),
-            "sub" => Ok(Tag::SUB),
-            "sup" => Ok(Tag::SUP),
-            "table" => Ok(Tag::TABLE),
-            "textarea" => Ok(Tag::TEXTAREA),
-            "tfoot" => Ok(Tag::TFOOT),
-            "time" => Ok(Tag::TIME),
-            "title" => Ok(Tag::TITLE),
-            "tt" => Ok(Tag::TT),
-            "ul" => Ok(Tag::UL),
-            "var" => Ok(Tag::VAR),
-            "video" => Ok(Tag::VIDEO),
-            "wbr" => Ok(Tag::WBR),
-            _ => Ok(Tag::Unknown(s.to_string()))
+impl Tag {
+    /// Get the namespace of the element, eg. SVG for ```circle``` in
+    /// ```<svg>```.
+    pub fn namespace(&self) -> Namespace {
+        match *self {
+            Tag::Svg(_) => Namespace::Svg,
+            Tag::MathMl(_) => Namespace::MathMl,
+            _ => Namespace::Html,
         }
     }
+
+    /// Test if the element is void, ie. it has no content and no end
+    /// tag, eg. ```<br>```.
+    pub fn is_void(&self) -> bool {
+        self._flags() & VOID != 0
+    }
+
+    /// Test if the content of the element is text read without markup
+    /// nor character references and serialized without escaping, eg.
+    /// ```<script>```.
+    pub fn is_raw_text(&self) -> bool {
+        self._flags() & RAW_TEXT != 0
+    }
+
+    /// Test if the content of the element is text read without markup
+    /// but with character references, eg. ```<textarea>```.
+    pub fn is_escapable_raw_text(&self) -> bool {
+        self._flags() & ESCAPABLE_RAW_TEXT != 0
+    }
+
+    /// Test if the element is flow content, ie. used in the body of a
+    /// document.
+    pub fn is_flow(&self) -> bool {
+        self._flags() & FLOW != 0
+    }
+
+    /// Test if the element is phrasing content, ie. the text of a
+    /// document and the elements marking it up.
+    pub fn is_phrasing(&self) -> bool {
+        self._flags() & PHRASING != 0
+    }
+
+    /// Test if the element is heading content, ie. ```<h1>``` to
+    /// ```<h6>``` and ```<hgroup>```.
+    pub fn is_heading(&self) -> bool {
+        self._flags() & HEADING != 0
+    }
+
+    /// Test if the element is sectioning content, ie. defines the
+    /// scope of headings.
+    pub fn is_sectioning(&self) -> bool {
+        self._flags() & SECTIONING != 0
+    }
+
+    /// Test if the element is interactive content, ie. intended for
+    /// user interaction.
+    pub fn is_interactive(&self) -> bool {
+        self._flags() & INTERACTIVE != 0
+    }
+
+    /// Test if the element is form-associated, ie. can have a form
+    /// owner.
+    pub fn is_form_associated(&self) -> bool {
+        self._flags() & FORM_ASSOCIATED != 0
+    }
+
+    /// Test if the element is rendered as a block by default, eg.
+    /// ```<div>```, list items and the parts of a table.
+    pub fn is_block(&self) -> bool {
+        self._flags() & BLOCK != 0
+    }
+
+    /// Test if the element is rendered inline by default, ie. neither
+    /// as a block nor hidden as eg. ```<head>``` and ```<script>```.
+    /// Unknown elements are inline.
+    pub fn is_inline(&self) -> bool {
+        self._flags() & (BLOCK | HIDDEN) == 0
+    }
+}
+
+// Names of SVG elements as spelled by the HTML standard, names read in
+// lowercase are adjusted to this spelling
+const SVG_TAG_NAMES: [&str; 37] = [
+    "altGlyph", "altGlyphDef", "altGlyphItem", "animateColor", "animateMotion",
+    "animateTransform", "clipPath", "feBlend", "feColorMatrix",
+    "feComponentTransfer", "feComposite", "feConvolveMatrix",
+    "feDiffuseLighting", "feDisplacementMap", "feDistantLight", "feDropShadow",
+    "feFlood", "feFuncA", "feFuncB", "feFuncG", "feFuncR", "feGaussianBlur",
+    "feImage", "feMerge", "feMergeNode", "feMorphology", "feOffset",
+    "fePointLight", "feSpecularLighting", "feSpotLight", "feTile",
+    "feTurbulence", "foreignObject", "glyphRef", "linearGradient",
+    "radialGradient", "textPath",
+];
+
+/// Get the tag for a tag name read from a document, the name of an
+/// unknown or foreign tag is interned in atoms.
+///
+/// Names are matched case-insensitively, eg. ```BR``` is ```Tag::BR```.
+/// Unless the case is preserved the name of an unknown tag is
+/// lowercased, see [tag_spelling] for the name as written of a
+/// standard tag.
+///
+/// [tag_spelling]: fn.tag_spelling.html
+pub fn tag_from_name(name: &str, preserve_case: bool, atoms: &mut Atoms) -> Tag {
+    if name.is_ascii() && !name.bytes().any(|x| x.is_ascii_uppercase()) {
+        return Tag::_from_name(name, |x| atoms.intern(x));
+    }
+    match Tag::_from_name(&name.to_lowercase(), |x| atoms.intern(x)) {
+        Tag::Unknown(_) if preserve_case => Tag::Unknown(atoms.intern(name)),
+        tag => tag,
+    }
+}
+
+/// Get the name of a tag as written in the document when the case is
+/// preserved and the name differs from the name of the tag, eg.
+/// ```BR``` for ```Tag::BR```.
+pub fn tag_spelling(tag: &Tag, name: &str, preserve_case: bool, atoms: &mut Atoms) -> Option<Atom> {
+    match preserve_case && tag.as_str() != name {
+        true => Some(atoms.intern(name)),
+        false => None,
+    }
+}
+
+/// Get the tag of an element in a namespace for the tag read from a
+/// document.
+///
+/// Unless the case is preserved SVG element names are adjusted to their
+/// spelling in the HTML standard, eg. ```foreignobject``` to
+/// ```foreignObject```, and names of HTML elements are lowercased.
+pub fn tag_in_namespace(tag: &Tag, namespace: Namespace, preserve_case: bool, atoms: &mut Atoms) -> Tag {
+    let name = tag.as_str();
+    match namespace {
+        _ if tag.namespace() == namespace && (preserve_case || namespace != Namespace::Svg) => tag.clone(),
+        Namespace::Svg => match SVG_TAG_NAMES.iter().find(|x| !preserve_case && x.eq_ignore_ascii_case(name)) {
+            Some(x) => Tag::Svg(atoms.intern(x)),
+            None => Tag::Svg(atoms.intern(name)),
+        },
+        Namespace::MathMl => Tag::MathMl(atoms.intern(name)),
+        _ => match tag.namespace() {
+            Namespace::Html => tag.clone(),
+            _ => tag_from_name(name, preserve_case, atoms),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tag::{Tag, TAGS};
+
+    #[test]
+    fn tag_parses_standard_elements() {
+        for name in ["tr", "td", "th", "thead", "tbody", "menu", "details", "summary", "dialog",
+                     "source", "track", "audio", "picture", "template", "slot", "mark", "u",
+                     "s", "del", "ins"].iter() {
+            let tag = name.parse::<Tag>().unwrap();
+            assert_ne!(tag, Tag::Unknown((*name).into()));
+            assert_eq!(tag.to_string(), *name);
+        }
+    }
+
+    #[test]
+    fn tag_round_trips_through_name_and_id() {
+        let mut ids = Vec::new();
+        for tag in TAGS.iter() {
+            assert_eq!(tag.to_string().parse::<Tag>().unwrap(), *tag);
+            assert_eq!(tag.to_string(), tag.to_string().to_lowercase());
+            assert_eq!(Tag::from_id(tag.id()).as_ref(), Some(tag));
+            assert!(tag.id() > 0);
+            ids.push(tag.id());
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), TAGS.len());
+
+        let tag = Tag::Unknown("x-card".into());
+        assert_eq!(tag.to_string().parse::<Tag>().unwrap(), tag);
+        assert_eq!(tag.id(), 0);
+        assert_eq!(Tag::from_id(0), None);
+    }
+
+    #[test]
+    fn tag_names_as_in_documents() {
+        assert_eq!(Tag::ABBR.to_string(), "abbr");
+        assert_eq!(Tag::BLOCKQUOTE.to_string(), "blockquote");
+        assert_eq!(Tag::CANVAS.to_string(), "canvas");
+        assert_eq!(Tag::KBD.to_string(), "kbd");
+    }
+
+    #[test]
+    fn tag_metadata() {
+        assert!(Tag::SOURCE.is_void() && Tag::TRACK.is_void() && !Tag::AUDIO.is_void());
+        assert!(Tag::XMP.is_raw_text() && !Tag::TEXTAREA.is_raw_text());
+        assert!(Tag::TITLE.is_escapable_raw_text());
+        assert!(Tag::SECTION.is_sectioning() && Tag::SECTION.is_flow() && !Tag::SECTION.is_phrasing());
+        assert!(Tag::MARK.is_phrasing() && Tag::MARK.is_flow());
+        assert!(Tag::HGROUP.is_heading() && Tag::HGROUP.is_flow());
+        assert!(Tag::DETAILS.is_interactive() && !Tag::DIV.is_interactive());
+        assert!(Tag::OUTPUT.is_form_associated() && !Tag::FORM.is_form_associated());
+        assert!(!Tag::TITLE.is_flow() && !Tag::LI.is_flow());
+        assert!("svg".parse::<Tag>().unwrap().is_phrasing() && !Tag::Svg("circle".into()).is_phrasing());
+        assert!(!Tag::Unknown("x-card".into()).is_flow());
+
+        assert!(Tag::TR.is_block() && !Tag::TR.is_inline());
+        assert!(Tag::SPAN.is_inline() && Tag::Unknown("x-card".into()).is_inline());
+        assert!(!Tag::SCRIPT.is_block() && !Tag::SCRIPT.is_inline());
+    }
 }
@@ -0,0 +1,1242 @@
+use std;
+use std::collections::VecDeque;
+use std::io::{BufRead, Read};
+
+use tag::{Tag, tag_from_name, tag_in_namespace, tag_spelling};
+use attribute::{Attribute, RawAttribute, adjust_attributes, remove_duplicate_attributes};
+use atom::{Atom, Atoms};
+use namespace::{Namespace, ForeignContent};
+use doctype::{Doctype};
+use error::{Error, ParseError};
+use encoding::{DecodingReader};
+use encoding_rs::{Encoding, UTF_8};
+use position::{Position, Span, Cursor};
+use parser::{ParserOptions, TokenizerMode};
+use whatwg::{Machine};
+use charref;
+
+/// Token produced by [Tokenizer](struct.Tokenizer.html).
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    /// Start tag, eg. ```<p class="info">```
+    StartTag {
+        tag: Tag,
+        /// Name as written when the case is preserved and it differs
+        /// from the name of the tag, eg. ```BR``` for ```<BR>```
+        spelling: Option<Atom>,
+        attributes: Vec<Attribute>,
+        self_closing: bool,
+        span: Span,
+    },
+
+    /// End tag, eg. ```</p>```
+    EndTag {
+        tag: Tag,
+        span: Span,
+    },
+
+    /// Text between tags and the content of elements such as
+    /// ```<script>``` and ```<textarea>```
+    Text {
+        data: Vec<u8>,
+        span: Span,
+    },
+
+    /// Comment, eg. the text between ```<!--``` and ```-->```
+    Comment {
+        data: Vec<u8>,
+        span: Span,
+    },
+
+    /// Doctype declaration
+    Doctype {
+        doctype: Doctype,
+        span: Span,
+    },
+
+    /// Content of a CDATA section
+    CData {
+        data: Vec<u8>,
+        span: Span,
+    },
+
+    /// Processing instruction, eg. the text between ```<?``` and
+    /// ```?>```
+    ProcessingInstruction {
+        data: Vec<u8>,
+        span: Span,
+    },
+
+    /// Recoverable error in the document
+    Error {
+        error: ParseError,
+        span: Span,
+    },
+}
+
+impl Token {
+    /// Get span of the token in the source document
+    pub fn span(&self) -> &Span {
+        match *self {
+            Token::StartTag { ref span, .. } => span,
+            Token::EndTag { ref span, .. } => span,
+            Token::Text { ref span, .. } => span,
+            Token::Comment { ref span, .. } => span,
+            Token::Doctype { ref span, .. } => span,
+            Token::CData { ref span, .. } => span,
+            Token::ProcessingInstruction { ref span, .. } => span,
+            Token::Error { ref span, .. } => span,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ParserTag {
+    name: Vec<u8>,
+    pub id: Option<Tag>,
+    closing: bool,
+    data: Vec<u8>,
+    pub attributes: Vec<RawAttribute>,
+    start: Position,
+    data_start: Position,
+    cursor: Cursor,
+    foreign: ForeignContent,
+    atoms: Atoms,
+}
+
+impl std::fmt::Display for ParserTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.closing {
+            false => f.write_str("<"),
+            true => f.write_str("</")
+        }.unwrap();
+
+        f.write_str(&format!("{}",self.id.as_ref().unwrap())).unwrap();
+
+        let mut av: Vec<String> = Vec::new();
+        for attr in self.attributes.iter() {
+            let name = String::from_utf8_lossy(&attr.name);
+            av.push(match attr.value.is_empty() {
+                true => name.into_owned(),
+                false => format!("{}=\"{}\"", name, String::from_utf8_lossy(&attr.value)),
+            });
+        }
+        if !av.is_empty() {
+            f.write_str(" ").unwrap();
+            f.write_str(av.join(" ").as_str()).unwrap();
+        }
+
+        f.write_str(">")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ParserState {
+    FindParserTag,
+    ReadComment,
+    ReadDoctype,
+    ReadCData,
+    ReadProcessingInstruction,
+    ReadParserTagName,
+    ReadData,
+    ReadRawData,
+    ReadRcData,
+    ReadPlainText,
+    ReadAttributeName,
+    ReadAttributeValue,
+}
+
+/// Get the length of data truncated to at most max bytes, without
+/// splitting a UTF-8 sequence.
+pub fn truncated_length(data: &[u8], max: usize) -> usize {
+    if data.len() <= max {
+        return data.len();
+    }
+
+    let mut length = max;
+    while length > 0 && max - length < 3 && data[length] & 0xC0 == 0x80 {
+        length -= 1;
+    }
+    match data[length] & 0xC0 == 0x80 {
+        true => max,
+        false => length,
+    }
+}
+
+/// Incremental state machine turning bytes into tokens, data is pushed
+/// in blocks and tokens are taken out when complete.
+pub struct Scanner {
+    state: ParserState,
+    tag: ParserTag,
+    buf: Vec<u8>,
+    position: Position,
+    total_parsed: usize,
+    tokens: VecDeque<Token>,
+    machine: Machine,
+}
+
+impl Scanner {
+
+    fn _state_find_tag(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState) -> usize {
+        let mut processed = 0;
+        for b in buf {
+            if *b == b'<' {
+                tag.start = tag.cursor.at(buf, processed);
+                tag.name.clear();
+                tag.id = None;
+                tag.data.clear();
+                tag.closing = false;
+                *state = ParserState::ReadParserTagName;
+                break;
+            }
+            processed += 1;
+        }
+        processed
+    }
+
+    // Test if buf starts with keyword, None if more data is needed to tell
+    fn _starts_with(buf: &[u8], keyword: &[u8], ignore_case: bool) -> Option<bool> {
+        let length = std::cmp::min(buf.len(), keyword.len());
+        let matched = match ignore_case {
+            true => buf[..length].eq_ignore_ascii_case(&keyword[..length]),
+            false => buf[..length] == keyword[..length],
+        };
+
+        if !matched {
+            Some(false)
+        } else if length < keyword.len() {
+            None
+        } else {
+            Some(true)
+        }
+    }
+
+    // Read data into tag until terminator, returns processed bytes and
+    // if the terminator was found.
+    fn _read_until(buf: &[u8], tag: &mut ParserTag, terminator: &[u8], keep: bool) -> (usize, bool) {
+        let mut processed = 0;
+        loop {
+            if (processed + terminator.len()) > buf.len() {
+                // we need more data to continue
+                return (processed, false);
+            }
+
+            if buf[processed..].starts_with(terminator) {
+                return (processed + terminator.len(), true);
+            }
+
+            if keep {
+                tag.data.push(buf[processed]);
+            }
+            processed += 1
+        }
+    }
+
+    fn _state_read_comment(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, options: &ParserOptions, tokens: &mut VecDeque<Token>) -> usize {
+        let (processed, found) = Scanner::_read_until(buf, tag, b"-->", !options.skip_comments);
+        if found {
+            if !options.skip_comments {
+                let span = Span::new(tag.start, tag.cursor.at(buf, processed));
+                tokens.push_back(Token::Comment { data: tag.data.clone(), span });
+            }
+            tag.data.clear();
+            *state = ParserState::ReadData;
+        }
+
+        processed
+    }
+
+    fn _state_read_cdata(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, tokens: &mut VecDeque<Token>) -> usize {
+        let (processed, found) = Scanner::_read_until(buf, tag, b"]]>", true);
+        if found {
+            let span = Span::new(tag.start, tag.cursor.at(buf, processed));
+            tokens.push_back(Token::CData { data: tag.data.clone(), span });
+            tag.data.clear();
+            *state = ParserState::ReadData;
+        }
+
+        processed
+    }
+
+    fn _state_read_processing_instruction(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, tokens: &mut VecDeque<Token>) -> usize {
+        let (processed, found) = Scanner::_read_until(buf, tag, b"?>", true);
+        if found {
+            let span = Span::new(tag.start, tag.cursor.at(buf, processed));
+            tokens.push_back(Token::ProcessingInstruction { data: tag.data.clone(), span });
+            tag.data.clear();
+            *state = ParserState::ReadData;
+        }
+
+        processed
+    }
+
+    fn _state_read_doctype(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, tokens: &mut VecDeque<Token>) -> usize {
+        let mut processed = 0;
+        for b in buf {
+            processed += 1;
+            if *b == b'>' {
+                let span = Span::new(tag.start, tag.cursor.at(buf, processed));
+                tokens.push_back(Token::Doctype { doctype: Doctype::parse(&tag.data), span });
+                tag.data.clear();
+                *state = ParserState::ReadData;
+                break;
+            }
+            tag.data.push(*b);
+        }
+
+        processed
+    }
+
+    // Report error for the byte at index
+    fn _error(buf: &[u8], tag: &mut ParserTag, index: usize, error: ParseError, tokens: &mut VecDeque<Token>) {
+        let span = Span::new(tag.cursor.at(buf, index), tag.cursor.at(buf, index + 1));
+        tokens.push_back(Token::Error { error, span });
+    }
+
+    fn _state_read_tag_name(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, options: &ParserOptions, tokens: &mut VecDeque<Token>) -> usize {
+        let mut processed = 0;
+
+        for b in buf {
+
+            match *b as char {
+                // Skip begin of tag
+                '<' => processed += 1,
+
+                '\r' | '\n' => {
+                    processed += 1;
+                },
+
+                // Comment, doctype or CDATA section detected
+                '!' => {
+                    let rest = &buf[processed + 1..];
+                    let comment = Scanner::_starts_with(rest, b"--", false);
+                    let doctype = Scanner::_starts_with(rest, b"DOCTYPE", true);
+                    let cdata = Scanner::_starts_with(rest, b"[CDATA[", false);
+
+                    *state = match (comment, doctype, cdata) {
+                        (Some(true), _, _) => {
+                            processed += 3;
+                            ParserState::ReadComment
+                        },
+                        (_, Some(true), _) => {
+                            processed += 8;
+                            ParserState::ReadDoctype
+                        },
+                        (_, _, Some(true)) => {
+                            processed += 8;
+                            ParserState::ReadCData
+                        },
+                        (None, _, _) | (_, None, _) | (_, _, None) => {
+                            // we need more data to continue
+                            break;
+                        },
+                        _ => {
+                            Scanner::_error(buf, tag, processed, ParseError::IncorrectlyOpenedComment, tokens);
+                            processed += 1;
+                            ParserState::FindParserTag
+                        }
+                    };
+                    break;
+                },
+
+                // Processing instruction detected, eg. <?xml ... ?>
+                '?' if tag.name.is_empty() && !tag.closing => {
+                    Scanner::_error(buf, tag, processed, ParseError::UnexpectedQuestionMarkInsteadOfTagName, tokens);
+                    processed += 1;
+                    *state = ParserState::ReadProcessingInstruction;
+                    break;
+                },
+
+                // Closing tag detected
+                '/' if tag.name.is_empty() => {
+                    tag.closing = true;
+                    processed += 1;
+                },
+
+                // Complete tag name read 
+                '>' | ' ' | '/' => {
+                    if tag.name.is_empty() {
+                        let error = match (*b, tag.closing) {
+                            (b'>', true) => ParseError::MissingEndTagName,
+                            _ => ParseError::InvalidFirstCharacterOfTagName,
+                        };
+                        Scanner::_error(buf, tag, processed, error, tokens);
+                    }
+                    tag.attributes.clear();
+                    tag.attributes.push(RawAttribute::new(Span::default()));
+                    *state = ParserState::ReadAttributeName;
+                    break;
+                },
+
+                _ => {
+                    if tag.name.is_empty() && !b.is_ascii_alphabetic() {
+                        Scanner::_error(buf, tag, processed, ParseError::InvalidFirstCharacterOfTagName, tokens);
+                    }
+                    // Keep one byte more than the limit to tell the name
+                    // is truncated
+                    if tag.name.len() <= options.max_name_length {
+                        tag.name.push(*b);
+                    }
+                    processed += 1;
+                }
+            }
+        }
+
+        processed
+    }
+
+    // Get state for reading the content of an element
+    fn _content_state(tag: &Tag) -> ParserState {
+        match *tag {
+            Tag::PLAINTEXT => ParserState::ReadPlainText,
+            _ if tag.is_raw_text() => ParserState::ReadRawData,
+            _ if tag.is_escapable_raw_text() => ParserState::ReadRcData,
+            _ => ParserState::ReadData,
+        }
+    }
+
+    // Test if buf starts with the end tag of element, eg. </script> or
+    // </SCRIPT foo>. Err if more data is needed to tell.
+    fn _is_element(buf: &[u8], el: &Option<Tag>) -> Result<bool, ()> {
+        let t = match *el {
+            Some(ref x) => x.to_string().into_bytes(),
+            None => return Ok(false),
+        };
+
+        if buf.len() < t.len() + 3 {
+            return Err(())
+        }
+
+        Ok(buf[1] == b'/' && buf[2..2+t.len()].eq_ignore_ascii_case(&t) && matches!(buf[2+t.len()], b'\t' | b'\n' | 0x0C | b'\r' | b' ' | b'/' | b'>'))
+    }
+
+    // Read data until next inline element
+    fn _state_read_data(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, options: &ParserOptions, tokens: &mut VecDeque<Token>) -> usize {
+        let mut processed = 0;
+
+        for b in buf {
+            match *b as char {
+                // Found begin of new tag which means we have read
+                // available data
+                '<' => {
+                    if !tag.data.is_empty() {
+                        let span = Span::new(tag.data_start, tag.cursor.at(buf, processed));
+                        match options.decode_entities {
+                            true => tokens.push_back(Token::Text { data: charref::decode(&tag.data, false), span }),
+                            false => tokens.push_back(Token::Text { data: tag.data.clone(), span }),
+                        }
+                    }
+                    *state = ParserState::FindParserTag;
+                    break;
+                },
+
+//                '\r' | '\n' => {
+//                    processed += 1;
+//                },
+
+                _ => {
+                    if tag.data.is_empty() {
+                        tag.data_start = tag.cursor.at(buf, processed);
+                    }
+                    tag.data.push(*b);
+                    processed += 1;
+                }
+            }
+        }
+
+        processed
+    }
+
+    // Read data until closing element, eg <script></script>, character
+    // references are decoded if decode is set
+    fn _state_read_raw_data(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, decode: bool, tokens: &mut VecDeque<Token>) -> usize {
+        let mut processed = 0;
+
+        for b in buf {
+
+            match *b as char {
+
+                // Found possible begin of closing tag
+                '<' => {
+
+                    match Scanner::_is_element(&buf[processed..], &tag.id) {
+                        Err(_) => break,
+                        Ok(x) => {
+                            match x {
+                                true => {
+                                    // Found closing tag, lets handle data
+                                    if !tag.data.is_empty() {
+                                        let span = Span::new(tag.data_start, tag.cursor.at(buf, processed));
+                                        match decode {
+                                            true => tokens.push_back(Token::Text { data: charref::decode(&tag.data, false), span }),
+                                            false => tokens.push_back(Token::Text { data: tag.data.clone(), span }),
+                                        }
+                                    }
+                                    *state = ParserState::FindParserTag;
+                                    break;
+                                },
+                                false => {
+                                    // Does not match, just byte add to data
+                                    if tag.data.is_empty() {
+                                        tag.data_start = tag.cursor.at(buf, processed);
+                                    }
+                                    tag.data.push(*b);
+                                    processed += 1; 
+                                }
+                            }
+                        }
+                    }
+                },
+
+                _ => {
+                    if tag.data.is_empty() {
+                        tag.data_start = tag.cursor.at(buf, processed);
+                    }
+                    tag.data.push(*b);
+                    processed += 1;
+                }
+            }
+        }
+
+        processed
+    }
+
+
+    // Read data until the end of the document, eg. after <plaintext>
+    fn _state_read_plain_text(buf: &[u8], tag: &mut ParserTag) -> usize {
+        if tag.data.is_empty() {
+            tag.data_start = tag.cursor.at(buf, 0);
+        }
+        tag.data.extend_from_slice(buf);
+        buf.len()
+    }
+
+    // Extend span of last attribute to include byte at index
+    fn _extend_attribute_span(buf: &[u8], tag: &mut ParserTag, index: usize) {
+        let start = tag.cursor.at(buf, index);
+        let end = tag.cursor.at(buf, index + 1);
+        let attr = tag.attributes.last_mut().unwrap();
+        if attr.name.is_empty() {
+            attr.span.start = start;
+        }
+        attr.span.end = end;
+    }
+
+    // Start a new attribute, when there are more attributes than the
+    // limit the last one is dropped
+    fn _new_attribute(tag: &mut ParserTag, options: &ParserOptions, tokens: &mut VecDeque<Token>) {
+        if tag.attributes.len() <= options.max_attributes {
+            tag.attributes.push(RawAttribute::new(Span::default()));
+            return;
+        }
+
+        let attr = tag.attributes.last_mut().unwrap();
+        if !attr.name.is_empty() {
+            tokens.push_back(Token::Error { error: ParseError::TooManyAttributes, span: attr.span });
+        }
+        *attr = RawAttribute::new(Span::default());
+    }
+
+    // Add byte to the value of the last attribute, keeping room for a
+    // quote and one byte more than the limit to tell the value is
+    // truncated
+    fn _push_value(tag: &mut ParserTag, b: u8, options: &ParserOptions) {
+        let value = &mut tag.attributes.last_mut().unwrap().value;
+        if value.len() <= options.max_attribute_value_length.saturating_add(1) {
+            value.push(b);
+        }
+    }
+
+    // Truncate names and values of the attributes to the limits and
+    // decode the values
+    fn _finish_attributes(tag: &mut ParserTag, options: &ParserOptions, tokens: &mut VecDeque<Token>) {
+        if tag.attributes.len() > options.max_attributes {
+            let attr = tag.attributes.pop().unwrap();
+            tokens.push_back(Token::Error { error: ParseError::TooManyAttributes, span: attr.span });
+        }
+
+        for attr in tag.attributes.iter_mut() {
+            let length = truncated_length(&attr.name, options.max_name_length);
+            if length < attr.name.len() {
+                attr.name.truncate(length);
+                tokens.push_back(Token::Error { error: ParseError::NameTooLong, span: attr.span });
+            }
+
+            let length = truncated_length(&attr.value, options.max_attribute_value_length);
+            let mut truncated = length < attr.value.len();
+            attr.value.truncate(length);
+            if options.decode_entities {
+                attr.value = charref::decode(&attr.value, true);
+                let length = truncated_length(&attr.value, options.max_attribute_value_length);
+                truncated |= length < attr.value.len();
+                attr.value.truncate(length);
+            }
+            if truncated {
+                tokens.push_back(Token::Error { error: ParseError::AttributeValueTooLong, span: attr.span });
+            }
+        }
+    }
+
+    fn _state_read_attribute_value(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, options: &ParserOptions, tokens: &mut VecDeque<Token> ) -> usize {
+        let mut processed = 0;
+        for b in buf {
+            match *b as char {
+                '>' => {
+                    let attr = tag.attributes.last().unwrap();
+                    if !attr.name.is_empty() && attr.value.is_empty() {
+                        Scanner::_error(buf, tag, processed, ParseError::MissingAttributeValue, tokens);
+                    }
+
+                    Scanner::_finish_tag(buf, tag, state, options, tokens, processed, false);
+                    tag.attributes.clear();
+                    processed += 1;
+                    break;
+                },
+
+                '"' | '\'' => {
+
+                    let have_value = !tag.attributes.last().as_mut().unwrap().value.is_empty();
+
+                    if have_value && *b != b' ' {
+
+                        Scanner::_extend_attribute_span(buf, tag, processed);
+                        {
+                            // Trim " and ' from attribute value
+                            let value = &mut tag.attributes.last_mut().unwrap().value;
+                            if !value.is_empty() && (value[0] == b'\'' || value[0] == b'"') {
+                                *value = value[1..].to_vec();
+                            }
+
+                            if !value.is_empty() && (value[value.len() - 1] == b'\'' || value[value.len() - 1] == b'"') {
+                                *value = value[..value.len() - 1].to_vec();
+                            }
+
+                        }
+
+                        Scanner::_new_attribute(tag, options, tokens);
+                        *state = ParserState::ReadAttributeName;
+                        processed += 1;
+                        break;
+
+                    } else {
+
+                        Scanner::_extend_attribute_span(buf, tag, processed);
+                        Scanner::_push_value(tag, *b, options);
+                        processed += 1;
+                    }
+                },
+
+                ' ' => {
+
+                    let (have_value, is_quoted) = {
+                        let value = &mut tag.attributes.last_mut().unwrap().value;
+                        match value.is_empty() {
+                            true => (false, false),
+                            false => (true, (value[0] == b'"' || value[0] == b'\'')),
+                        }
+                    };
+
+                    if have_value && !is_quoted {
+
+                        Scanner::_new_attribute(tag, options, tokens);
+                        *state = ParserState::ReadAttributeName;
+                        processed += 1;
+                        break;
+
+                    } else {
+
+                        Scanner::_extend_attribute_span(buf, tag, processed);
+                        Scanner::_push_value(tag, *b, options);
+                        processed += 1;
+                    }
+                }
+
+                _ => {
+                    Scanner::_extend_attribute_span(buf, tag, processed);
+                    Scanner::_push_value(tag, *b, options);
+                    processed += 1;
+                }
+            }
+        }
+        processed
+    }
+
+    // Finish start or end tag, index is the position of the closing '>'
+    fn _finish_tag(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, options: &ParserOptions, tokens: &mut VecDeque<Token>, index: usize, self_closing: bool) {
+        if tag.attributes.last().unwrap().name.is_empty() {
+            // pop last attribute if it is an empty placeholder
+            tag.attributes.pop();
+        }
+
+        let span = Span::new(tag.start, tag.cursor.at(buf, index + 1));
+        let length = truncated_length(&tag.name, options.max_name_length);
+        if length < tag.name.len() {
+            tag.name.truncate(length);
+            tokens.push_back(Token::Error { error: ParseError::NameTooLong, span });
+        }
+
+        let id = tag_from_name(&String::from_utf8_lossy(&tag.name), options.preserve_case, &mut tag.atoms);
+        let id = options.tags.resolve(&id).unwrap_or(id);
+
+        *state = match tag.closing {
+            false => {
+                Scanner::_finish_attributes(tag, options, tokens);
+                let namespace = tag.foreign.start_tag(&id, tag.attributes.iter().map(|x| (&x.name[..], &x.value[..])), self_closing);
+                tag.id = Some(tag_in_namespace(&id, namespace, options.preserve_case, &mut tag.atoms));
+                let atoms = &mut tag.atoms;
+                let mut attributes: Vec<Attribute> = tag.attributes.drain(..).map(|x| x.intern(atoms)).collect();
+                adjust_attributes(&mut attributes, namespace, options.preserve_case, atoms);
+                remove_duplicate_attributes(&mut attributes, |x| x.name.as_bytes(), |x| {
+                    tokens.push_back(Token::Error { error: ParseError::DuplicateAttribute, span: x.span });
+                });
+                let spelling = tag_spelling(tag.id.as_ref().unwrap(), &String::from_utf8_lossy(&tag.name), options.preserve_case, atoms);
+                tokens.push_back(Token::StartTag { tag: tag.id.clone().unwrap(), spelling, attributes, self_closing, span });
+                match namespace {
+                    Namespace::Html => Scanner::_content_state(tag.id.as_ref().unwrap()),
+                    _ => ParserState::ReadData,
+                }
+            },
+            true => {
+                if !tag.attributes.is_empty() {
+                    tokens.push_back(Token::Error { error: ParseError::EndTagWithAttributes, span });
+                }
+                if self_closing {
+                    tokens.push_back(Token::Error { error: ParseError::EndTagWithTrailingSolidus, span });
+                }
+                let namespace = tag.foreign.end_tag(&id);
+                tag.id = Some(tag_in_namespace(&id, namespace, options.preserve_case, &mut tag.atoms));
+                tokens.push_back(Token::EndTag { tag: tag.id.clone().unwrap(), span });
+                ParserState::ReadData
+            }
+        };
+    }
+
+    fn _state_read_attribute_name(buf: &[u8], tag: &mut ParserTag, state: &mut ParserState, options: &ParserOptions, tokens: &mut VecDeque<Token>) -> usize {
+        let mut processed = 0;
+
+        for b in buf {
+
+            match *b as char {
+
+                // Self-closing tag, eg. <br/>, or a misplaced solidus
+                '/' => {
+                    match buf.get(processed + 1) {
+                        Some(&b'>') => {
+                            Scanner::_finish_tag(buf, tag, state, options, tokens, processed + 1, true);
+                            processed += 2;
+                            break;
+                        },
+                        Some(_) => {
+                            Scanner::_error(buf, tag, processed, ParseError::UnexpectedSolidusInTag, tokens);
+                            if !tag.attributes.last().unwrap().name.is_empty() {
+                                Scanner::_new_attribute(tag, options, tokens);
+                            }
+                            processed += 1;
+                        },
+                        // we need more data to continue
+                        None => break,
+                    }
+                },
+
+                // Found closing, lets finish up
+                '>' => {
+                    Scanner::_finish_tag(buf, tag, state, options, tokens, processed, false);
+                    processed += 1;
+                    break;
+                },
+
+                '=' => {
+                    *state = ParserState::ReadAttributeValue;
+                    processed += 1;
+                    break;
+                },
+
+                ' ' => {
+                    if !tag.attributes.last().unwrap().name.is_empty() {
+                        Scanner::_new_attribute(tag, options, tokens);
+                        *state = ParserState::ReadAttributeName;
+                    }
+
+                    processed += 1;
+                },
+
+                '\n' | '\r' | '\t' => {
+                    processed += 1;
+                },
+
+                _ => {
+                    Scanner::_extend_attribute_span(buf, tag, processed);
+                    let name = &mut tag.attributes.last_mut().unwrap().name;
+                    if name.len() <= options.max_name_length {
+                        name.push(*b);
+                    }
+                    processed += 1;
+                }
+            }
+        }
+
+        processed
+    }
+
+    /// Create scanner at the start of a document
+    pub fn new() -> Scanner {
+        let position = Position::new();
+        Scanner {
+            state: ParserState::FindParserTag,
+            tag: ParserTag {
+                name: Vec::new(),
+                id: None,
+                closing: false,
+                data: Vec::new(),
+                attributes: Vec::new(),
+                start: position,
+                data_start: position,
+                cursor: Cursor::new(position),
+                foreign: ForeignContent::new(),
+                atoms: Atoms::new(),
+            },
+            buf: Vec::new(),
+            position,
+            total_parsed: 0,
+            tokens: VecDeque::new(),
+            machine: Machine::new(),
+        }
+    }
+
+    /// Add data to be scanned
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Get the next complete token
+    pub fn next_token(&mut self) -> Option<Token> {
+        self.tokens.pop_front()
+    }
+
+    /// Get the number of bytes scanned so far
+    pub fn parsed(&self) -> usize {
+        self.total_parsed
+    }
+
+    /// Run the state machine over buffered data until more data is
+    /// needed, at end of file data that can not be parsed is discarded.
+    pub fn run(&mut self, options: &ParserOptions, end_of_file: bool) {
+        if options.tokenizer == TokenizerMode::Whatwg {
+            let (processed, position) = self.machine.run(&self.buf, self.position, options, end_of_file, &mut self.tokens);
+            self.buf.drain(..processed);
+            self.position = position;
+            self.total_parsed += processed;
+            return;
+        }
+
+        let buf = &mut self.buf;
+        let tag = &mut self.tag;
+        let state = &mut self.state;
+        let tokens = &mut self.tokens;
+
+        while !buf.is_empty() {
+            let previous_state = *state;
+            tag.cursor = Cursor::new(self.position);
+            let processed = match *state {
+                ParserState::FindParserTag => Scanner::_state_find_tag(buf, tag, state),
+                ParserState::ReadComment => Scanner::_state_read_comment(buf, tag, state, options, tokens),
+                ParserState::ReadDoctype => Scanner::_state_read_doctype(buf, tag, state, tokens),
+                ParserState::ReadCData => Scanner::_state_read_cdata(buf, tag, state, tokens),
+                ParserState::ReadProcessingInstruction => Scanner::_state_read_processing_instruction(buf, tag, state, tokens),
+                ParserState::ReadParserTagName => Scanner::_state_read_tag_name(buf, tag, state, options, tokens),
+                ParserState::ReadData => Scanner::_state_read_data(buf, tag, state, options, tokens),
+                ParserState::ReadRawData => Scanner::_state_read_raw_data(buf, tag, state, false, tokens),
+                ParserState::ReadRcData => Scanner::_state_read_raw_data(buf, tag, state, options.decode_entities, tokens),
+                ParserState::ReadPlainText => Scanner::_state_read_plain_text(buf, tag),
+                ParserState::ReadAttributeName => Scanner::_state_read_attribute_name(buf, tag, state, options, tokens),
+                ParserState::ReadAttributeValue => Scanner::_state_read_attribute_value(buf, tag, state, options, tokens),
+            };
+
+            if processed == 0 {
+                if *state != previous_state {
+                    continue;
+                }
+
+                // Nothing more can be parsed, discard what is left
+                // except raw text which runs to the end of the document
+                if end_of_file {
+                    if *state == ParserState::ReadRawData || *state == ParserState::ReadRcData {
+                        if tag.data.is_empty() {
+                            tag.data_start = tag.cursor.at(buf, 0);
+                        }
+                        tag.data.extend_from_slice(buf);
+                    }
+                    self.position = tag.cursor.at(buf, buf.len());
+                    buf.clear();
+                }
+                break;
+            }
+
+            self.position = tag.cursor.at(buf, processed);
+            buf.drain(..processed);
+            self.total_parsed += processed;
+        }
+    }
+
+    /// Emit text at the end of the document and report errors for a
+    /// document ending inside a token
+    pub fn finish(&mut self, options: &ParserOptions) {
+        if options.tokenizer == TokenizerMode::Whatwg {
+            self.machine.finish(self.position, options, &mut self.tokens);
+            return;
+        }
+
+        if !self.tag.data.is_empty() {
+            let span = Span::new(self.tag.data_start, self.position);
+            match self.state {
+                ParserState::ReadData | ParserState::ReadRcData if options.decode_entities => self.tokens.push_back(Token::Text { data: charref::decode(&self.tag.data, false), span }),
+                ParserState::ReadData | ParserState::ReadRcData | ParserState::ReadRawData | ParserState::ReadPlainText => self.tokens.push_back(Token::Text { data: self.tag.data.clone(), span }),
+                _ => (),
+            }
+        }
+
+        let error = match self.state {
+            ParserState::ReadComment => Some(ParseError::EofInComment),
+            ParserState::ReadDoctype => Some(ParseError::EofInDoctype),
+            ParserState::ReadCData => Some(ParseError::EofInCdata),
+            ParserState::ReadParserTagName if self.tag.name.is_empty() && !self.tag.closing => Some(ParseError::EofBeforeTagName),
+            ParserState::ReadParserTagName | ParserState::ReadAttributeName | ParserState::ReadAttributeValue => Some(ParseError::EofInTag),
+            _ => None,
+        };
+        if let Some(x) = error {
+            self.tokens.push_back(Token::Error { error: x, span: Span::new(self.position, self.position) });
+        }
+        self.tag.data.clear();
+        self.state = ParserState::FindParserTag;
+    }
+}
+
+// Source of the tokenizer, transcoded to UTF-8 or read as is
+enum Input<R: BufRead> {
+    Decoded(DecodingReader<R>),
+    Raw(R),
+    Failed,
+}
+
+/// Pull based tokenizer for HTML documents.
+///
+/// The tokenizer reads the document from any ```BufRead``` and is
+/// iterated for [Token]s. It is the same tokenizer as used by
+/// [Parser](struct.Parser.html) and it behaves the same.
+///
+/// [Token]: enum.Token.html
+///
+/// # Examples
+///
+/// ```
+/// use domx::{Tokenizer, Token};
+///
+/// let html = "<p class=\"info\">Hello</p>";
+/// for token in Tokenizer::new(html.as_bytes()) {
+///     match token.unwrap() {
+///         Token::StartTag { tag, .. } => println!("start {}", tag),
+///         Token::Text { data, .. } => println!("text {}", String::from_utf8_lossy(&data)),
+///         _ => (),
+///     }
+/// }
+/// ```
+pub struct Tokenizer<R: BufRead> {
+    input: Input<R>,
+    options: ParserOptions,
+    encoding: &'static Encoding,
+    scanner: Scanner,
+    error: Option<Error>,
+    end_of_file: bool,
+    finished: bool,
+}
+
+impl<R: BufRead> Tokenizer<R> {
+    /// Create a new tokenizer reading from source
+    pub fn new(source: R) -> Tokenizer<R> {
+        Tokenizer::with_options(source, &ParserOptions::default())
+    }
+
+    /// Create a new tokenizer reading from source using options
+    ///
+    /// The encoding of the document is detected when the tokenizer is
+    /// created, which reads the start of source. A failure to read is
+    /// returned as the first token.
+    pub fn with_options(source: R, options: &ParserOptions) -> Tokenizer<R> {
+        let (input, encoding, error) = match options.detect_encoding {
+            true => match DecodingReader::new(source, options.transport_encoding) {
+                Ok(x) => {
+                    let encoding = x.encoding();
+                    (Input::Decoded(x), encoding, None)
+                },
+                Err(x) => (Input::Failed, UTF_8, Some(Error::Io(x))),
+            },
+            false => (Input::Raw(source), UTF_8, None),
+        };
+
+        Tokenizer {
+            input,
+            options: options.clone(),
+            encoding,
+            scanner: Scanner::new(),
+            error,
+            end_of_file: false,
+            finished: false,
+        }
+    }
+
+    /// Get the character encoding of the document
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Get the number of bytes of the document parsed so far
+    pub fn parsed(&self) -> usize {
+        self.scanner.total_parsed
+    }
+
+    // Read next block from input
+    fn _read(&mut self, block: &mut [u8]) -> std::io::Result<usize> {
+        match self.input {
+            Input::Decoded(ref mut x) => x.read(block),
+            Input::Raw(ref mut x) => x.read(block),
+            Input::Failed => Ok(0),
+        }
+    }
+
+    // Read more data and run the scanner
+    fn _step(&mut self) -> Result<(), Error> {
+        // If buffer is low and there is still data to be read, read block
+        if !self.end_of_file && self.scanner.buf.len() < 64 {
+            let mut block = [0; 2048];
+            let bytes_read = self._read(&mut block[..])?;
+            match bytes_read {
+                0 => self.end_of_file = true,
+                _ => self.scanner.buf.extend_from_slice(&block[..bytes_read]),
+            };
+        }
+
+        self.scanner.run(&self.options, self.end_of_file);
+
+        if self.end_of_file && self.scanner.buf.is_empty() {
+            self.scanner.finish(&self.options);
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for Tokenizer<R> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Result<Token, Error>> {
+        loop {
+            if let Some(token) = self.scanner.tokens.pop_front() {
+                return Some(Ok(token));
+            }
+
+            if let Some(error) = self.error.take() {
+                self.finished = true;
+                return Some(Err(error));
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            if let Err(error) = self._step() {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tag::Tag;
+    use error::ParseError;
+    use tokenizer::{Tokenizer, Token};
+    use parser::{ParserOptions, TokenizerMode};
+    use std::io::BufReader;
+
+    fn tokens(data: &str) -> Vec<Token> {
+        Tokenizer::new(data.as_bytes()).map(|x| x.unwrap()).collect()
+    }
+
+    #[test]
+    fn tokenize_empty_document() {
+        assert_eq!(tokens("").len(), 0);
+    }
+
+    #[test]
+    fn tokenize_document() {
+        let t = tokens("<!DOCTYPE html><p class=\"info\">Hello<br/></p><!-- end -->");
+        assert_eq!(t.len(), 6);
+        match t[0] {
+            Token::Doctype { ref doctype, .. } => assert_eq!(doctype.name(), Some("html")),
+            _ => panic!("expected doctype"),
+        }
+        match t[1] {
+            Token::StartTag { ref tag, ref attributes, self_closing, .. } => {
+                assert_eq!(*tag, Tag::P);
+                assert_eq!(attributes[0].value().unwrap(), "info");
+                assert!(!self_closing);
+            },
+            _ => panic!("expected start tag"),
+        }
+        match t[2] {
+            Token::Text { ref data, ref span } => {
+                assert_eq!(data, b"Hello");
+                assert_eq!(span.start.offset, 31);
+            },
+            _ => panic!("expected text"),
+        }
+        match t[4] {
+            Token::EndTag { ref tag, .. } => assert_eq!(*tag, Tag::P),
+            _ => panic!("expected end tag"),
+        }
+        match t[5] {
+            Token::Comment { ref data, .. } => assert_eq!(data, b" end "),
+            _ => panic!("expected comment"),
+        }
+    }
+
+    #[test]
+    fn tokenize_self_closing_tag() {
+        let t = tokens("<img src=\"x\" /><br/>");
+        assert_eq!(t.len(), 2);
+        match t[0] {
+            Token::StartTag { ref tag, self_closing, ref span, .. } => {
+                assert_eq!(*tag, Tag::IMG);
+                assert!(self_closing);
+                assert_eq!(span.len(), 15);
+            },
+            _ => panic!("expected start tag"),
+        }
+        match t[1] {
+            Token::StartTag { ref tag, self_closing, .. } => {
+                assert_eq!(*tag, Tag::BR);
+                assert!(self_closing);
+            },
+            _ => panic!("expected start tag"),
+        }
+    }
+
+    #[test]
+    fn tokenize_adjusts_or_preserves_case() {
+        let data = "<SVG ViewBox=\"0 0 1 1\"><foreignobject><DIV viewbox=x></DIV></foreignObject>";
+        for mode in [TokenizerMode::Legacy, TokenizerMode::Whatwg].iter() {
+            let names = |preserve_case: bool| -> Vec<String> {
+                let options = ParserOptions { tokenizer: *mode, preserve_case, ..Default::default() };
+                Tokenizer::with_options(data.as_bytes(), &options).map(|x| match x.unwrap() {
+                    Token::StartTag { tag, spelling, attributes, .. } => {
+                        let name = spelling.map(|x| x.to_string()).unwrap_or(tag.to_string());
+                        attributes.iter().fold(format!("<{} {}", tag.namespace(), name), |x, y| x + " " + y.name().unwrap()) + ">"
+                    },
+                    Token::EndTag { tag, .. } => format!("</{} {}>", tag.namespace(), tag),
+                    _ => panic!("expected tag"),
+                }).collect()
+            };
+            assert_eq!(names(false), ["<svg svg viewBox>", "<svg foreignObject>", "<html div viewbox>", "</html div>", "</svg foreignObject>"]);
+            assert_eq!(names(true), ["<svg SVG ViewBox>", "<svg foreignobject>", "<html DIV viewbox>", "</html div>", "</svg foreignObject>"]);
+        }
+    }
+
+    #[test]
+    fn tokenize_foreign_content() {
+        let data = "<svg><style>a<g/></style><![CDATA[<x>]]><a xlink:href=x></a></svg><title>a<b></title><![CDATA[y]]>";
+        for mode in [TokenizerMode::Legacy, TokenizerMode::Whatwg].iter() {
+            let options = ParserOptions { tokenizer: *mode, ..Default::default() };
+            let tokens: Vec<String> = Tokenizer::with_options(data.as_bytes(), &options).map(|x| match x.unwrap() {
+                Token::StartTag { tag, attributes, .. } => {
+                    attributes.iter().fold(format!("<{} {}", tag.namespace(), tag), |x, y| format!("{} {:?}", x, y.namespace())) + ">"
+                },
+                Token::EndTag { tag, .. } => format!("</{} {}>", tag.namespace(), tag),
+                Token::Text { data, .. } | Token::CData { data, .. } | Token::Comment { data, .. } => String::from_utf8(data).unwrap(),
+                _ => String::new(),
+            }).collect();
+            assert_eq!(tokens[..12], ["<svg svg>", "<svg style>", "a", "<svg g>", "</svg style>", "<x>", "<svg a Some(XLink)>",
+                                      "</svg a>", "</svg svg>", "<html title>", "a<b>", "</html title>"]);
+        }
+
+        let options = ParserOptions { tokenizer: TokenizerMode::Whatwg, ..Default::default() };
+        match Tokenizer::with_options(data.as_bytes(), &options).last().unwrap().unwrap() {
+            Token::Comment { data, .. } => assert_eq!(data, b"[CDATA[y]]"),
+            _ => panic!("expected comment"),
+        }
+    }
+
+    #[test]
+    fn tokenize_custom_tags() {
+        let mut tags = ::TagRegistry::new();
+        tags.register("x-code", ::TagProperties { raw_text: true, ..Default::default() });
+        let data = "<x-code><b>&amp;</b></x-code><x-card></x-card>";
+        for mode in [TokenizerMode::Legacy, TokenizerMode::Whatwg].iter() {
+            let options = ParserOptions { tokenizer: *mode, tags: tags.clone(), ..Default::default() };
+            let tokens: Vec<Token> = Tokenizer::with_options(data.as_bytes(), &options).map(|x| x.unwrap()).collect();
+            match tokens[0] {
+                Token::StartTag { ref tag, .. } => assert_eq!(*tag, tags.get("x-code").unwrap()),
+                _ => panic!("expected start tag"),
+            }
+            match tokens[1] {
+                Token::Text { ref data, .. } => assert_eq!(data, b"<b>&amp;</b>"),
+                _ => panic!("expected text"),
+            }
+            match tokens[3] {
+                Token::StartTag { ref tag, .. } => assert_eq!(*tag, "x-card".parse::<Tag>().unwrap()),
+                _ => panic!("expected start tag"),
+            }
+        }
+    }
+
+    #[test]
+    fn tokenize_limits_tags() {
+        let mut data = "<p a=1 b=2 c=3 dddddddd=4>".to_string();
+        data.push_str(&format!("<pppppppp title=\"{}\">", "x".repeat(100000)));
+        for mode in [TokenizerMode::Legacy, TokenizerMode::Whatwg].iter() {
+            let options = ParserOptions {
+                tokenizer: *mode,
+                max_name_length: 4,
+                max_attributes: 2,
+                max_attribute_value_length: 10,
+                ..Default::default()
+            };
+            let mut tags = Vec::new();
+            let mut errors = Vec::new();
+            for x in Tokenizer::with_options(data.as_bytes(), &options) {
+                match x.unwrap() {
+                    Token::StartTag { tag, attributes, .. } => {
+                        tags.push(attributes.iter().fold(format!("<{}", tag), |x, y| x + " " + &y.to_string()) + ">");
+                    },
+                    Token::Error { error, .. } => errors.push(error.code()),
+                    _ => panic!("expected start tag or error"),
+                }
+            }
+            assert_eq!(tags, ["<p a=\"1\" b=\"2\">", "<pppp titl=\"xxxxxxxxxx\">"]);
+            errors.sort();
+            assert_eq!(errors, ["attribute-value-too-long", "name-too-long", "name-too-long", "too-many-attributes", "too-many-attributes"]);
+        }
+    }
+
+    #[test]
+    fn tokenize_reports_errors_in_order() {
+        let t = tokens("<p id=a id=b>x<!-- open");
+        assert_eq!(t.len(), 4);
+        assert_eq!(t[0], Token::Error { error: ParseError::DuplicateAttribute, span: *t[0].span() });
+        match t[3] {
+            Token::Error { error, .. } => assert_eq!(error, ParseError::EofInComment),
+            _ => panic!("expected error"),
+        }
+    }
+
+    #[test]
+    fn tokenize_large_document() {
+        let mut data = String::new();
+        for i in 0..1000 {
+            data.push_str(&format!("<li id=\"{}\">item</li>\n", i));
+        }
+        let mut tokenizer = Tokenizer::new(BufReader::new(data.as_bytes()));
+        let starttags = tokenizer.by_ref().filter(|x| matches!(*x, Ok(Token::StartTag { .. }))).count();
+        assert_eq!(starttags, 1000);
+        assert_eq!(tokenizer.parsed(), data.len());
+    }
+}
@@ -0,0 +1,2055 @@
+use std;
+use std::collections::VecDeque;
+
+use tag::{Tag, tag_from_name, tag_in_namespace, tag_spelling};
+use attribute::{Attribute, RawAttribute, adjust_attributes, remove_duplicate_attributes};
+use atom::{Atoms};
+use namespace::{Namespace, ForeignContent};
+use doctype::{Doctype};
+use error::{ParseError};
+use position::{Position, Span};
+use parser::{ParserOptions};
+use tokenizer::{Token, truncated_length};
+use charref;
+
+// Replacement for U+0000 in all states but the data state
+const REPLACEMENT: &[u8] = "\u{FFFD}".as_bytes();
+
+// Tokenization states of the HTML Living Standard
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Data,
+    RcData,
+    RawText,
+    ScriptData,
+    PlainText,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RcDataLessThanSign,
+    RcDataEndTagOpen,
+    RcDataEndTagName,
+    RawTextLessThanSign,
+    RawTextEndTagOpen,
+    RawTextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+}
+
+// Outcome of processing the current input character
+enum Step {
+    // The character is consumed
+    Consume,
+    // The character is processed again in the new state
+    Reconsume,
+    // The character and the bytes following it, in total the given
+    // number of bytes, are consumed
+    Skip(usize),
+    // More data is needed to continue
+    Wait,
+    // End of file is processed
+    Done,
+}
+
+/// Tokenizer state machine following the HTML Living Standard, data
+/// is passed in blocks and tokens are added to a queue when complete.
+pub struct Machine {
+    state: State,
+    return_state: State,
+
+    // Switch to the state for the content of elements such as
+    // <script> after their start tag and read CDATA sections in
+    // foreign content, which the standard leaves to the tree builder.
+    switch_content: bool,
+    foreign: ForeignContent,
+
+    // Names of the tags and attributes read
+    atoms: Atoms,
+
+    // Tag being read
+    start: Position,
+    name: Vec<u8>,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<RawAttribute>,
+    last_start_tag: Vec<u8>,
+
+    // Comment or doctype being read
+    data: Vec<u8>,
+    doctype_name: Option<Vec<u8>>,
+    public_id: Option<Vec<u8>>,
+    system_id: Option<Vec<u8>>,
+    force_quirks: bool,
+
+    // Temporary buffer and character reference being read
+    buffer: Vec<u8>,
+    reference: u32,
+    reference_start: Position,
+
+    // Text not yet emitted and errors reported while reading it
+    text: Vec<u8>,
+    text_span: Span,
+    errors: Vec<Token>,
+}
+
+impl Machine {
+
+    /// Create state machine at the start of a document
+    pub fn new() -> Machine {
+        Machine {
+            state: State::Data,
+            return_state: State::Data,
+            switch_content: true,
+            foreign: ForeignContent::new(),
+            atoms: Atoms::new(),
+            start: Position::new(),
+            name: Vec::new(),
+            closing: false,
+            self_closing: false,
+            attributes: Vec::new(),
+            last_start_tag: Vec::new(),
+            data: Vec::new(),
+            doctype_name: None,
+            public_id: None,
+            system_id: None,
+            force_quirks: false,
+            buffer: Vec::new(),
+            reference: 0,
+            reference_start: Position::new(),
+            text: Vec::new(),
+            text_span: Span::default(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn _is_whitespace(c: u8) -> bool {
+        matches!(c, b'\t' | b'\n' | 0x0C | b' ')
+    }
+
+    // Test if rest starts with keyword, None if more data is needed to
+    // tell
+    fn _starts_with(rest: &[u8], keyword: &[u8], ignore_case: bool, end_of_file: bool) -> Option<bool> {
+        let length = std::cmp::min(rest.len(), keyword.len());
+        let matched = match ignore_case {
+            true => rest[..length].eq_ignore_ascii_case(&keyword[..length]),
+            false => rest[..length] == keyword[..length],
+        };
+
+        if matched && length < keyword.len() {
+            match end_of_file {
+                true => Some(false),
+                false => None,
+            }
+        } else {
+            Some(matched)
+        }
+    }
+
+    // Get state for reading the content of an element
+    fn _content_state(tag: &Tag) -> State {
+        match *tag {
+            Tag::SCRIPT => State::ScriptData,
+            Tag::PLAINTEXT => State::PlainText,
+            _ if tag.is_raw_text() => State::RawText,
+            _ if tag.is_escapable_raw_text() => State::RcData,
+            _ => State::Data,
+        }
+    }
+
+    // Add characters to the text being read
+    fn _text(&mut self, data: &[u8], start: Position, end: Position) {
+        if self.text.is_empty() {
+            self.text_span.start = start;
+        }
+        self.text.extend_from_slice(data);
+        self.text_span.end = end;
+    }
+
+    // Report error, errors in text follow the text token
+    fn _error(&mut self, error: ParseError, start: Position, end: Position, tokens: &mut VecDeque<Token>) {
+        let token = Token::Error { error, span: Span::new(start, end) };
+        match self.text.is_empty() {
+            true => tokens.push_back(token),
+            false => self.errors.push(token),
+        }
+    }
+
+    // Emit text read so far and errors reported while reading it
+    fn _flush(&mut self, tokens: &mut VecDeque<Token>) {
+        if !self.text.is_empty() {
+            let data = std::mem::take(&mut self.text);
+            tokens.push_back(Token::Text { data, span: self.text_span });
+        }
+        tokens.extend(self.errors.drain(..));
+    }
+
+    fn _emit(&mut self, token: Token, tokens: &mut VecDeque<Token>) {
+        self._flush(tokens);
+        tokens.push_back(token);
+    }
+
+    fn _new_tag(&mut self, closing: bool) {
+        self.name.clear();
+        self.closing = closing;
+        self.self_closing = false;
+        self.attributes.clear();
+    }
+
+    // Add characters to the tag name, keeping one byte more than the
+    // limit to tell the name is truncated
+    fn _tag_name(&mut self, data: &[u8], options: &ParserOptions) {
+        if self.name.len() <= options.max_name_length {
+            self.name.extend_from_slice(data);
+        }
+    }
+
+    // Start a new attribute, when there are more attributes than the
+    // limit the last one is dropped
+    fn _new_attribute(&mut self, start: Position, options: &ParserOptions, tokens: &mut VecDeque<Token>) {
+        if self.attributes.len() > options.max_attributes {
+            let attr = self.attributes.pop().unwrap();
+            self._error(ParseError::TooManyAttributes, attr.span.start, attr.span.end, tokens);
+        }
+        self.attributes.push(RawAttribute::new(Span::new(start, start)));
+    }
+
+    fn _attribute_name(&mut self, data: &[u8], end: Position, options: &ParserOptions) {
+        let attr = self.attributes.last_mut().unwrap();
+        if attr.name.len() <= options.max_name_length {
+            attr.name.extend_from_slice(data);
+        }
+        attr.span.end = end;
+    }
+
+    fn _attribute_value(&mut self, data: &[u8], end: Position, options: &ParserOptions) {
+        let attr = self.attributes.last_mut().unwrap();
+        if attr.value.len() <= options.max_attribute_value_length {
+            attr.value.extend_from_slice(data);
+        }
+        attr.span.end = end;
+    }
+
+    // Truncate the tag name, names and values of the attributes to the
+    // limits
+    fn _limit_tag(&mut self, end: Position, options: &ParserOptions, tokens: &mut VecDeque<Token>) {
+        let length = truncated_length(&self.name, options.max_name_length);
+        if length < self.name.len() {
+            self.name.truncate(length);
+            let start = self.start;
+            self._error(ParseError::NameTooLong, start, end, tokens);
+        }
+
+        if self.attributes.len() > options.max_attributes {
+            let attr = self.attributes.pop().unwrap();
+            self._error(ParseError::TooManyAttributes, attr.span.start, attr.span.end, tokens);
+        }
+
+        let mut errors = Vec::new();
+        for attr in self.attributes.iter_mut() {
+            let length = truncated_length(&attr.name, options.max_name_length);
+            if length < attr.name.len() {
+                attr.name.truncate(length);
+                errors.push((ParseError::NameTooLong, attr.span));
+            }
+            let length = truncated_length(&attr.value, options.max_attribute_value_length);
+            if length < attr.value.len() {
+                attr.value.truncate(length);
+                errors.push((ParseError::AttributeValueTooLong, attr.span));
+            }
+        }
+        for (error, span) in errors {
+            self._error(error, span.start, span.end, tokens);
+        }
+    }
+
+    // Remove attributes with the same name as a previous attribute
+    fn _remove_duplicate_attributes(&mut self, tokens: &mut VecDeque<Token>) {
+        let mut spans = Vec::new();
+        remove_duplicate_attributes(&mut self.attributes, |x| &x.name, |x| spans.push(x.span));
+        for span in spans {
+            self._error(ParseError::DuplicateAttribute, span.start, span.end, tokens);
+        }
+    }
+
+    // Emit start or end tag ending at end
+    fn _emit_tag(&mut self, end: Position, options: &ParserOptions, tokens: &mut VecDeque<Token>) {
+        self._limit_tag(end, options, tokens);
+        let tag = tag_from_name(&String::from_utf8_lossy(&self.name), options.preserve_case, &mut self.atoms);
+        let tag = options.tags.resolve(&tag).unwrap_or(tag);
+        let span = Span::new(self.start, end);
+        if !self.closing {
+            self._remove_duplicate_attributes(tokens);
+        }
+
+        match self.closing {
+            false => {
+                let namespace = match self.switch_content {
+                    true => self.foreign.start_tag(&tag, self.attributes.iter().map(|x| (&x.name[..], &x.value[..])), self.self_closing),
+                    false => Namespace::Html,
+                };
+                let tag = tag_in_namespace(&tag, namespace, options.preserve_case, &mut self.atoms);
+                let atoms = &mut self.atoms;
+                let mut attributes: Vec<Attribute> = self.attributes.drain(..).map(|x| x.intern(atoms)).collect();
+                adjust_attributes(&mut attributes, namespace, options.preserve_case, atoms);
+                self.last_start_tag = self.name.clone();
+                if self.switch_content && namespace == Namespace::Html {
+                    self.state = Machine::_content_state(&tag);
+                }
+                let spelling = tag_spelling(&tag, &String::from_utf8_lossy(&self.name), options.preserve_case, atoms);
+                let self_closing = self.self_closing;
+                self._emit(Token::StartTag { tag, spelling, attributes, self_closing, span }, tokens);
+            },
+            true => {
+                if !self.attributes.is_empty() {
+                    self._error(ParseError::EndTagWithAttributes, self.start, end, tokens);
+                }
+                if self.self_closing {
+                    self._error(ParseError::EndTagWithTrailingSolidus, self.start, end, tokens);
+                }
+                let namespace = match self.switch_content {
+                    true => self.foreign.end_tag(&tag),
+                    false => Namespace::Html,
+                };
+                let tag = tag_in_namespace(&tag, namespace, options.preserve_case, &mut self.atoms);
+                self._emit(Token::EndTag { tag, span }, tokens);
+            }
+        }
+    }
+
+    fn _emit_comment(&mut self, end: Position, options: &ParserOptions, tokens: &mut VecDeque<Token>) {
+        let data = std::mem::take(&mut self.data);
+        match options.skip_comments {
+            true => self._flush(tokens),
+            false => {
+                let span = Span::new(self.start, end);
+                self._emit(Token::Comment { data, span }, tokens);
+            }
+        }
+    }
+
+    fn _emit_cdata(&mut self, end: Position, tokens: &mut VecDeque<Token>) {
+        let data = std::mem::take(&mut self.data);
+        let span = Span::new(self.start, end);
+        self._emit(Token::CData { data, span }, tokens);
+    }
+
+    fn _new_doctype(&mut self) {
+        self.doctype_name = None;
+        self.public_id = None;
+        self.system_id = None;
+        self.force_quirks = false;
+    }
+
+    fn _emit_doctype(&mut self, end: Position, tokens: &mut VecDeque<Token>) {
+        let string = |x: Option<Vec<u8>>| x.map(|x| String::from_utf8_lossy(&x).into_owned());
+        let doctype = Doctype::from_parts(string(self.doctype_name.take()), string(self.public_id.take()),
+                                          string(self.system_id.take()), self.force_quirks);
+        let span = Span::new(self.start, end);
+        self._emit(Token::Doctype { doctype, span }, tokens);
+    }
+
+    fn _doctype_name(&mut self, data: &[u8]) {
+        self.doctype_name.get_or_insert_with(Vec::new).extend_from_slice(data);
+    }
+
+    // Test if the end tag being read closes the element of the last
+    // start tag
+    fn _is_appropriate_end_tag(&self) -> bool {
+        !self.last_start_tag.is_empty() && self.name.eq_ignore_ascii_case(&self.last_start_tag)
+    }
+
+    // Character of a tag or attribute name, lowercased unless the case
+    // is preserved
+    fn _name_char(c: u8, options: &ParserOptions) -> u8 {
+        match options.preserve_case {
+            true => c,
+            false => c.to_ascii_lowercase(),
+        }
+    }
+
+    fn _in_attribute(&self) -> bool {
+        matches!(self.return_state, State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted | State::AttributeValueUnquoted)
+    }
+
+    // Add characters of a character reference to the attribute value or
+    // text being read
+    fn _reference(&mut self, data: &[u8], end: Position, options: &ParserOptions) {
+        match self._in_attribute() {
+            true => self._attribute_value(data, end, options),
+            false => {
+                let start = self.reference_start;
+                self._text(data, start, end);
+            }
+        }
+    }
+
+    // Flush characters consumed as a character reference
+    fn _flush_reference(&mut self, end: Position, options: &ParserOptions) {
+        let buffer = std::mem::take(&mut self.buffer);
+        self._reference(&buffer, end, options);
+    }
+
+    // Handle character in the end tag name state of RCDATA, RAWTEXT or
+    // script data, state is the state of the content
+    fn _end_tag_name(&mut self, c: Option<u8>, state: State, here: Position, next: Position, options: &ParserOptions, tokens: &mut VecDeque<Token>) -> Step {
+        match c {
+            Some(x) if Machine::_is_whitespace(x) && self._is_appropriate_end_tag() => {
+                self.state = State::BeforeAttributeName;
+                Step::Consume
+            },
+            Some(b'/') if self._is_appropriate_end_tag() => {
+                self.state = State::SelfClosingStartTag;
+                Step::Consume
+            },
+            Some(b'>') if self._is_appropriate_end_tag() => {
+                self.state = State::Data;
+                self._emit_tag(next, options, tokens);
+                Step::Consume
+            },
+            Some(x) if x.is_ascii_alphabetic() => {
+                self._tag_name(&[Machine::_name_char(x, options)], options);
+                self.buffer.push(x);
+                Step::Consume
+            },
+            // Names of registered elements may go on with other name
+            // characters, eg. x-code
+            Some(x) if self.last_start_tag.get(self.name.len()) == Some(&x) && self.last_start_tag.starts_with(&self.name) => {
+                self._tag_name(&[x], options);
+                self.buffer.push(x);
+                Step::Consume
+            },
+            _ => {
+                let start = self.start;
+                let buffer = std::mem::take(&mut self.buffer);
+                self._text(b"</", start, here);
+                self._text(&buffer, start, here);
+                self.state = state;
+                Step::Reconsume
+            }
+        }
+    }
+
+    // Handle character in the script data double escape start and end
+    // states, which switch to state when "script" is read and to
+    // otherwise when not
+    fn _double_escape(&mut self, c: Option<u8>, state: State, otherwise: State, here: Position, next: Position) -> Step {
+        match c {
+            Some(x) if Machine::_is_whitespace(x) || x == b'/' || x == b'>' => {
+                self.state = match self.buffer == b"script" {
+                    true => state,
+                    false => otherwise,
+                };
+                self._text(&[x], here, next);
+                Step::Consume
+            },
+            Some(x) if x.is_ascii_alphabetic() => {
+                self.buffer.push(x.to_ascii_lowercase());
+                self._text(&[x], here, next);
+                Step::Consume
+            },
+            _ => {
+                self.state = otherwise;
+                Step::Reconsume
+            }
+        }
+    }
+
+    // Handle end of file in a tag
+    fn _eof_in_tag(&mut self, here: Position, tokens: &mut VecDeque<Token>) -> Step {
+        self._error(ParseError::EofInTag, here, here, tokens);
+        Step::Done
+    }
+
+    // Handle end of file in a comment
+    fn _eof_in_comment(&mut self, here: Position, options: &ParserOptions, tokens: &mut VecDeque<Token>) -> Step {
+        self._error(ParseError::EofInComment, here, here, tokens);
+        self._emit_comment(here, options, tokens);
+        Step::Done
+    }
+
+    // Handle end of file in a doctype
+    fn _eof_in_doctype(&mut self, here: Position, tokens: &mut VecDeque<Token>) -> Step {
+        self._error(ParseError::EofInDoctype, here, here, tokens);
+        self.force_quirks = true;
+        self._emit_doctype(here, tokens);
+        Step::Done
+    }
+
+    // Handle a malformed doctype, which is read as bogus doctype
+    fn _bogus_doctype(&mut self, error: ParseError, force_quirks: bool, here: Position, next: Position, tokens: &mut VecDeque<Token>) -> Step {
+        self._error(error, here, next, tokens);
+        if force_quirks {
+            self.force_quirks = true;
+        }
+        self.state = State::BogusDoctype;
+        Step::Reconsume
+    }
+
+    // Process the current input character c, None at end of file. rest
+    // is the data starting at c and here is the position of c.
+    fn _step(&mut self, c: Option<u8>, rest: &[u8], here: Position, options: &ParserOptions, end_of_file: bool, tokens: &mut VecDeque<Token>) -> Step {
+        let mut next = here;
+        next.advance(&rest[..std::cmp::min(rest.len(), 1)]);
+
+        match self.state {
+            State::Data => match c {
+                Some(b'&') if options.decode_entities => {
+                    self.return_state = State::Data;
+                    self.reference_start = here;
+                    self.state = State::CharacterReference;
+                    Step::Consume
+                },
+                Some(b'<') => {
+                    self.start = here;
+                    self.state = State::TagOpen;
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._text(&[0], here, next);
+                    Step::Consume
+                },
+                Some(x) => {
+                    self._text(&[x], here, next);
+                    Step::Consume
+                },
+                None => Step::Done,
+            },
+
+            State::RcData => match c {
+                Some(b'&') if options.decode_entities => {
+                    self.return_state = State::RcData;
+                    self.reference_start = here;
+                    self.state = State::CharacterReference;
+                    Step::Consume
+                },
+                Some(b'<') => {
+                    self.start = here;
+                    self.state = State::RcDataLessThanSign;
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._text(REPLACEMENT, here, next);
+                    Step::Consume
+                },
+                Some(x) => {
+                    self._text(&[x], here, next);
+                    Step::Consume
+                },
+                None => Step::Done,
+            },
+
+            State::RawText | State::ScriptData | State::PlainText => match c {
+                Some(b'<') if self.state != State::PlainText => {
+                    self.start = here;
+                    self.state = match self.state {
+                        State::RawText => State::RawTextLessThanSign,
+                        _ => State::ScriptDataLessThanSign,
+                    };
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._text(REPLACEMENT, here, next);
+                    Step::Consume
+                },
+                Some(x) => {
+                    self._text(&[x], here, next);
+                    Step::Consume
+                },
+                None => Step::Done,
+            },
+
+            State::TagOpen => match c {
+                Some(b'!') => {
+                    self.state = State::MarkupDeclarationOpen;
+                    Step::Consume
+                },
+                Some(b'/') => {
+                    self.state = State::EndTagOpen;
+                    Step::Consume
+                },
+                Some(x) if x.is_ascii_alphabetic() => {
+                    self._new_tag(false);
+                    self.state = State::TagName;
+                    Step::Reconsume
+                },
+                Some(b'?') => {
+                    self._error(ParseError::UnexpectedQuestionMarkInsteadOfTagName, here, next, tokens);
+                    self.data.clear();
+                    self.state = State::BogusComment;
+                    Step::Reconsume
+                },
+                Some(_) => {
+                    self._error(ParseError::InvalidFirstCharacterOfTagName, here, next, tokens);
+                    let start = self.start;
+                    self._text(b"<", start, here);
+                    self.state = State::Data;
+                    Step::Reconsume
+                },
+                None => {
+                    self._error(ParseError::EofBeforeTagName, here, here, tokens);
+                    let start = self.start;
+                    self._text(b"<", start, here);
+                    Step::Done
+                },
+            },
+
+            State::EndTagOpen => match c {
+                Some(x) if x.is_ascii_alphabetic() => {
+                    self._new_tag(true);
+                    self.state = State::TagName;
+                    Step::Reconsume
+                },
+                Some(b'>') => {
+                    let start = self.start;
+                    self._error(ParseError::MissingEndTagName, start, next, tokens);
+                    self.state = State::Data;
+                    Step::Consume
+                },
+                Some(_) => {
+                    self._error(ParseError::InvalidFirstCharacterOfTagName, here, next, tokens);
+                    self.data.clear();
+                    self.state = State::BogusComment;
+                    Step::Reconsume
+                },
+                None => {
+                    self._error(ParseError::EofBeforeTagName, here, here, tokens);
+                    let start = self.start;
+                    self._text(b"</", start, here);
+                    Step::Done
+                },
+            },
+
+            State::TagName => match c {
+                Some(x) if Machine::_is_whitespace(x) => {
+                    self.state = State::BeforeAttributeName;
+                    Step::Consume
+                },
+                Some(b'/') => {
+                    self.state = State::SelfClosingStartTag;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_tag(next, options, tokens);
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._tag_name(REPLACEMENT, options);
+                    Step::Consume
+                },
+                Some(x) => {
+                    self._tag_name(&[Machine::_name_char(x, options)], options);
+                    Step::Consume
+                },
+                None => self._eof_in_tag(here, tokens),
+            },
+
+            State::RcDataLessThanSign | State::RawTextLessThanSign => {
+                let (content, end_tag_open) = match self.state {
+                    State::RcDataLessThanSign => (State::RcData, State::RcDataEndTagOpen),
+                    _ => (State::RawText, State::RawTextEndTagOpen),
+                };
+                match c {
+                    Some(b'/') => {
+                        self.buffer.clear();
+                        self.state = end_tag_open;
+                        Step::Consume
+                    },
+                    _ => {
+                        let start = self.start;
+                        self._text(b"<", start, here);
+                        self.state = content;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::RcDataEndTagOpen | State::RawTextEndTagOpen | State::ScriptDataEndTagOpen | State::ScriptDataEscapedEndTagOpen => {
+                let (content, end_tag_name) = match self.state {
+                    State::RcDataEndTagOpen => (State::RcData, State::RcDataEndTagName),
+                    State::RawTextEndTagOpen => (State::RawText, State::RawTextEndTagName),
+                    State::ScriptDataEndTagOpen => (State::ScriptData, State::ScriptDataEndTagName),
+                    _ => (State::ScriptDataEscaped, State::ScriptDataEscapedEndTagName),
+                };
+                match c {
+                    Some(x) if x.is_ascii_alphabetic() => {
+                        self._new_tag(true);
+                        self.state = end_tag_name;
+                        Step::Reconsume
+                    },
+                    _ => {
+                        let start = self.start;
+                        self._text(b"</", start, here);
+                        self.state = content;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::RcDataEndTagName => self._end_tag_name(c, State::RcData, here, next, options, tokens),
+            State::RawTextEndTagName => self._end_tag_name(c, State::RawText, here, next, options, tokens),
+            State::ScriptDataEndTagName => self._end_tag_name(c, State::ScriptData, here, next, options, tokens),
+            State::ScriptDataEscapedEndTagName => self._end_tag_name(c, State::ScriptDataEscaped, here, next, options, tokens),
+
+            State::ScriptDataLessThanSign => match c {
+                Some(b'/') => {
+                    self.buffer.clear();
+                    self.state = State::ScriptDataEndTagOpen;
+                    Step::Consume
+                },
+                Some(b'!') => {
+                    let start = self.start;
+                    self._text(b"<!", start, next);
+                    self.state = State::ScriptDataEscapeStart;
+                    Step::Consume
+                },
+                _ => {
+                    let start = self.start;
+                    self._text(b"<", start, here);
+                    self.state = State::ScriptData;
+                    Step::Reconsume
+                }
+            },
+
+            State::ScriptDataEscapeStart | State::ScriptDataEscapeStartDash => match c {
+                Some(b'-') => {
+                    self._text(b"-", here, next);
+                    self.state = match self.state {
+                        State::ScriptDataEscapeStart => State::ScriptDataEscapeStartDash,
+                        _ => State::ScriptDataEscapedDashDash,
+                    };
+                    Step::Consume
+                },
+                _ => {
+                    self.state = State::ScriptData;
+                    Step::Reconsume
+                }
+            },
+
+            State::ScriptDataEscaped | State::ScriptDataEscapedDash | State::ScriptDataEscapedDashDash => match c {
+                Some(b'-') => {
+                    self._text(b"-", here, next);
+                    self.state = match self.state {
+                        State::ScriptDataEscaped => State::ScriptDataEscapedDash,
+                        _ => State::ScriptDataEscapedDashDash,
+                    };
+                    Step::Consume
+                },
+                Some(b'<') => {
+                    self.start = here;
+                    self.state = State::ScriptDataEscapedLessThanSign;
+                    Step::Consume
+                },
+                Some(b'>') if self.state == State::ScriptDataEscapedDashDash => {
+                    self._text(b">", here, next);
+                    self.state = State::ScriptData;
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._text(REPLACEMENT, here, next);
+                    self.state = State::ScriptDataEscaped;
+                    Step::Consume
+                },
+                Some(x) => {
+                    self._text(&[x], here, next);
+                    self.state = State::ScriptDataEscaped;
+                    Step::Consume
+                },
+                None => {
+                    self._error(ParseError::EofInScriptHtmlCommentLikeText, here, here, tokens);
+                    Step::Done
+                },
+            },
+
+            State::ScriptDataEscapedLessThanSign => match c {
+                Some(b'/') => {
+                    self.buffer.clear();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                    Step::Consume
+                },
+                Some(x) if x.is_ascii_alphabetic() => {
+                    self.buffer.clear();
+                    let start = self.start;
+                    self._text(b"<", start, here);
+                    self.state = State::ScriptDataDoubleEscapeStart;
+                    Step::Reconsume
+                },
+                _ => {
+                    let start = self.start;
+                    self._text(b"<", start, here);
+                    self.state = State::ScriptDataEscaped;
+                    Step::Reconsume
+                }
+            },
+
+            State::ScriptDataDoubleEscapeStart => self._double_escape(c, State::ScriptDataDoubleEscaped, State::ScriptDataEscaped, here, next),
+
+            State::ScriptDataDoubleEscaped | State::ScriptDataDoubleEscapedDash | State::ScriptDataDoubleEscapedDashDash => match c {
+                Some(b'-') => {
+                    self._text(b"-", here, next);
+                    self.state = match self.state {
+                        State::ScriptDataDoubleEscaped => State::ScriptDataDoubleEscapedDash,
+                        _ => State::ScriptDataDoubleEscapedDashDash,
+                    };
+                    Step::Consume
+                },
+                Some(b'<') => {
+                    self._text(b"<", here, next);
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    Step::Consume
+                },
+                Some(b'>') if self.state == State::ScriptDataDoubleEscapedDashDash => {
+                    self._text(b">", here, next);
+                    self.state = State::ScriptData;
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._text(REPLACEMENT, here, next);
+                    self.state = State::ScriptDataDoubleEscaped;
+                    Step::Consume
+                },
+                Some(x) => {
+                    self._text(&[x], here, next);
+                    self.state = State::ScriptDataDoubleEscaped;
+                    Step::Consume
+                },
+                None => {
+                    self._error(ParseError::EofInScriptHtmlCommentLikeText, here, here, tokens);
+                    Step::Done
+                },
+            },
+
+            State::ScriptDataDoubleEscapedLessThanSign => match c {
+                Some(b'/') => {
+                    self.buffer.clear();
+                    self._text(b"/", here, next);
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                    Step::Consume
+                },
+                _ => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    Step::Reconsume
+                }
+            },
+
+            State::ScriptDataDoubleEscapeEnd => self._double_escape(c, State::ScriptDataEscaped, State::ScriptDataDoubleEscaped, here, next),
+
+            State::BeforeAttributeName => match c {
+                Some(x) if Machine::_is_whitespace(x) => Step::Consume,
+                Some(b'/') | Some(b'>') | None => {
+                    self.state = State::AfterAttributeName;
+                    Step::Reconsume
+                },
+                Some(b'=') => {
+                    self._error(ParseError::UnexpectedEqualsSignBeforeAttributeName, here, next, tokens);
+                    self._new_attribute(here, options, tokens);
+                    self._attribute_name(b"=", next, options);
+                    self.state = State::AttributeName;
+                    Step::Consume
+                },
+                Some(_) => {
+                    self._new_attribute(here, options, tokens);
+                    self.state = State::AttributeName;
+                    Step::Reconsume
+                },
+            },
+
+            State::AttributeName => match c {
+                Some(x) if Machine::_is_whitespace(x) || x == b'/' || x == b'>' => {
+                    self.state = State::AfterAttributeName;
+                    Step::Reconsume
+                },
+                None => {
+                    self.state = State::AfterAttributeName;
+                    Step::Reconsume
+                },
+                Some(b'=') => {
+                    self.state = State::BeforeAttributeValue;
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._attribute_name(REPLACEMENT, next, options);
+                    Step::Consume
+                },
+                Some(x) => {
+                    if x == b'"' || x == b'\'' || x == b'<' {
+                        self._error(ParseError::UnexpectedCharacterInAttributeName, here, next, tokens);
+                    }
+                    self._attribute_name(&[Machine::_name_char(x, options)], next, options);
+                    Step::Consume
+                },
+            },
+
+            State::AfterAttributeName => match c {
+                Some(x) if Machine::_is_whitespace(x) => Step::Consume,
+                Some(b'/') => {
+                    self.state = State::SelfClosingStartTag;
+                    Step::Consume
+                },
+                Some(b'=') => {
+                    self.state = State::BeforeAttributeValue;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_tag(next, options, tokens);
+                    Step::Consume
+                },
+                Some(_) => {
+                    self._new_attribute(here, options, tokens);
+                    self.state = State::AttributeName;
+                    Step::Reconsume
+                },
+                None => self._eof_in_tag(here, tokens),
+            },
+
+            State::BeforeAttributeValue => match c {
+                Some(x) if Machine::_is_whitespace(x) => Step::Consume,
+                Some(b'"') => {
+                    self._attribute_value(b"", next, options);
+                    self.state = State::AttributeValueDoubleQuoted;
+                    Step::Consume
+                },
+                Some(b'\'') => {
+                    self._attribute_value(b"", next, options);
+                    self.state = State::AttributeValueSingleQuoted;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self._error(ParseError::MissingAttributeValue, here, next, tokens);
+                    self.state = State::Data;
+                    self._emit_tag(next, options, tokens);
+                    Step::Consume
+                },
+                _ => {
+                    self.state = State::AttributeValueUnquoted;
+                    Step::Reconsume
+                },
+            },
+
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote = match self.state {
+                    State::AttributeValueDoubleQuoted => b'"',
+                    _ => b'\'',
+                };
+                match c {
+                    Some(x) if x == quote => {
+                        self._attribute_value(b"", next, options);
+                        self.state = State::AfterAttributeValueQuoted;
+                        Step::Consume
+                    },
+                    Some(b'&') if options.decode_entities => {
+                        self.return_state = self.state;
+                        self.state = State::CharacterReference;
+                        Step::Consume
+                    },
+                    Some(0) => {
+                        self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                        self._attribute_value(REPLACEMENT, next, options);
+                        Step::Consume
+                    },
+                    Some(x) => {
+                        self._attribute_value(&[x], next, options);
+                        Step::Consume
+                    },
+                    None => self._eof_in_tag(here, tokens),
+                }
+            },
+
+            State::AttributeValueUnquoted => match c {
+                Some(x) if Machine::_is_whitespace(x) => {
+                    self.state = State::BeforeAttributeName;
+                    Step::Consume
+                },
+                Some(b'&') if options.decode_entities => {
+                    self.return_state = State::AttributeValueUnquoted;
+                    self.state = State::CharacterReference;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_tag(next, options, tokens);
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._attribute_value(REPLACEMENT, next, options);
+                    Step::Consume
+                },
+                Some(x) => {
+                    match x {
+                        b'"' | b'\'' | b'<' | b'=' | b'`' => self._error(ParseError::UnexpectedCharacterInUnquotedAttributeValue, here, next, tokens),
+                        _ => (),
+                    }
+                    self._attribute_value(&[x], next, options);
+                    Step::Consume
+                },
+                None => self._eof_in_tag(here, tokens),
+            },
+
+            State::AfterAttributeValueQuoted => match c {
+                Some(x) if Machine::_is_whitespace(x) => {
+                    self.state = State::BeforeAttributeName;
+                    Step::Consume
+                },
+                Some(b'/') => {
+                    self.state = State::SelfClosingStartTag;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_tag(next, options, tokens);
+                    Step::Consume
+                },
+                Some(_) => {
+                    self._error(ParseError::MissingWhitespaceBetweenAttributes, here, next, tokens);
+                    self.state = State::BeforeAttributeName;
+                    Step::Reconsume
+                },
+                None => self._eof_in_tag(here, tokens),
+            },
+
+            State::SelfClosingStartTag => match c {
+                Some(b'>') => {
+                    self.self_closing = true;
+                    self.state = State::Data;
+                    self._emit_tag(next, options, tokens);
+                    Step::Consume
+                },
+                Some(_) => {
+                    self._error(ParseError::UnexpectedSolidusInTag, here, next, tokens);
+                    self.state = State::BeforeAttributeName;
+                    Step::Reconsume
+                },
+                None => self._eof_in_tag(here, tokens),
+            },
+
+            State::BogusComment => match c {
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_comment(next, options, tokens);
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self.data.extend_from_slice(REPLACEMENT);
+                    Step::Consume
+                },
+                Some(x) => {
+                    self.data.push(x);
+                    Step::Consume
+                },
+                None => {
+                    self._emit_comment(here, options, tokens);
+                    Step::Done
+                },
+            },
+
+            State::MarkupDeclarationOpen => {
+                let comment = Machine::_starts_with(rest, b"--", false, end_of_file);
+                let doctype = Machine::_starts_with(rest, b"DOCTYPE", true, end_of_file);
+                let cdata = Machine::_starts_with(rest, b"[CDATA[", false, end_of_file);
+                match (comment, doctype, cdata) {
+                    (Some(true), _, _) => {
+                        self.data.clear();
+                        self.state = State::CommentStart;
+                        Step::Skip(2)
+                    },
+                    (_, Some(true), _) => {
+                        self.state = State::Doctype;
+                        Step::Skip(7)
+                    },
+                    (_, _, Some(true)) if self.foreign.is_foreign() => {
+                        self.data.clear();
+                        self.state = State::CdataSection;
+                        Step::Skip(7)
+                    },
+                    (_, _, Some(true)) => {
+                        let mut end = here;
+                        end.advance(&rest[..7]);
+                        self._error(ParseError::CdataInHtmlContent, here, end, tokens);
+                        self.data.clear();
+                        self.data.extend_from_slice(b"[CDATA[");
+                        self.state = State::BogusComment;
+                        Step::Skip(7)
+                    },
+                    (None, _, _) | (_, None, _) | (_, _, None) => Step::Wait,
+                    _ => {
+                        self._error(ParseError::IncorrectlyOpenedComment, here, next, tokens);
+                        self.data.clear();
+                        self.state = State::BogusComment;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::CdataSection => match c {
+                Some(b']') => {
+                    self.state = State::CdataSectionBracket;
+                    Step::Consume
+                },
+                Some(x) => {
+                    self.data.push(x);
+                    Step::Consume
+                },
+                None => {
+                    self._error(ParseError::EofInCdata, here, here, tokens);
+                    self._emit_cdata(here, tokens);
+                    Step::Done
+                },
+            },
+
+            State::CdataSectionBracket => match c {
+                Some(b']') => {
+                    self.state = State::CdataSectionEnd;
+                    Step::Consume
+                },
+                _ => {
+                    self.data.push(b']');
+                    self.state = State::CdataSection;
+                    Step::Reconsume
+                },
+            },
+
+            State::CdataSectionEnd => match c {
+                Some(b']') => {
+                    self.data.push(b']');
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_cdata(next, tokens);
+                    Step::Consume
+                },
+                _ => {
+                    self.data.extend_from_slice(b"]]");
+                    self.state = State::CdataSection;
+                    Step::Reconsume
+                },
+            },
+
+            State::CommentStart => match c {
+                Some(b'-') => {
+                    self.state = State::CommentStartDash;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self._error(ParseError::AbruptClosingOfEmptyComment, here, next, tokens);
+                    self.state = State::Data;
+                    self._emit_comment(next, options, tokens);
+                    Step::Consume
+                },
+                _ => {
+                    self.state = State::Comment;
+                    Step::Reconsume
+                },
+            },
+
+            State::CommentStartDash => match c {
+                Some(b'-') => {
+                    self.state = State::CommentEnd;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self._error(ParseError::AbruptClosingOfEmptyComment, here, next, tokens);
+                    self.state = State::Data;
+                    self._emit_comment(next, options, tokens);
+                    Step::Consume
+                },
+                Some(_) => {
+                    self.data.push(b'-');
+                    self.state = State::Comment;
+                    Step::Reconsume
+                },
+                None => self._eof_in_comment(here, options, tokens),
+            },
+
+            State::Comment => match c {
+                Some(b'<') => {
+                    self.data.push(b'<');
+                    self.state = State::CommentLessThanSign;
+                    Step::Consume
+                },
+                Some(b'-') => {
+                    self.state = State::CommentEndDash;
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self.data.extend_from_slice(REPLACEMENT);
+                    Step::Consume
+                },
+                Some(x) => {
+                    self.data.push(x);
+                    Step::Consume
+                },
+                None => self._eof_in_comment(here, options, tokens),
+            },
+
+            State::CommentLessThanSign => match c {
+                Some(b'!') => {
+                    self.data.push(b'!');
+                    self.state = State::CommentLessThanSignBang;
+                    Step::Consume
+                },
+                Some(b'<') => {
+                    self.data.push(b'<');
+                    Step::Consume
+                },
+                _ => {
+                    self.state = State::Comment;
+                    Step::Reconsume
+                },
+            },
+
+            State::CommentLessThanSignBang => match c {
+                Some(b'-') => {
+                    self.state = State::CommentLessThanSignBangDash;
+                    Step::Consume
+                },
+                _ => {
+                    self.state = State::Comment;
+                    Step::Reconsume
+                },
+            },
+
+            State::CommentLessThanSignBangDash => match c {
+                Some(b'-') => {
+                    self.state = State::CommentLessThanSignBangDashDash;
+                    Step::Consume
+                },
+                _ => {
+                    self.state = State::CommentEndDash;
+                    Step::Reconsume
+                },
+            },
+
+            State::CommentLessThanSignBangDashDash => {
+                match c {
+                    Some(b'>') | None => (),
+                    Some(_) => self._error(ParseError::NestedComment, here, next, tokens),
+                }
+                self.state = State::CommentEnd;
+                Step::Reconsume
+            },
+
+            State::CommentEndDash => match c {
+                Some(b'-') => {
+                    self.state = State::CommentEnd;
+                    Step::Consume
+                },
+                Some(_) => {
+                    self.data.push(b'-');
+                    self.state = State::Comment;
+                    Step::Reconsume
+                },
+                None => self._eof_in_comment(here, options, tokens),
+            },
+
+            State::CommentEnd => match c {
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_comment(next, options, tokens);
+                    Step::Consume
+                },
+                Some(b'!') => {
+                    self.state = State::CommentEndBang;
+                    Step::Consume
+                },
+                Some(b'-') => {
+                    self.data.push(b'-');
+                    Step::Consume
+                },
+                Some(_) => {
+                    self.data.extend_from_slice(b"--");
+                    self.state = State::Comment;
+                    Step::Reconsume
+                },
+                None => self._eof_in_comment(here, options, tokens),
+            },
+
+            State::CommentEndBang => match c {
+                Some(b'-') => {
+                    self.data.extend_from_slice(b"--!");
+                    self.state = State::CommentEndDash;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self._error(ParseError::IncorrectlyClosedComment, here, next, tokens);
+                    self.state = State::Data;
+                    self._emit_comment(next, options, tokens);
+                    Step::Consume
+                },
+                Some(_) => {
+                    self.data.extend_from_slice(b"--!");
+                    self.state = State::Comment;
+                    Step::Reconsume
+                },
+                None => self._eof_in_comment(here, options, tokens),
+            },
+
+            State::Doctype => match c {
+                Some(x) if Machine::_is_whitespace(x) => {
+                    self.state = State::BeforeDoctypeName;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::BeforeDoctypeName;
+                    Step::Reconsume
+                },
+                Some(_) => {
+                    self._error(ParseError::MissingWhitespaceBeforeDoctypeName, here, next, tokens);
+                    self.state = State::BeforeDoctypeName;
+                    Step::Reconsume
+                },
+                None => {
+                    self._new_doctype();
+                    self._eof_in_doctype(here, tokens)
+                },
+            },
+
+            State::BeforeDoctypeName => match c {
+                Some(x) if Machine::_is_whitespace(x) => Step::Consume,
+                Some(b'>') => {
+                    self._error(ParseError::MissingDoctypeName, here, next, tokens);
+                    self._new_doctype();
+                    self.force_quirks = true;
+                    self.state = State::Data;
+                    self._emit_doctype(next, tokens);
+                    Step::Consume
+                },
+                Some(_) => {
+                    self._new_doctype();
+                    self._doctype_name(b"");
+                    self.state = State::DoctypeName;
+                    Step::Reconsume
+                },
+                None => {
+                    self._new_doctype();
+                    self._eof_in_doctype(here, tokens)
+                },
+            },
+
+            State::DoctypeName => match c {
+                Some(x) if Machine::_is_whitespace(x) => {
+                    self.state = State::AfterDoctypeName;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_doctype(next, tokens);
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    self._doctype_name(REPLACEMENT);
+                    Step::Consume
+                },
+                Some(x) => {
+                    self._doctype_name(&[x.to_ascii_lowercase()]);
+                    Step::Consume
+                },
+                None => self._eof_in_doctype(here, tokens),
+            },
+
+            State::AfterDoctypeName => match c {
+                Some(x) if Machine::_is_whitespace(x) => Step::Consume,
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_doctype(next, tokens);
+                    Step::Consume
+                },
+                Some(_) => {
+                    let public = Machine::_starts_with(rest, b"PUBLIC", true, end_of_file);
+                    let system = Machine::_starts_with(rest, b"SYSTEM", true, end_of_file);
+                    match (public, system) {
+                        (Some(true), _) => {
+                            self.state = State::AfterDoctypePublicKeyword;
+                            Step::Skip(6)
+                        },
+                        (_, Some(true)) => {
+                            self.state = State::AfterDoctypeSystemKeyword;
+                            Step::Skip(6)
+                        },
+                        (None, _) | (_, None) => Step::Wait,
+                        _ => self._bogus_doctype(ParseError::InvalidCharacterSequenceAfterDoctypeName, true, here, next, tokens),
+                    }
+                },
+                None => self._eof_in_doctype(here, tokens),
+            },
+
+            State::AfterDoctypePublicKeyword | State::AfterDoctypeSystemKeyword |
+            State::BeforeDoctypePublicIdentifier | State::BeforeDoctypeSystemIdentifier => {
+                let public = matches!(self.state, State::AfterDoctypePublicKeyword | State::BeforeDoctypePublicIdentifier);
+                let keyword = matches!(self.state, State::AfterDoctypePublicKeyword | State::AfterDoctypeSystemKeyword);
+                match c {
+                    Some(x) if Machine::_is_whitespace(x) => {
+                        self.state = match (keyword, public) {
+                            (true, true) => State::BeforeDoctypePublicIdentifier,
+                            (true, false) => State::BeforeDoctypeSystemIdentifier,
+                            (false, _) => self.state,
+                        };
+                        Step::Consume
+                    },
+                    Some(x) if x == b'"' || x == b'\'' => {
+                        if keyword {
+                            let error = match public {
+                                true => ParseError::MissingWhitespaceAfterDoctypePublicKeyword,
+                                false => ParseError::MissingWhitespaceAfterDoctypeSystemKeyword,
+                            };
+                            self._error(error, here, next, tokens);
+                        }
+                        self.state = match (public, x) {
+                            (true, b'"') => State::DoctypePublicIdentifierDoubleQuoted,
+                            (true, _) => State::DoctypePublicIdentifierSingleQuoted,
+                            (false, b'"') => State::DoctypeSystemIdentifierDoubleQuoted,
+                            (false, _) => State::DoctypeSystemIdentifierSingleQuoted,
+                        };
+                        match public {
+                            true => self.public_id = Some(Vec::new()),
+                            false => self.system_id = Some(Vec::new()),
+                        }
+                        Step::Consume
+                    },
+                    Some(b'>') => {
+                        let error = match public {
+                            true => ParseError::MissingDoctypePublicIdentifier,
+                            false => ParseError::MissingDoctypeSystemIdentifier,
+                        };
+                        self._error(error, here, next, tokens);
+                        self.force_quirks = true;
+                        self.state = State::Data;
+                        self._emit_doctype(next, tokens);
+                        Step::Consume
+                    },
+                    Some(_) => {
+                        let error = match public {
+                            true => ParseError::MissingQuoteBeforeDoctypePublicIdentifier,
+                            false => ParseError::MissingQuoteBeforeDoctypeSystemIdentifier,
+                        };
+                        self._bogus_doctype(error, true, here, next, tokens)
+                    },
+                    None => self._eof_in_doctype(here, tokens),
+                }
+            },
+
+            State::DoctypePublicIdentifierDoubleQuoted | State::DoctypePublicIdentifierSingleQuoted |
+            State::DoctypeSystemIdentifierDoubleQuoted | State::DoctypeSystemIdentifierSingleQuoted => {
+                let (public, quote) = match self.state {
+                    State::DoctypePublicIdentifierDoubleQuoted => (true, b'"'),
+                    State::DoctypePublicIdentifierSingleQuoted => (true, b'\''),
+                    State::DoctypeSystemIdentifierDoubleQuoted => (false, b'"'),
+                    _ => (false, b'\''),
+                };
+                match c {
+                    Some(x) if x == quote => {
+                        self.state = match public {
+                            true => State::AfterDoctypePublicIdentifier,
+                            false => State::AfterDoctypeSystemIdentifier,
+                        };
+                        Step::Consume
+                    },
+                    Some(b'>') => {
+                        let error = match public {
+                            true => ParseError::AbruptDoctypePublicIdentifier,
+                            false => ParseError::AbruptDoctypeSystemIdentifier,
+                        };
+                        self._error(error, here, next, tokens);
+                        self.force_quirks = true;
+                        self.state = State::Data;
+                        self._emit_doctype(next, tokens);
+                        Step::Consume
+                    },
+                    Some(x) => {
+                        let byte = [x];
+                        let data = match x {
+                            0 => {
+                                self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                                REPLACEMENT
+                            },
+                            _ => &byte[..],
+                        };
+                        let identifier = match public {
+                            true => &mut self.public_id,
+                            false => &mut self.system_id,
+                        };
+                        identifier.get_or_insert_with(Vec::new).extend_from_slice(data);
+                        Step::Consume
+                    },
+                    None => self._eof_in_doctype(here, tokens),
+                }
+            },
+
+            State::AfterDoctypePublicIdentifier | State::BetweenDoctypePublicAndSystemIdentifiers => match c {
+                Some(x) if Machine::_is_whitespace(x) => {
+                    self.state = State::BetweenDoctypePublicAndSystemIdentifiers;
+                    Step::Consume
+                },
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_doctype(next, tokens);
+                    Step::Consume
+                },
+                Some(x) if x == b'"' || x == b'\'' => {
+                    if self.state == State::AfterDoctypePublicIdentifier {
+                        self._error(ParseError::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers, here, next, tokens);
+                    }
+                    self.system_id = Some(Vec::new());
+                    self.state = match x {
+                        b'"' => State::DoctypeSystemIdentifierDoubleQuoted,
+                        _ => State::DoctypeSystemIdentifierSingleQuoted,
+                    };
+                    Step::Consume
+                },
+                Some(_) => self._bogus_doctype(ParseError::MissingQuoteBeforeDoctypeSystemIdentifier, true, here, next, tokens),
+                None => self._eof_in_doctype(here, tokens),
+            },
+
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some(x) if Machine::_is_whitespace(x) => Step::Consume,
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_doctype(next, tokens);
+                    Step::Consume
+                },
+                Some(_) => self._bogus_doctype(ParseError::UnexpectedCharacterAfterDoctypeSystemIdentifier, false, here, next, tokens),
+                None => self._eof_in_doctype(here, tokens),
+            },
+
+            State::BogusDoctype => match c {
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self._emit_doctype(next, tokens);
+                    Step::Consume
+                },
+                Some(0) => {
+                    self._error(ParseError::UnexpectedNullCharacter, here, next, tokens);
+                    Step::Consume
+                },
+                Some(_) => Step::Consume,
+                None => {
+                    self._emit_doctype(here, tokens);
+                    Step::Done
+                },
+            },
+
+            State::CharacterReference => {
+                self.buffer.clear();
+                self.buffer.push(b'&');
+                match c {
+                    Some(x) if x.is_ascii_alphanumeric() => {
+                        self.state = State::NamedCharacterReference;
+                        Step::Reconsume
+                    },
+                    Some(b'#') => {
+                        self.buffer.push(b'#');
+                        self.state = State::NumericCharacterReference;
+                        Step::Consume
+                    },
+                    _ => {
+                        self._flush_reference(here, options);
+                        self.state = self.return_state;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::NamedCharacterReference => {
+                // Wait until the longest possible name is available
+                let mut length = 0;
+                while length < rest.len() && length < charref::MAX_NAME_LENGTH && rest[length].is_ascii_alphanumeric() {
+                    length += 1;
+                }
+                if length == rest.len() && length < charref::MAX_NAME_LENGTH && !end_of_file {
+                    return Step::Wait;
+                }
+
+                match charref::match_named(rest) {
+                    Some((value, length)) => {
+                        let mut end = here;
+                        end.advance(&rest[..length]);
+                        let semicolon = rest[length - 1] == b';';
+                        if !semicolon && self._in_attribute() && charref::is_legacy_attribute_text(rest.get(length).cloned()) {
+                            // Kept as is for historical reasons
+                            self.buffer.extend_from_slice(&rest[..length]);
+                            self._flush_reference(end, options);
+                        } else {
+                            if !semicolon {
+                                self._error(ParseError::MissingSemicolonAfterCharacterReference, here, end, tokens);
+                            }
+                            self._reference(value.as_bytes(), end, options);
+                        }
+                        self.state = self.return_state;
+                        Step::Skip(length)
+                    },
+                    None => {
+                        self._flush_reference(here, options);
+                        self.state = State::AmbiguousAmpersand;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::AmbiguousAmpersand => match c {
+                Some(x) if x.is_ascii_alphanumeric() => {
+                    match self._in_attribute() {
+                        true => self._attribute_value(&[x], next, options),
+                        false => self._text(&[x], here, next),
+                    }
+                    Step::Consume
+                },
+                Some(b';') => {
+                    self._error(ParseError::UnknownNamedCharacterReference, here, next, tokens);
+                    self.state = self.return_state;
+                    Step::Reconsume
+                },
+                _ => {
+                    self.state = self.return_state;
+                    Step::Reconsume
+                },
+            },
+
+            State::NumericCharacterReference => {
+                self.reference = 0;
+                match c {
+                    Some(x) if x == b'x' || x == b'X' => {
+                        self.buffer.push(x);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                        Step::Consume
+                    },
+                    _ => {
+                        self.state = State::DecimalCharacterReferenceStart;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::HexadecimalCharacterReferenceStart | State::DecimalCharacterReferenceStart => {
+                let (radix, state) = match self.state {
+                    State::HexadecimalCharacterReferenceStart => (16, State::HexadecimalCharacterReference),
+                    _ => (10, State::DecimalCharacterReference),
+                };
+                match c.and_then(|x| (x as char).to_digit(radix)) {
+                    Some(_) => {
+                        self.state = state;
+                        Step::Reconsume
+                    },
+                    None => {
+                        let start = self.reference_start;
+                        self._error(ParseError::AbsenceOfDigitsInNumericCharacterReference, start, here, tokens);
+                        self._flush_reference(here, options);
+                        self.state = self.return_state;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                let radix = match self.state {
+                    State::HexadecimalCharacterReference => 16,
+                    _ => 10,
+                };
+                match c {
+                    Some(b';') => {
+                        self.state = State::NumericCharacterReferenceEnd;
+                        Step::Consume
+                    },
+                    Some(x) if (x as char).is_digit(radix) => {
+                        // Saturate above the unicode range, the result is U+FFFD anyway
+                        let digit = (x as char).to_digit(radix).unwrap();
+                        self.reference = self.reference.saturating_mul(radix).saturating_add(digit).min(0x110000);
+                        Step::Consume
+                    },
+                    _ => {
+                        let start = self.reference_start;
+                        self._error(ParseError::MissingSemicolonAfterCharacterReference, start, here, tokens);
+                        self.state = State::NumericCharacterReferenceEnd;
+                        Step::Reconsume
+                    }
+                }
+            },
+
+            State::NumericCharacterReferenceEnd => {
+                let value = self.reference;
+                let error = match value {
+                    0 => Some(ParseError::NullCharacterReference),
+                    0x110000..=0xFFFFFFFF => Some(ParseError::CharacterReferenceOutsideUnicodeRange),
+                    0xD800..=0xDFFF => Some(ParseError::SurrogateCharacterReference),
+                    0xFDD0..=0xFDEF => Some(ParseError::NoncharacterCharacterReference),
+                    x if (x & 0xFFFE) == 0xFFFE => Some(ParseError::NoncharacterCharacterReference),
+                    0x0D | 0x80..=0x9F => Some(ParseError::ControlCharacterReference),
+                    x if x < 0x20 && !Machine::_is_whitespace(x as u8) => Some(ParseError::ControlCharacterReference),
+                    0x7F => Some(ParseError::ControlCharacterReference),
+                    _ => None,
+                };
+                let start = self.reference_start;
+                if let Some(x) = error {
+                    self._error(x, start, here, tokens);
+                }
+
+                let mut utf8 = [0; 4];
+                let data = charref::numeric(value).encode_utf8(&mut utf8).as_bytes().to_vec();
+                self._reference(&data, here, options);
+                self.state = self.return_state;
+                Step::Reconsume
+            },
+        }
+    }
+
+    /// Run the state machine over buf starting at position, returns
+    /// the number of bytes processed and the position after them. At
+    /// end of file all of buf is processed.
+    pub fn run(&mut self, buf: &[u8], position: Position, options: &ParserOptions, end_of_file: bool, tokens: &mut VecDeque<Token>) -> (usize, Position) {
+        let mut index = 0;
+        let mut here = position;
+
+        while index < buf.len() {
+            // Newlines are normalized, CR LF and a lone CR are read as LF
+            let c = match buf[index] {
+                b'\r' => match buf.get(index + 1) {
+                    Some(&b'\n') => {
+                        here.advance(&buf[index..index + 1]);
+                        index += 1;
+                        continue;
+                    },
+                    None if !end_of_file => break,
+                    _ => b'\n',
+                },
+                x => x,
+            };
+
+            match self._step(Some(c), &buf[index..], here, options, end_of_file, tokens) {
+                Step::Consume => {
+                    here.advance(&buf[index..index + 1]);
+                    index += 1;
+                },
+                Step::Skip(length) => {
+                    here.advance(&buf[index..index + length]);
+                    index += length;
+                },
+                Step::Reconsume | Step::Done => (),
+                Step::Wait => break,
+            }
+        }
+
+        (index, here)
+    }
+
+    /// Process end of file at position, which emits the text and
+    /// token being read
+    pub fn finish(&mut self, position: Position, options: &ParserOptions, tokens: &mut VecDeque<Token>) {
+        loop {
+            if let Step::Done = self._step(None, &[], position, options, true, tokens) {
+                break;
+            }
+        }
+        self._flush(tokens);
+        self.state = State::Data;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+    use std::collections::VecDeque;
+    use std::fs::File;
+    use std::io::Read;
+    use serde_json::{self, Value, Map};
+    use parser::{ParserOptions, TokenizerMode};
+    use position::{Position};
+    use tag::{Tag};
+    use error::{ParseError};
+    use tokenizer::{Tokenizer, Token};
+    use whatwg::{Machine, State};
+
+    fn options() -> ParserOptions {
+        ParserOptions { tokenizer: TokenizerMode::Whatwg, ..Default::default() }
+    }
+
+    fn string(data: &[u8]) -> Value {
+        Value::String(String::from_utf8(data.to_vec()).unwrap())
+    }
+
+    // Add token in the html5lib-tests format to output, characters are
+    // coalesced and errors are left out
+    fn push_token(output: &mut Vec<Value>, token: Value) {
+        let text = match (output.last(), &token) {
+            (Some(Value::Array(x)), Value::Array(y)) if x[0] == "Character" && y[0] == "Character" => {
+                Some(format!("{}{}", x[1].as_str().unwrap(), y[1].as_str().unwrap()))
+            },
+            _ => None,
+        };
+        match text {
+            Some(x) => *output.last_mut().unwrap() = Value::Array(vec![Value::from("Character"), Value::from(x)]),
+            None if token != "ParseError" => output.push(token),
+            None => (),
+        }
+    }
+
+    // Tokenize input passed in chunks of size, like a scanner does
+    fn tokenize(input: &str, state: State, last_start_tag: &str, size: usize) -> Vec<Value> {
+        let mut machine = Machine::new();
+        machine.switch_content = false;
+        machine.state = state;
+        machine.last_start_tag = last_start_tag.as_bytes().to_vec();
+
+        let mut tokens = VecDeque::new();
+        let mut buf = Vec::new();
+        let mut position = Position::new();
+        for chunk in input.as_bytes().chunks(size) {
+            buf.extend_from_slice(chunk);
+            let (processed, next) = machine.run(&buf, position, &options(), false, &mut tokens);
+            buf.drain(..processed);
+            position = next;
+        }
+        let (_, position) = machine.run(&buf, position, &options(), true, &mut tokens);
+        machine.finish(position, &options(), &mut tokens);
+
+        let mut output = Vec::new();
+        for token in tokens {
+            let value = match token {
+                Token::StartTag { tag, attributes, self_closing, .. } => {
+                    let mut map = Map::new();
+                    for attr in attributes {
+                        map.insert(attr.name.to_string(), string(&attr.value));
+                    }
+                    let mut value = vec![Value::from("StartTag"), Value::from(tag.to_string()), Value::Object(map)];
+                    if self_closing {
+                        value.push(Value::Bool(true));
+                    }
+                    Value::Array(value)
+                },
+                Token::EndTag { tag, .. } => Value::Array(vec![Value::from("EndTag"), Value::from(tag.to_string())]),
+                Token::Comment { data, .. } => Value::Array(vec![Value::from("Comment"), string(&data)]),
+                Token::Text { data, .. } => Value::Array(vec![Value::from("Character"), string(&data)]),
+                Token::Doctype { doctype, .. } => Value::Array(vec![
+                    Value::from("DOCTYPE"),
+                    doctype.name().map_or(Value::Null, Value::from),
+                    doctype.public_id().map_or(Value::Null, Value::from),
+                    doctype.system_id().map_or(Value::Null, Value::from),
+                    Value::Bool(!doctype.force_quirks()),
+                ]),
+                Token::Error { .. } => Value::from("ParseError"),
+                x => panic!("unexpected token {:?}", x),
+            };
+            push_token(&mut output, value);
+        }
+        output
+    }
+
+    // Unescape \uHHHH sequences of a double escaped test, None if the
+    // result is not a valid string
+    fn unescape(data: &str) -> Option<String> {
+        let mut result = String::new();
+        let mut rest = data;
+        while let Some(index) = rest.find("\\u") {
+            result.push_str(&rest[..index]);
+            let code = u32::from_str_radix(rest.get(index + 2..index + 6)?, 16).ok()?;
+            result.push(std::char::from_u32(code)?);
+            rest = &rest[index + 6..];
+        }
+        result.push_str(rest);
+        Some(result)
+    }
+
+    fn unescape_value(value: &Value) -> Option<Value> {
+        match *value {
+            Value::String(ref x) => unescape(x).map(Value::String),
+            Value::Array(ref x) => x.iter().map(unescape_value).collect::<Option<Vec<Value>>>().map(Value::Array),
+            Value::Object(ref x) => x.iter()
+                .map(|(k, v)| unescape(k).and_then(|k| unescape_value(v).map(|v| (k, v))))
+                .collect::<Option<Map<String, Value>>>().map(Value::Object),
+            ref x => Some(x.clone()),
+        }
+    }
+
+    fn initial_state(name: &str) -> State {
+        match name {
+            "Data state" => State::Data,
+            "PLAINTEXT state" => State::PlainText,
+            "RCDATA state" => State::RcData,
+            "RAWTEXT state" => State::RawText,
+            "Script data state" => State::ScriptData,
+            x => panic!("unknown initial state {}", x),
+        }
+    }
+
+    fn tokens(data: &str) -> Vec<Token> {
+        Tokenizer::with_options(data.as_bytes(), &options()).map(|x| x.unwrap()).collect()
+    }
+
+    #[test]
+    fn tokenize_malformed_markup() {
+        let t = tokens("a < b<a href=\"x\"class=y title = 'z'\n>c</>d<!x></\nb>");
+        assert_eq!(t.len(), 10);
+        match t[0] {
+            Token::Text { ref data, ref span } => {
+                assert_eq!(data, b"a < b");
+                assert_eq!(span.len(), 5);
+            },
+            _ => panic!("expected text"),
+        }
+        assert_eq!(t[1], Token::Error { error: ParseError::InvalidFirstCharacterOfTagName, span: *t[1].span() });
+        assert_eq!(t[2], Token::Error { error: ParseError::MissingWhitespaceBetweenAttributes, span: *t[2].span() });
+        match t[3] {
+            Token::StartTag { ref tag, ref attributes, ref span, .. } => {
+                assert_eq!(*tag, Tag::A);
+                let values = attributes.iter().map(|x| (x.name().unwrap(), x.value().unwrap())).collect::<Vec<_>>();
+                assert_eq!(values, vec![("href", "x"), ("class", "y"), ("title", "z")]);
+                assert_eq!(span.start.offset, 5);
+                assert_eq!(span.end.line, 2);
+            },
+            _ => panic!("expected start tag"),
+        }
+        // </> is dropped, the text around it is one token
+        assert_eq!(t[4], Token::Text { data: b"cd".to_vec(), span: *t[4].span() });
+        assert_eq!(t[5], Token::Error { error: ParseError::MissingEndTagName, span: *t[5].span() });
+        assert_eq!(t[7], Token::Comment { data: b"x".to_vec(), span: *t[7].span() });
+        assert_eq!(t[9], Token::Comment { data: b"\nb".to_vec(), span: *t[9].span() });
+    }
+
+    #[test]
+    fn tokenize_element_content() {
+        let t = tokens("<script>a<b</script><title>&lt;i&gt;</title>x&amp;y");
+        let data = t.iter().filter_map(|x| match *x {
+            Token::Text { ref data, .. } => Some(String::from_utf8(data.clone()).unwrap()),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(data, vec!["a<b", "<i>", "x&y"]);
+    }
+
+    #[test]
+    fn tokenize_html5lib_tests() {
+        let directory = format!("{}/tests/html5lib-tests/tokenizer", env!("CARGO_MANIFEST_DIR"));
+        let mut paths = std::fs::read_dir(&directory).unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension().is_some_and(|x| x == "test"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut count = 0;
+        let mut failures = Vec::new();
+        for path in paths {
+            let mut data = String::new();
+            File::open(&path).unwrap().read_to_string(&mut data).unwrap();
+            let suite: Value = serde_json::from_str(&data).unwrap();
+
+            // The xml violation tests are for coercing a document into
+            // an infoset, they are not tokenizer tests
+            let tests = match suite.get("tests") {
+                Some(x) => x.as_array().unwrap(),
+                None => continue,
+            };
+
+            for test in tests {
+                let double_escaped = test.get("doubleEscaped").and_then(|x| x.as_bool()).unwrap_or(false);
+                let (input, expected) = match double_escaped {
+                    true => match (unescape_value(&test["input"]), unescape_value(&test["output"])) {
+                        (Some(input), Some(expected)) => (input, expected),
+                        // Lone surrogates can not be represented in UTF-8
+                        _ => continue,
+                    },
+                    false => (test["input"].clone(), test["output"].clone()),
+                };
+                let input = input.as_str().unwrap();
+
+                let mut output = Vec::new();
+                for token in expected.as_array().unwrap() {
+                    push_token(&mut output, token.clone());
+                }
+
+                let states = match test.get("initialStates") {
+                    Some(x) => x.as_array().unwrap().iter().map(|x| x.as_str().unwrap().to_string()).collect(),
+                    None => vec!["Data state".to_string()],
+                };
+                let last_start_tag = test.get("lastStartTag").and_then(|x| x.as_str()).unwrap_or("");
+
+                for state in states {
+                    for &size in [input.len() + 1, 1].iter() {
+                        count += 1;
+                        let tokens = tokenize(input, initial_state(&state), last_start_tag, size);
+                        if tokens != output {
+                            failures.push(format!("{}: {} ({}, chunks of {})\n  input:    {:?}\n  expected: {}\n  got:      {}",
+                                                  path.file_name().unwrap().to_string_lossy(), test["description"], state, size,
+                                                  input, Value::Array(output.clone()), Value::Array(tokens)));
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(count > 10000);
+        assert!(failures.is_empty(), "{} of {} failed\n{}", failures.len(), count, failures.join("\n"));
+    }
+}